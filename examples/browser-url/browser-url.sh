@@ -0,0 +1,22 @@
+#!/bin/sh
+# Prints the focused window like the window script in $BASE_WINDOW_SCRIPT (default:
+# ../window-scripts/sway.sh) and adds the "url" of the active browser tab reported by
+# duckypad_tab_host.py. The url is only added if the window's title starts with the
+# tab's title, so other windows and unfocused browsers don't get one. Requires jq.
+dir=$(dirname "$0")
+base=${BASE_WINDOW_SCRIPT:-$dir/../window-scripts/sway.sh}
+tab=${XDG_RUNTIME_DIR:-/tmp}/duckypad_tab.json
+
+window=$("$base") || exit 1
+
+if [ ! -r "$tab" ]; then
+    printf '%s\n' "$window"
+    exit 0
+fi
+
+printf '%s' "$window" | jq -c --slurpfile tab "$tab" '
+  if ($tab[0].title != "") and (.title | startswith($tab[0].title))
+  then . + {url: $tab[0].url}
+  else .
+  end
+'
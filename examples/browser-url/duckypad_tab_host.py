@@ -0,0 +1,24 @@
+#!/usr/bin/env python3
+# Native messaging host for the tab reporter extension. Every message (the URL and
+# title of the active tab) is written to $XDG_RUNTIME_DIR/duckypad_tab.json, where
+# browser-url.sh picks it up.
+import json
+import os
+import struct
+import sys
+
+path = os.path.join(os.environ.get("XDG_RUNTIME_DIR", "/tmp"), "duckypad_tab.json")
+
+while True:
+    # every message is prefixed with its length as a native-endian u32
+    header = sys.stdin.buffer.read(4)
+    if len(header) < 4:
+        break
+
+    (length,) = struct.unpack("=I", header)
+    message = json.loads(sys.stdin.buffer.read(length))
+
+    # write to a temporary file first, so the window script never reads half a file
+    with open(path + ".tmp", "w") as file:
+        json.dump({"url": message.get("url", ""), "title": message.get("title", "")}, file)
+    os.replace(path + ".tmp", path)
@@ -0,0 +1,20 @@
+// Sends the URL and title of the active tab of the focused window to the native
+// messaging host (duckypad_tab_host.py) whenever it changes.
+const port = browser.runtime.connectNative("duckypad_tab_host");
+
+async function report() {
+  const [tab] = await browser.tabs.query({ active: true, lastFocusedWindow: true });
+
+  if (tab) {
+    port.postMessage({ url: tab.url || "", title: tab.title || "" });
+  }
+}
+
+browser.tabs.onActivated.addListener(report);
+browser.tabs.onUpdated.addListener((_id, change) => {
+  if (change.url !== undefined || change.title !== undefined) {
+    report();
+  }
+});
+browser.windows.onFocusChanged.addListener(report);
+report();
@@ -0,0 +1,10 @@
+#!/bin/sh
+# Instead of parsing the arguments, the information about the switch can also be read
+# from environment variables. DUCKYPAD_PREV_PROFILE is empty if the previous profile
+# isn't known and DUCKYPAD_RULE if the default_profile was used.
+echo "Switched from profile ${DUCKYPAD_PREV_PROFILE:-unknown} to $DUCKYPAD_PROFILE"
+echo "Rule: ${DUCKYPAD_RULE:-default_profile}"
+echo "App Name: $DUCKYPAD_APP_NAME"
+echo "Title: $DUCKYPAD_TITLE"
+echo "Process Name: $DUCKYPAD_PROCESS_NAME"
+echo "Window: ${DUCKYPAD_WINDOW_WIDTH}x${DUCKYPAD_WINDOW_HEIGHT} at $DUCKYPAD_WINDOW_X,$DUCKYPAD_WINDOW_Y"
@@ -0,0 +1,39 @@
+#!/bin/sh
+# Prints the focused GNOME window as the JSON object expected by --window-script.
+# GNOME Shell doesn't expose the focused window to other programs on Wayland, so this
+# requires the "Window Calls" extension (https://extensions.gnome.org/extension/4724/window-calls/)
+# as well as busctl and jq. If nothing is focused, empty values are printed.
+call() {
+    busctl --user --json=short call org.gnome.Shell /org/gnome/Shell/Extensions/Windows \
+        org.gnome.Shell.Extensions.Windows "$@"
+}
+
+if ! windows=$(call List 2>&1); then
+    echo "Couldn't query windows from GNOME Shell, is the Window Calls extension installed and enabled? ($windows)" >&2
+    exit 1
+fi
+
+window=$(printf '%s' "$windows" | jq -c '.data[0] | fromjson | map(select(.focus)) | first // {}')
+id=$(printf '%s' "$window" | jq -r '.id // empty')
+
+# newer versions of the extension only report the title and position via Details
+details='{}'
+if [ -n "$id" ] && reply=$(call Details u "$id" 2>/dev/null); then
+    details=$(printf '%s' "$reply" | jq -c '.data[0] | fromjson') || details='{}'
+fi
+
+printf '%s %s' "$window" "$details" | jq -cs '
+  add
+  | {
+      title: (.title // ""),
+      process_name: (.wm_class // ""),
+      process_id: (.pid // 0),
+      window_id: ((.id // "") | tostring),
+      position: {
+        x: (.x // 0),
+        y: (.y // 0),
+        w: (.width // 0),
+        h: (.height // 0)
+      }
+    }
+'
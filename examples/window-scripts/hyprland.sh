@@ -1,11 +1,20 @@
 #!/bin/sh
-window=$(hyprctl activewindow)
-
-if [ "$window" == "Invalid" ]; then
-  echo '{"title":"","process_name":"","process_id":0}'
-else
-  title=$(echo "$window" | grep -P '^Window .*? -> ' | sed -r 's|^Window .*? -> (.*?):$|\1|' | sed -e 's|\\|\\\\|g; s|"|\\"|g')
-  process_name=$(echo "$window" | grep -P '^\tclass: ' | sed -r 's|^\s*class: (.*)$|\1|' | sed -e 's|\\|\\\\|g; s|"|\\"|g')
-  process_id=$(echo "$window" | grep -P '^\tpid: ' | sed -r 's|^\s*pid: (.*)$|\1|')
-  echo '{"title":"'$title'","process_name":"'$process_name'","process_id":'$process_id'}'
-fi
+# Prints the active Hyprland window as the JSON object expected by --window-script.
+# Requires hyprctl and jq. If no window is active (e.g. an empty workspace), empty
+# values are printed.
+hyprctl activewindow -j | jq -c '
+  (if type == "object" then . else {} end)
+  | {
+      title: (.title // ""),
+      process_name: (.class // ""),
+      process_id: (.pid // 0),
+      window_id: (.address // ""),
+      position: {
+        x: (.at[0] // 0),
+        y: (.at[1] // 0),
+        w: (.size[0] // 0),
+        h: (.size[1] // 0)
+      },
+      fullscreen: ((.fullscreen // false) | if type == "boolean" then . else . > 0 end)
+    }
+'
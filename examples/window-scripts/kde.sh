@@ -0,0 +1,76 @@
+#!/bin/sh
+# Prints the active KDE Plasma (KWin) window as the JSON object expected by --window-script.
+# KWin doesn't expose the active window to other programs on Wayland, so this loads a small
+# KWin script via D-Bus, which prints the window to KWin's log, reads it back from the journal
+# and unloads the script again. Requires busctl, journalctl and jq, and Plasma started via
+# systemd (the default since Plasma 5.25), so KWin logs to the journal. Works with the
+# workspace.activeWindow of Plasma 6 as well as the workspace.activeClient of Plasma 5.
+# If nothing is active, empty values are printed.
+call() {
+    busctl --user call org.kde.KWin "$@"
+}
+
+name="duckypad_daemon_$$"
+marker="duckypad_daemon:$$:$(date +%s%N):"
+kwin_script=$(mktemp "${TMPDIR:-/tmp}/$name.XXXXXX.js") || exit 1
+trap 'call /Scripting org.kde.kwin.Scripting unloadScript s "$name" >/dev/null 2>&1; rm -f "$kwin_script"' EXIT
+
+cat > "$kwin_script" <<EOF
+const window = workspace.activeWindow || workspace.activeClient;
+const geometry = window ? window.frameGeometry : null;
+print("$marker" + JSON.stringify(window ? {
+    caption: window.caption,
+    resourceClass: window.resourceClass,
+    resourceName: window.resourceName,
+    windowRole: window.windowRole,
+    pid: window.pid,
+    internalId: String(window.internalId),
+    x: geometry.x,
+    y: geometry.y,
+    width: geometry.width,
+    height: geometry.height,
+    fullScreen: window.fullScreen
+} : {}));
+EOF
+
+since=$(date '+%Y-%m-%d %H:%M:%S')
+if ! reply=$(call /Scripting org.kde.kwin.Scripting loadScript ss "$kwin_script" "$name" 2>&1); then
+    echo "Couldn't load the KWin script, is KWin running? ($reply)" >&2
+    exit 1
+fi
+
+# the reply is "i <id>", the script is at /Scripting/Script<id> on Plasma 6 and /<id> on Plasma 5
+id=${reply#i }
+call "/Scripting/Script$id" org.kde.kwin.Script run >/dev/null 2>&1 ||
+    call "/$id" org.kde.kwin.Script run >/dev/null 2>&1
+
+# the journal may take a moment to receive the line
+for _ in 1 2 3 4 5 6 7 8 9 10; do
+    window=$(journalctl --user _COMM=kwin_wayland --since "$since" -o cat 2>/dev/null |
+        sed -n "s/.*$marker//p" | tail -n 1)
+    [ -n "$window" ] && break
+    sleep 0.05
+done
+
+if [ -z "$window" ]; then
+    echo "KWin didn't log the active window, is Plasma started via systemd so KWin logs to the journal?" >&2
+    exit 1
+fi
+
+printf '%s' "$window" | jq -c '
+  {
+    title: (.caption // ""),
+    process_name: (.resourceClass // ""),
+    process_id: (.pid // 0),
+    window_id: (.internalId // ""),
+    position: {
+      x: (.x // 0),
+      y: (.y // 0),
+      w: (.width // 0),
+      h: (.height // 0)
+    },
+    fullscreen: (.fullScreen // false),
+    wm_instance: (.resourceName // ""),
+    wm_role: (.windowRole // "")
+  }
+'
@@ -1,11 +1,24 @@
 #!/bin/sh
-window=$(swaymsg -t get_tree | jq '.. | select(.type?) | select(.focused==true)')
-
-if [ "$window" == "" ]; then
-  echo '{"title":"","process_name":"","process_id":0}'
-else
-  title=$(echo "$window" | jq '.name')
-  process_name=$(echo "$window" | jq '.app_id')
-  process_id=$(echo "$window" | jq '.pid')
-  echo '{"title":'$title',"process_name":'$process_name',"process_id":'$process_id'}'
-fi
+# Prints the focused sway window as the JSON object expected by --window-script.
+# Requires swaymsg and jq. XWayland windows have no app_id, so their X11 class is
+# used as process_name instead, together with their WM_CLASS instance and
+# WM_WINDOW_ROLE (empty for Wayland windows). If nothing is focused (e.g. an empty workspace),
+# empty values are printed.
+swaymsg -t get_tree | jq -c '
+  first(.. | select(.type? == "con" or .type? == "floating_con") | select(.focused == true)) // {}
+  | {
+      title: (.name // ""),
+      process_name: (.app_id // .window_properties.class // ""),
+      process_id: (.pid // 0),
+      window_id: ((.id // "") | tostring),
+      position: {
+        x: (.rect.x // 0),
+        y: (.rect.y // 0),
+        w: (.rect.width // 0),
+        h: (.rect.height // 0)
+      },
+      fullscreen: ((.fullscreen_mode // 0) > 0),
+      wm_instance: (.window_properties.instance // ""),
+      wm_role: (.window_properties.window_role // "")
+    }
+'
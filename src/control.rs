@@ -0,0 +1,221 @@
+use crate::status::Status;
+use std::{
+    io::{BufRead, BufReader, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::Path,
+    sync::mpsc::{channel, Receiver, Sender},
+    time::Duration,
+};
+
+/// Time a connection waits for the daemon to answer a request.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Commands that can be sent to a running daemon over the control socket.
+pub enum Request {
+    /// `switch <PROFILE>` - switch to a profile directly
+    Switch(u32),
+    /// `status` - print the current profile and whether autoswitch is active
+    Status,
+    /// `info` - print the connected duckyPad, the current profile and whether
+    /// autoswitching is paused as a JSON object
+    Info,
+    /// `pause` - pause autoswitching
+    Pause,
+    /// `resume` - resume autoswitching
+    Resume,
+    /// `reload` - reload the config file
+    Reload,
+    /// `use-config <NAME>` - load the config named `NAME` from the config
+    /// directory instead of the current one
+    UseConfig(String),
+    /// `subscribe` - print the current `Status` as a JSON object and another
+    /// one whenever it changes, until the client disconnects
+    Subscribe,
+}
+
+/// A request received over the control socket together with a way to answer
+/// the client that sent it.
+pub struct Message {
+    pub request: Request,
+    reply: Sender<String>,
+}
+
+impl Message {
+    /// Sends `text` as the answer to the client. The client might have
+    /// disconnected already, which is ignored.
+    pub fn reply(&self, text: String) {
+        let _: Result<_, _> = self.reply.send(text);
+    }
+}
+
+/// Parses a single line sent to the control socket.
+///
+/// # Errors
+///
+/// Will return a message describing the problem if `line` isn't a known
+/// command or has invalid arguments.
+pub fn parse(line: &str) -> Result<Request, String> {
+    let mut words = line.split_whitespace();
+    let command = words.next().unwrap_or_default();
+    let request = match command {
+        "switch" => {
+            let profile = words
+                .next()
+                .ok_or("missing profile, usage: switch <PROFILE>")?;
+            Request::Switch(
+                profile
+                    .parse()
+                    .map_err(|_| format!("invalid profile: {profile}"))?,
+            )
+        }
+        "status" => Request::Status,
+        "info" => Request::Info,
+        "pause" => Request::Pause,
+        "resume" => Request::Resume,
+        "reload" => Request::Reload,
+        "subscribe" => Request::Subscribe,
+        "use-config" => Request::UseConfig(
+            words
+                .next()
+                .ok_or("missing name, usage: use-config <NAME>")?
+                .to_string(),
+        ),
+        _ => return Err(format!("unknown command: {command}")),
+    };
+
+    if words.next().is_some() {
+        return Err(format!("too many arguments for {command}"));
+    }
+
+    Ok(request)
+}
+
+/// Clients that sent `subscribe`, which get a JSON line with the `Status` on
+/// every change, e.g. of the profile or when autoswitching is paused. The
+/// same `Status` is written to the `--status-file`.
+#[derive(Default)]
+pub struct Subscribers {
+    senders: Vec<Sender<String>>,
+    /// the status sent last, so unchanged ones aren't sent again
+    published: Option<String>,
+}
+
+impl Subscribers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the client that sent `message` (a `Request::Subscribe`), which
+    /// was already answered with the current status.
+    pub fn add(&mut self, message: Message) {
+        self.senders.push(message.reply);
+    }
+
+    /// Sends `status` to all subscribers if it differs from the one sent
+    /// last. Subscribers that disconnected are removed.
+    pub fn publish(&mut self, status: &Status) {
+        let Ok(json) = serde_json::to_string(status) else {
+            return;
+        };
+
+        if self.published.as_ref() == Some(&json) {
+            return;
+        }
+
+        self.senders
+            .retain(|sender| sender.send(json.clone()).is_ok());
+        self.published = Some(json);
+    }
+}
+
+/// Binds a unix domain socket at `path` and returns a `Receiver` for the
+/// requests sent to it. Every connection gets handled on its own thread and
+/// can send multiple newline-delimited commands. An existing file at `path`
+/// (e.g. a stale socket of a previous run) is replaced.
+///
+/// # Arguments
+///
+/// * `path` - path of the socket to create
+///
+/// # Errors
+///
+/// Will return `std::io::Error` if the socket cannot be created.
+pub fn listen(path: &Path) -> std::io::Result<Receiver<Message>> {
+    match std::fs::remove_file(path) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err),
+        _ => {}
+    }
+
+    let listener = UnixListener::bind(path)?;
+    let (sender, receiver) = channel();
+
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let sender = sender.clone();
+                    std::thread::spawn(move || handle_connection(stream, &sender));
+                }
+                Err(err) => tracing::error!("Failed to accept control socket connection: {err}"),
+            }
+        }
+    });
+
+    Ok(receiver)
+}
+
+fn handle_connection(stream: UnixStream, sender: &Sender<Message>) {
+    let Ok(mut writer) = stream.try_clone() else {
+        return;
+    };
+
+    for line in BufReader::new(stream).lines() {
+        let Ok(line) = line else {
+            return;
+        };
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let answer = match parse(&line) {
+            Ok(request) => {
+                let subscribe = matches!(request, Request::Subscribe);
+                let (reply, answer) = channel();
+
+                if sender.send(Message { request, reply }).is_err() {
+                    return;
+                }
+
+                if subscribe {
+                    stream_events(&mut writer, &answer);
+                    return;
+                }
+
+                answer
+                    .recv_timeout(REPLY_TIMEOUT)
+                    .unwrap_or_else(|_| "error: daemon didn't answer".to_string())
+            }
+            Err(err) => format!("error: {err}"),
+        };
+
+        if writeln!(writer, "{answer}").is_err() {
+            return;
+        }
+    }
+}
+
+/// Writes the events of a subscription to the client until it disconnects,
+/// which is noticed on the next event, or the daemon drops the subscription.
+fn stream_events(writer: &mut UnixStream, events: &Receiver<String>) {
+    let Ok(first) = events.recv_timeout(REPLY_TIMEOUT) else {
+        let _: Result<_, _> = writeln!(writer, "error: daemon didn't answer");
+        return;
+    };
+
+    for event in std::iter::once(first).chain(events.iter()) {
+        if writeln!(writer, "{event}").is_err() {
+            return;
+        }
+    }
+}
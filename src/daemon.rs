@@ -0,0 +1,1605 @@
+#[cfg(all(unix, feature = "control-socket"))]
+use crate::control;
+#[cfg(all(unix, feature = "dbus"))]
+use crate::dbus;
+#[cfg(all(unix, feature = "systemd"))]
+use crate::systemd::Systemd;
+use crate::{
+    goto_profile, hid,
+    hid::DuckyPadTransport,
+    history::History,
+    load_config,
+    lock::InstanceLock,
+    metrics::Metrics,
+    named_config_file,
+    notification::Notifier,
+    provider::{self, WindowProvider},
+    run_device_callback,
+    screen_lock::LockDetector,
+    status::{Status, StatusFile},
+    switch_profile,
+    watch::ConfigWatcher,
+    Callback, Config, ConfigError, Debounce, ManualOverride, MinInterval, Processes,
+    RelativeTargets, SwitchResult, SLEEP,
+};
+use hidapi::{HidApi, HidError};
+#[cfg(all(unix, any(feature = "control-socket", feature = "dbus")))]
+use std::sync::mpsc::Receiver;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+/// Default time between two attempts to reconnect to the duckypad.
+pub const WAIT_INTERVAL: Duration = Duration::from_millis(250);
+const NOTIFY_INTERVAL: Duration = Duration::from_secs(2);
+/// Longest time the main loop waits without checking whether to shut down.
+const SHUTDOWN_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Reasons why the `Daemon` couldn't be started or had to stop.
+#[derive(Debug)]
+pub enum DaemonError {
+    /// The config couldn't be loaded, or it doesn't fit the connected
+    /// duckypad with `strict` (see `Config::check_max_profiles`)
+    Config(ConfigError),
+    /// The hid api couldn't be initialized
+    HidApi(HidError),
+    /// The duckypad couldn't be connected to on startup
+    Connect(HidError),
+    /// The connection to the duckypad was lost and reconnecting failed for
+    /// the maximum number of attempts
+    Reconnect(HidError),
+}
+
+impl std::fmt::Display for DaemonError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Config(err) => write!(f, "{err}"),
+            Self::HidApi(err) => write!(f, "Failed to connect to HidApi: {err}"),
+            Self::Connect(err) => write!(f, "Failed to connect to duckyPad: {err}"),
+            Self::Reconnect(err) => {
+                write!(f, "Failed to reconnect to duckyPad, giving up: {err}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for DaemonError {}
+
+/// What the daemon does when the profile read from the duckyPad differs from
+/// the one it switched to.
+#[derive(PartialEq, Eq)]
+enum SyncMode {
+    /// take over the profile, as it was changed on the duckyPad on purpose
+    /// (`--sync-interval`)
+    Adopt,
+    /// switch back, unless autoswitching is paused (`--reassert-interval`)
+    Reassert,
+}
+
+/// The last autoswitch, see `Daemon::last_switch`.
+struct LastSwitch {
+    profile: u32,
+    /// index of the matching rule, `None` for the `default_profile`
+    rule: Option<usize>,
+    /// app name of the window that caused the switch
+    app_name: String,
+}
+
+/// Sources of events the main loop reacts to besides the active window.
+struct Events {
+    reload: Arc<AtomicBool>,
+    toggle_pause: Arc<AtomicBool>,
+    config_watcher: Option<ConfigWatcher>,
+    #[cfg(all(unix, feature = "control-socket"))]
+    control: Option<Receiver<control::Message>>,
+    #[cfg(all(unix, feature = "dbus"))]
+    dbus: Option<(dbus::Service, Receiver<dbus::Message>)>,
+    /// last profile sent with `ProfileChanged` over D-Bus
+    #[cfg(all(unix, feature = "dbus"))]
+    signalled_profile: Option<u32>,
+}
+
+/// The profile switcher with everything the `duckypad_daemon` binary does
+/// around `switch_profile`: reconnecting to the duckyPad, syncing its
+/// profile, pausing, reloading the config and answering the control socket
+/// and D-Bus. Created with `Daemon::builder`, then either driven by `run` or
+/// by calling `tick` from the caller's own loop.
+///
+/// ```no_run
+/// use duckypad_daemon::daemon::Daemon;
+/// use std::time::Duration;
+///
+/// let daemon = Daemon::builder("config.json")
+///     .poll_interval(Duration::from_millis(500))
+///     .build()
+///     .expect("Failed to start the daemon");
+///
+/// daemon.run().expect("Lost the connection to the duckyPad");
+/// ```
+#[allow(clippy::struct_excessive_bools)]
+pub struct Daemon {
+    api: HidApi,
+    duckypad: Option<hid::DuckyPad>,
+    dry_run: bool,
+    strict: bool,
+    /// whether the firmware version is read on connect (see
+    /// `--no-firmware-query`)
+    query_firmware: bool,
+    serial: Option<String>,
+    /// how to retry connecting after the connection was lost
+    retry: hid::Retry,
+    config_path: PathBuf,
+    config: Config,
+    /// directory of the configs for `use_config` (see `--config-dir`)
+    config_dir: Option<PathBuf>,
+    processes: Processes,
+    callback: Option<Callback>,
+    on_connect: Option<PathBuf>,
+    on_disconnect: Option<PathBuf>,
+    /// information about the connected duckyPad, kept for `on_disconnect`
+    info: Option<hid::DuckyPadInfo>,
+    provider: Box<dyn WindowProvider>,
+    poll_interval: Duration,
+    /// whether the active window changed or the config was replaced since
+    /// the window was last checked, for providers that report window changes
+    /// (see `WindowProvider::notifies`)
+    recheck: bool,
+    /// profile and pause state the active window was last checked with
+    checked: Option<(Option<u32>, bool)>,
+    prev_profile: Option<u32>,
+    last_switch: Option<LastSwitch>,
+    paused: bool,
+    /// detects the lock screen for `--pause-on-lock`
+    screen_lock: Option<LockDetector>,
+    notifier: Option<Notifier>,
+    history: Option<History>,
+    status_file: Option<StatusFile>,
+    /// clients of the control socket that get every change of the status
+    #[cfg(all(unix, feature = "control-socket"))]
+    subscribers: control::Subscribers,
+    metrics: Arc<Metrics>,
+    sync_interval: Option<Duration>,
+    sync_mode: SyncMode,
+    last_sync: Instant,
+    debounce: Option<Debounce>,
+    min_interval: Option<MinInterval>,
+    /// grace period after manual profile changes (see
+    /// `--manual-override-grace`)
+    manual_override: Option<ManualOverride>,
+    /// state of rules with `"next"`, `"previous"` or `"toggle"` targets
+    relative: RelativeTargets,
+    events: Events,
+    /// set to make `run` return, e.g. on SIGTERM or SIGINT
+    shutdown: Arc<AtomicBool>,
+    /// held until the daemon is dropped (see `DaemonBuilder::lock`)
+    _lock: Option<InstanceLock>,
+    #[cfg(all(unix, feature = "systemd"))]
+    systemd: Systemd,
+}
+
+/// Options of a `Daemon`, see `Daemon::builder`. The defaults match the ones
+/// of the command line options of the same name.
+#[allow(clippy::struct_excessive_bools)]
+pub struct DaemonBuilder {
+    config_path: PathBuf,
+    config_dir: Option<PathBuf>,
+    strict: bool,
+    poll_interval: Duration,
+    provider: Option<Box<dyn WindowProvider>>,
+    callback: Option<Callback>,
+    connect: bool,
+    serial: Option<String>,
+    wait: Option<hid::Retry>,
+    retry: hid::Retry,
+    query_firmware: bool,
+    dry_run: bool,
+    on_connect: Option<PathBuf>,
+    on_disconnect: Option<PathBuf>,
+    pause_on_lock: bool,
+    notify: bool,
+    history: Option<History>,
+    status_file: Option<StatusFile>,
+    metrics: Arc<Metrics>,
+    sync_interval: Option<Duration>,
+    reassert_interval: Option<Duration>,
+    debounce: Option<Duration>,
+    min_interval: Option<Duration>,
+    manual_override_grace: Option<Duration>,
+    watch_config: bool,
+    reload: Arc<AtomicBool>,
+    toggle_pause: Arc<AtomicBool>,
+    shutdown: Arc<AtomicBool>,
+    lock: Option<InstanceLock>,
+    #[cfg(all(unix, feature = "control-socket"))]
+    control: Option<Receiver<control::Message>>,
+    #[cfg(all(unix, feature = "dbus"))]
+    dbus: Option<(dbus::Service, Receiver<dbus::Message>)>,
+}
+
+impl DaemonBuilder {
+    /// Sets the directory of the named configs for `Daemon::use_config`
+    /// (see `named_config_file`).
+    #[must_use]
+    pub fn config_dir(mut self, config_dir: Option<PathBuf>) -> Self {
+        self.config_dir = config_dir;
+        self
+    }
+
+    /// Refuses configs with warnings and configs that switch to profiles the
+    /// connected duckyPad doesn't have, instead of logging them.
+    #[must_use]
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Sets the interval at which `Daemon::run` checks the active window
+    /// (default: 250 ms).
+    #[must_use]
+    pub fn poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// Sets where the active window comes from. Defaults to the native API
+    /// on Windows and macOS and to the X server elsewhere, so on Wayland a
+    /// `WindowScript` has to be given.
+    #[must_use]
+    pub fn window_provider(mut self, provider: Box<dyn WindowProvider>) -> Self {
+        self.provider = Some(provider);
+        self
+    }
+
+    /// Sets the executable that is called on every profile switch.
+    #[must_use]
+    pub fn callback(mut self, callback: Option<Callback>) -> Self {
+        self.callback = callback;
+        self
+    }
+
+    /// Sets whether to connect to a duckyPad at all (default: `true`).
+    /// Without one, switches are only logged like in a dry run.
+    #[must_use]
+    pub fn connect(mut self, connect: bool) -> Self {
+        self.connect = connect;
+        self
+    }
+
+    /// Sets the serial number of the duckyPad to use if more than one is
+    /// connected.
+    #[must_use]
+    pub fn serial(mut self, serial: Option<String>) -> Self {
+        self.serial = serial;
+        self
+    }
+
+    /// Waits for the duckyPad on startup like after losing the connection
+    /// (see `hid::wait_for_device`), instead of failing if it isn't
+    /// connected.
+    #[must_use]
+    pub fn wait(mut self, wait: Option<hid::Retry>) -> Self {
+        self.wait = wait;
+        self
+    }
+
+    /// Sets how reconnecting is retried after the connection to the duckyPad
+    /// was lost (default: every 250 ms at first, up to every 10 s, forever).
+    #[must_use]
+    pub fn retry(mut self, retry: hid::Retry) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Sets whether the firmware version is read on connect (default:
+    /// `true`).
+    #[must_use]
+    pub fn query_firmware(mut self, query_firmware: bool) -> Self {
+        self.query_firmware = query_firmware;
+        self
+    }
+
+    /// Only logs the profile switches instead of switching the duckyPad.
+    #[must_use]
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Sets the executable that is called when the duckyPad gets connected
+    /// (see `run_device_callback`).
+    #[must_use]
+    pub fn on_connect(mut self, on_connect: Option<PathBuf>) -> Self {
+        self.on_connect = on_connect;
+        self
+    }
+
+    /// Sets the executable that is called when the connection to the
+    /// duckyPad is lost or the daemon stops (see `run_device_callback`).
+    #[must_use]
+    pub fn on_disconnect(mut self, on_disconnect: Option<PathBuf>) -> Self {
+        self.on_disconnect = on_disconnect;
+        self
+    }
+
+    /// Pauses autoswitching while the screen is locked.
+    #[must_use]
+    pub fn pause_on_lock(mut self, pause_on_lock: bool) -> Self {
+        self.pause_on_lock = pause_on_lock;
+        self
+    }
+
+    /// Shows a desktop notification on profile switches.
+    #[must_use]
+    pub fn notify(mut self, notify: bool) -> Self {
+        self.notify = notify;
+        self
+    }
+
+    /// Sets the history file every profile switch is recorded in.
+    #[must_use]
+    pub fn history(mut self, history: Option<History>) -> Self {
+        self.history = history;
+        self
+    }
+
+    /// Sets the file the current status is written to.
+    #[must_use]
+    pub fn status_file(mut self, status_file: Option<StatusFile>) -> Self {
+        self.status_file = status_file;
+        self
+    }
+
+    /// Sets the metrics the daemon counts its switches and errors in, e.g.
+    /// to serve them with `metrics::serve`.
+    #[must_use]
+    pub fn metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Sets how often the profile is read from the duckyPad to notice
+    /// changes on the device, `None` never does (default: 5 s).
+    #[must_use]
+    pub fn sync_interval(mut self, sync_interval: Option<Duration>) -> Self {
+        self.sync_interval = sync_interval;
+        self
+    }
+
+    /// Reads the profile from the duckyPad at this interval instead of the
+    /// `sync_interval` and switches back if it differs from the one the
+    /// daemon switched to.
+    #[must_use]
+    pub fn reassert_interval(mut self, reassert_interval: Option<Duration>) -> Self {
+        self.reassert_interval = reassert_interval;
+        self
+    }
+
+    /// Only switches once the active window matched the same profile for
+    /// this long (see `Debounce`).
+    #[must_use]
+    pub fn debounce(mut self, debounce: Option<Duration>) -> Self {
+        self.debounce = debounce;
+        self
+    }
+
+    /// Sets the minimum time between two profile switches (see
+    /// `MinInterval`).
+    #[must_use]
+    pub fn min_interval(mut self, min_interval: Option<Duration>) -> Self {
+        self.min_interval = min_interval;
+        self
+    }
+
+    /// Pauses autoswitching for this long after the profile was changed on
+    /// the duckyPad (see `ManualOverride`).
+    #[must_use]
+    pub fn manual_override_grace(mut self, grace: Option<Duration>) -> Self {
+        self.manual_override_grace = grace;
+        self
+    }
+
+    /// Reloads the config whenever it or a file it includes changes.
+    #[must_use]
+    pub fn watch_config(mut self, watch_config: bool) -> Self {
+        self.watch_config = watch_config;
+        self
+    }
+
+    /// Sets a flag that makes the daemon reload the config once it is set,
+    /// e.g. on SIGHUP.
+    #[must_use]
+    pub fn reload_flag(mut self, reload: Arc<AtomicBool>) -> Self {
+        self.reload = reload;
+        self
+    }
+
+    /// Sets a flag that makes the daemon pause or resume autoswitching once
+    /// it is set, e.g. on SIGUSR1.
+    #[must_use]
+    pub fn toggle_pause_flag(mut self, toggle_pause: Arc<AtomicBool>) -> Self {
+        self.toggle_pause = toggle_pause;
+        self
+    }
+
+    /// Sets a flag that makes `Daemon::run` return once it is set, e.g. on
+    /// SIGTERM.
+    #[must_use]
+    pub fn shutdown_flag(mut self, shutdown: Arc<AtomicBool>) -> Self {
+        self.shutdown = shutdown;
+        self
+    }
+
+    /// Sets the lock that is held as long as the daemon exists.
+    #[must_use]
+    pub fn lock(mut self, lock: Option<InstanceLock>) -> Self {
+        self.lock = lock;
+        self
+    }
+
+    /// Sets the requests of the control socket to answer (see
+    /// `control::listen`).
+    #[cfg(all(unix, feature = "control-socket"))]
+    #[must_use]
+    pub fn control(mut self, control: Option<Receiver<control::Message>>) -> Self {
+        self.control = control;
+        self
+    }
+
+    /// Sets the D-Bus service to answer and signal profile changes on (see
+    /// `dbus::serve`).
+    #[cfg(all(unix, feature = "dbus"))]
+    #[must_use]
+    pub fn dbus(mut self, dbus: Option<(dbus::Service, Receiver<dbus::Message>)>) -> Self {
+        self.dbus = dbus;
+        self
+    }
+
+    /// Connects to the duckyPad (waiting for it with `wait`), loads the config
+    /// with the names of its profiles (see `read_config_with`) and returns the
+    /// daemon.
+    ///
+    /// # Errors
+    ///
+    /// Will return `DaemonError` if the config can't be loaded, or if the
+    /// duckyPad can't be connected to or doesn't fit the config with
+    /// `strict`.
+    pub fn build(self) -> Result<Daemon, DaemonError> {
+        let mut api = HidApi::new().map_err(DaemonError::HidApi)?;
+
+        let mut duckypad = if self.connect {
+            let serial = self.serial.as_deref();
+            let duckypad = match self.wait {
+                Some(retry) => hid::wait_for_device(&mut api, serial, retry, || ()),
+                None => hid::open(&api, serial),
+            };
+            Some(duckypad.map_err(DaemonError::Connect)?)
+        } else {
+            None
+        };
+
+        if let Some(duckypad) = &mut duckypad {
+            query_device(duckypad, self.query_firmware);
+        }
+        let device_names = duckypad
+            .as_ref()
+            .map(|duckypad| duckypad.profile_names().clone())
+            .unwrap_or_default();
+        let config = load_config(&self.config_path, self.strict, &device_names)
+            .map_err(DaemonError::Config)?;
+
+        let (sync_interval, sync_mode) = match self.reassert_interval {
+            Some(interval) => (Some(interval), SyncMode::Reassert),
+            None => (self.sync_interval, SyncMode::Adopt),
+        };
+
+        if self.manual_override_grace.is_some() && sync_interval.is_none() {
+            tracing::warn!(
+                "--manual-override-grace has no effect without --sync-interval or --reassert-interval"
+            );
+        }
+
+        let provider = self.provider.unwrap_or_else(native_provider);
+
+        let mut daemon = Daemon {
+            api,
+            duckypad: None,
+            dry_run: self.dry_run,
+            strict: self.strict,
+            query_firmware: self.query_firmware,
+            serial: self.serial,
+            retry: self.retry,
+            config_path: self.config_path,
+            config,
+            config_dir: self.config_dir,
+            processes: Processes::new(),
+            callback: self.callback,
+            on_connect: self.on_connect,
+            on_disconnect: self.on_disconnect,
+            info: None,
+            screen_lock: self
+                .pause_on_lock
+                .then(|| LockDetector::new(provider.is_xorg())),
+            provider,
+            poll_interval: self.poll_interval,
+            recheck: true,
+            checked: None,
+            prev_profile: None,
+            last_switch: None,
+            paused: false,
+            notifier: self.notify.then(|| Notifier::new(NOTIFY_INTERVAL)),
+            history: self.history,
+            status_file: self.status_file,
+            #[cfg(all(unix, feature = "control-socket"))]
+            subscribers: control::Subscribers::new(),
+            metrics: self.metrics,
+            sync_interval,
+            sync_mode,
+            last_sync: Instant::now(),
+            debounce: self.debounce.map(Debounce::new),
+            min_interval: self.min_interval.map(MinInterval::new),
+            manual_override: self.manual_override_grace.map(ManualOverride::new),
+            relative: RelativeTargets::new(),
+            events: Events {
+                reload: self.reload,
+                toggle_pause: self.toggle_pause,
+                config_watcher: None,
+                #[cfg(all(unix, feature = "control-socket"))]
+                control: self.control,
+                #[cfg(all(unix, feature = "dbus"))]
+                dbus: self.dbus,
+                #[cfg(all(unix, feature = "dbus"))]
+                signalled_profile: None,
+            },
+            shutdown: self.shutdown,
+            _lock: self.lock,
+            #[cfg(all(unix, feature = "systemd"))]
+            systemd: Systemd::new(),
+        };
+
+        if self.watch_config {
+            daemon.events.config_watcher = Some(ConfigWatcher::new(daemon.config_files()));
+        }
+
+        if let Some(duckypad) = duckypad {
+            daemon.connected(duckypad)?;
+        }
+
+        Ok(daemon)
+    }
+}
+
+/// Reads the firmware version (unless disabled with `query_firmware`) and the
+/// names of the profiles from the newly connected `duckypad`.
+fn query_device(duckypad: &mut hid::DuckyPad, query_firmware: bool) {
+    if query_firmware {
+        duckypad.query_version();
+    }
+    duckypad.query_profile_names();
+}
+
+/// Returns the `WindowProvider` of the platform's native API.
+fn native_provider() -> Box<dyn WindowProvider> {
+    if cfg!(any(windows, target_os = "macos")) {
+        Box::new(provider::Native)
+    } else {
+        Box::new(provider::X11::new())
+    }
+}
+
+impl Daemon {
+    /// Returns a builder for a daemon that uses the config file at
+    /// `config_path`.
+    pub fn builder(config_path: impl Into<PathBuf>) -> DaemonBuilder {
+        DaemonBuilder {
+            config_path: config_path.into(),
+            config_dir: None,
+            strict: false,
+            poll_interval: Duration::from_millis(250),
+            provider: None,
+            callback: None,
+            connect: true,
+            serial: None,
+            wait: None,
+            retry: hid::Retry {
+                interval: WAIT_INTERVAL,
+                max_interval: Duration::from_secs(10),
+                max_attempts: None,
+            },
+            query_firmware: true,
+            dry_run: false,
+            on_connect: None,
+            on_disconnect: None,
+            pause_on_lock: false,
+            notify: false,
+            history: None,
+            status_file: None,
+            metrics: Arc::default(),
+            sync_interval: Some(Duration::from_secs(5)),
+            reassert_interval: None,
+            debounce: None,
+            min_interval: None,
+            manual_override_grace: None,
+            watch_config: false,
+            reload: Arc::default(),
+            toggle_pause: Arc::default(),
+            shutdown: Arc::default(),
+            lock: None,
+            #[cfg(all(unix, feature = "control-socket"))]
+            control: None,
+            #[cfg(all(unix, feature = "dbus"))]
+            dbus: None,
+        }
+    }
+
+    /// Runs the main loop: calls `tick` every `poll_interval` until the
+    /// `shutdown_flag` is set.
+    ///
+    /// # Errors
+    ///
+    /// Will return `DaemonError` if `tick` fails.
+    pub fn run(mut self) -> Result<(), DaemonError> {
+        #[cfg(all(unix, feature = "systemd"))]
+        self.systemd.ready();
+
+        self.write_status();
+
+        while !self.shutdown.load(Ordering::Relaxed) {
+            // checked before the tick, so the last window of a closed provider
+            // is still handled
+            let closed = self.provider.closed();
+            self.tick()?;
+
+            if closed {
+                tracing::info!("No more windows will arrive, shutting down");
+                break;
+            }
+
+            // apply a switch skipped because of --min-interval as soon as allowed
+            let pending = self.min_interval.as_ref().and_then(MinInterval::pending);
+            self.recheck |= self.wait(pending.map_or(self.poll_interval, |pending| {
+                pending.min(self.poll_interval)
+            }));
+        }
+
+        self.shut_down();
+        Ok(())
+    }
+
+    /// Handles the pending events (config changes, pause toggles, control
+    /// requests), checks the active window, switches the profile if necessary
+    /// and publishes the new status. If the duckyPad got disconnected, this
+    /// blocks until it is reconnected or the `shutdown_flag` is set.
+    ///
+    /// # Errors
+    ///
+    /// Will return `DaemonError` if reconnecting to the duckyPad failed for
+    /// the maximum number of attempts, or if it doesn't fit the config with
+    /// `strict`.
+    pub fn tick(&mut self) -> Result<(), DaemonError> {
+        self.handle_events();
+        self.check_window()?;
+        self.metrics.set_current_profile(self.prev_profile);
+        self.write_status();
+
+        #[cfg(all(unix, feature = "systemd"))]
+        {
+            let status = self.status();
+            self.systemd.status(status);
+            self.systemd.watchdog();
+        }
+
+        #[cfg(all(unix, feature = "dbus"))]
+        if let (Some((service, _)), Some(profile)) = (&self.events.dbus, self.prev_profile) {
+            if self.events.signalled_profile != Some(profile) {
+                self.events.signalled_profile = Some(profile);
+                service.profile_changed(profile);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Handles the signals, config changes and requests that arrived since
+    /// the last tick.
+    fn handle_events(&mut self) {
+        if self.events.reload.swap(false, Ordering::Relaxed)
+            || self
+                .events
+                .config_watcher
+                .as_mut()
+                .is_some_and(ConfigWatcher::changed)
+        {
+            let _: Result<_, _> = self.reload_config();
+        }
+
+        if self.events.toggle_pause.swap(false, Ordering::Relaxed) {
+            self.set_paused(!self.paused);
+        }
+
+        #[cfg(all(unix, feature = "control-socket"))]
+        {
+            let messages: Vec<_> = self
+                .events
+                .control
+                .iter()
+                .flat_map(Receiver::try_iter)
+                .collect();
+            for message in messages {
+                message.reply(self.handle_request(&message.request));
+
+                if matches!(message.request, control::Request::Subscribe) {
+                    self.subscribers.add(message);
+                }
+            }
+        }
+
+        #[cfg(all(unix, feature = "dbus"))]
+        {
+            let messages: Vec<_> = self
+                .events
+                .dbus
+                .iter()
+                .flat_map(|(_, receiver)| receiver.try_iter())
+                .collect();
+            for message in messages {
+                message.reply(self.handle_dbus_request(&message.request));
+            }
+        }
+
+        // keep watching the config files that are in use (see `use_config`
+        // and `include`)
+        let outdated = self.events.config_watcher.as_ref().is_some_and(|watcher| {
+            watcher.paths().split_first() != Some((&self.config_path, self.config.included_files()))
+        });
+        if outdated {
+            self.events.config_watcher = Some(ConfigWatcher::new(self.config_files()));
+        }
+    }
+
+    /// Checks the active window and switches the profile if necessary.
+    fn check_window(&mut self) -> Result<(), DaemonError> {
+        self.update_locked();
+        if let Some(manual_override) = &mut self.manual_override {
+            if manual_override.expire(Instant::now()) {
+                tracing::info!(
+                    "Grace period after the manual profile change is over, resuming autoswitching"
+                );
+            }
+        }
+        let paused = self.paused || self.locked();
+        self.sync_profile(paused)?;
+
+        let paused = paused || self.overridden();
+        if !self.needs_check(paused) {
+            tracing::trace!("Active window didn't change, skipping the rules");
+            return Ok(());
+        }
+        self.recheck = false;
+
+        match switch_profile(
+            self.duckypad.as_ref().filter(|_| !self.dry_run),
+            &mut self.processes,
+            &self.config,
+            self.prev_profile,
+            self.callback.as_ref(),
+            self.provider.as_mut(),
+            paused,
+            self.debounce.as_mut(),
+            self.min_interval.as_mut(),
+            Some(&mut self.relative),
+        ) {
+            Ok(result) => {
+                self.prev_profile = result.profile();
+                self.checked = Some((self.prev_profile, paused));
+
+                if let SwitchResult::Switched(event) = &result {
+                    self.metrics.switched(event.profile);
+                    self.last_switch = Some(LastSwitch {
+                        profile: event.profile,
+                        rule: event.rule,
+                        app_name: event.window.app_name.clone(),
+                    });
+                }
+
+                if let (Some(history), SwitchResult::Switched(event)) = (&mut self.history, &result)
+                {
+                    history.record(event);
+                }
+
+                if let Some(notifier) = &mut self.notifier {
+                    match &result {
+                        SwitchResult::Switched(event) => notifier.notify(event),
+                        SwitchResult::Kept(_) | SwitchResult::Unmatched(_) => notifier.flush(),
+                    }
+                }
+
+                Ok(())
+            }
+            Err(err) => self.hid_failed(&err, "switch the profile"),
+        }
+    }
+
+    /// Reads the profile from the duckyPad every `sync_interval` and adopts
+    /// it or switches back (see `SyncMode`), unless autoswitching is `paused`.
+    fn sync_profile(&mut self, paused: bool) -> Result<(), DaemonError> {
+        let device = self.duckypad.as_ref().filter(|_| !self.dry_run);
+
+        // a sleeping duckyPad still reports the profile it was on
+        if let Some(device) = device.filter(|device| {
+            self.prev_profile != Some(SLEEP)
+                && device.supports(hid::Feature::ReadProfile)
+                && self
+                    .sync_interval
+                    .is_some_and(|interval| self.last_sync.elapsed() >= interval)
+        }) {
+            self.last_sync = Instant::now();
+
+            match device.current_profile() {
+                Ok(profile) => {
+                    let profile = u32::from(profile);
+
+                    match self.prev_profile {
+                        Some(prev)
+                            if prev != profile
+                                && self.sync_mode == SyncMode::Reassert
+                                && self.manual_override.is_none()
+                                && !paused =>
+                        {
+                            tracing::info!(
+                                "The duckyPad is on profile {profile} instead of {prev}, switching back"
+                            );
+                            if let Err(err) = goto_profile(device, prev) {
+                                return self.hid_failed(&err, "switch back");
+                            }
+                        }
+                        Some(prev) if prev != profile => {
+                            if let Some(manual_override) = &mut self.manual_override {
+                                tracing::info!(
+                                    "Profile was changed on the duckyPad, pausing autoswitching for {}s",
+                                    manual_override.grace().as_secs()
+                                );
+                                manual_override.start(Instant::now());
+                            } else {
+                                tracing::info!("Profile was changed on the duckyPad");
+                            }
+                            self.prev_profile = Some(profile);
+                        }
+                        _ => self.prev_profile = Some(profile),
+                    }
+                }
+                // e.g. a timeout, the profile is read again on the next sync
+                Err(err) if !hid::is_disconnect(&err) => {
+                    tracing::debug!("Skipping the profile sync: {err}");
+                    self.metrics.hid_error();
+                }
+                Err(err) => return self.reconnect(&err),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns whether the active window has to be checked (with `paused`).
+    /// Without a provider that reports window changes, that is on every tick.
+    /// With one, it is only after the window changed, unless the profile,
+    /// the pause state or the config changed since the last check or rules
+    /// can stop matching within the same window (see `Config::needs_polling`).
+    /// Debounced and delayed switches are checked until they happened.
+    fn needs_check(&mut self, paused: bool) -> bool {
+        if !self.provider.notifies() {
+            return true;
+        }
+
+        // `tick` may be called without `run` waiting for the changes
+        self.recheck |= self.provider.wait(Duration::ZERO);
+
+        self.recheck
+            || self.checked != Some((self.prev_profile, paused))
+            || self.config.needs_polling()
+            || self.debounce.is_some()
+            || self
+                .min_interval
+                .as_ref()
+                .and_then(MinInterval::pending)
+                .is_some()
+    }
+
+    /// Handles `err` of a failed attempt to `action`: reconnects if the
+    /// duckyPad is gone (see `hid::is_disconnect`), otherwise the error is
+    /// logged and the profile is treated as unknown, so the switch is tried
+    /// again on the next tick.
+    fn hid_failed(&mut self, err: &HidError, action: &str) -> Result<(), DaemonError> {
+        if hid::is_disconnect(err) {
+            return self.reconnect(err);
+        }
+
+        tracing::warn!("Failed to {action}: {err}");
+        self.metrics.hid_error();
+        self.prev_profile = None;
+        Ok(())
+    }
+
+    /// Checks whether the screen got locked or unlocked (see
+    /// `--pause-on-lock`). Like resuming, unlocking resets the cached profile,
+    /// so the window focused after the lock screen is re-evaluated.
+    fn update_locked(&mut self) {
+        let Some(locked) = self.screen_lock.as_mut().and_then(LockDetector::update) else {
+            return;
+        };
+
+        if locked {
+            tracing::info!("Screen locked, pausing autoswitching");
+        } else {
+            tracing::info!("Screen unlocked, resuming autoswitching");
+            self.prev_profile = None;
+        }
+    }
+
+    /// Returns whether the screen is locked (see `--pause-on-lock`).
+    fn locked(&self) -> bool {
+        self.screen_lock.as_ref().is_some_and(LockDetector::locked)
+    }
+
+    /// Returns whether autoswitching is paused after a manual profile change
+    /// (see `--manual-override-grace`).
+    fn overridden(&self) -> bool {
+        self.manual_override
+            .as_ref()
+            .is_some_and(|manual_override| manual_override.active(Instant::now()))
+    }
+
+    /// Blocks until the duckyPad is connected again after communicating with it
+    /// failed with `err`, or until the daemon is asked to shut down.
+    fn reconnect(&mut self, err: &HidError) -> Result<(), DaemonError> {
+        tracing::warn!("Lost connection to duckyPad: {err}");
+        self.metrics.hid_error();
+        self.duckypad = None;
+
+        if let (Some(on_disconnect), Some(info)) = (&self.on_disconnect, self.info.take()) {
+            run_device_callback(on_disconnect, &info);
+        }
+
+        #[cfg(all(unix, feature = "systemd"))]
+        self.systemd
+            .status("waiting for the duckyPad to be reconnected".to_string());
+
+        let duckypad =
+            hid::wait_for_device_until(&mut self.api, self.serial.as_deref(), self.retry, || {
+                #[cfg(all(unix, feature = "systemd"))]
+                self.systemd.watchdog();
+
+                // there is nothing to clean up without a duckyPad
+                self.shutdown.load(Ordering::Relaxed)
+            })
+            .map_err(DaemonError::Reconnect)?;
+
+        // `run` shuts down once the flag is checked again
+        let Some(mut duckypad) = duckypad else {
+            return Ok(());
+        };
+
+        tracing::info!("Reconnected to duckyPad");
+        query_device(&mut duckypad, self.query_firmware);
+        self.metrics.reconnected();
+        self.connected(duckypad)?;
+        self.prev_profile = None;
+        Ok(())
+    }
+
+    /// Starts using the newly connected `duckypad`, logs information about it
+    /// and runs the `on_connect` callback. If its profiles have other names
+    /// than the ones the config was loaded with, e.g. after plugging in
+    /// another duckyPad, the config is loaded again with its names.
+    fn connected(&mut self, duckypad: hid::DuckyPad) -> Result<(), DaemonError> {
+        let info = print_info(&duckypad);
+
+        if self.sync_interval.is_some() && !info.supports(hid::Feature::ReadProfile) {
+            tracing::warn!(
+                "The firmware of the duckyPad doesn't support reading the current profile (requires {} or newer), profile changes on the duckyPad won't be noticed",
+                hid::format_version(hid::Feature::ReadProfile.min_firmware())
+            );
+        }
+
+        if let Some(on_connect) = &self.on_connect {
+            run_device_callback(on_connect, &info);
+        }
+
+        self.info = Some(info);
+        let renamed = duckypad.profile_names() != self.config.device_names();
+        self.duckypad = Some(duckypad);
+
+        if renamed {
+            match self.load_checked(&self.config_path) {
+                Ok(config) => {
+                    tracing::info!("Reloaded config with the profile names of the duckyPad");
+                    self.config = config;
+                    self.recheck = true;
+                    return Ok(());
+                }
+                Err(err) if self.strict => return Err(DaemonError::Config(err)),
+                Err(err) => tracing::error!(
+                    "Failed to reload config with the profile names of the duckyPad, keeping the old one: {err}"
+                ),
+            }
+        }
+
+        match self.check_profiles(&self.config) {
+            Err(err) if self.strict => Err(DaemonError::Config(err)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks the active window a single time and switches the profile if a
+    /// rule matches, ignoring the profile the duckyPad is on (see `--once`).
+    ///
+    /// # Errors
+    ///
+    /// Will return `HidError` if switching the duckyPad failed.
+    pub fn once(mut self) -> Result<SwitchResult, HidError> {
+        let result = switch_profile(
+            self.duckypad.as_ref().filter(|_| !self.dry_run),
+            &mut self.processes,
+            &self.config,
+            None,
+            self.callback.as_ref(),
+            self.provider.as_mut(),
+            false,
+            None,
+            None,
+            None,
+        )?;
+
+        if let SwitchResult::Switched(event) = &result {
+            if let Some(history) = &mut self.history {
+                history.record(event);
+            }
+
+            if let Some(notifier) = &mut self.notifier {
+                notifier.notify(event);
+            }
+        }
+
+        Ok(result)
+    }
+
+    /// Pauses or resumes autoswitching. On resume the cached profile is reset,
+    /// so the next tick re-evaluates the active window.
+    pub fn set_paused(&mut self, paused: bool) {
+        if self.paused == paused {
+            return;
+        }
+
+        self.paused = paused;
+
+        if paused {
+            tracing::info!("Autoswitching paused");
+        } else {
+            tracing::info!("Autoswitching resumed");
+            self.prev_profile = None;
+        }
+    }
+
+    /// Returns the paths of the config file and the files it includes.
+    fn config_files(&self) -> Vec<PathBuf> {
+        std::iter::once(self.config_path.clone())
+            .chain(self.config.included_files().iter().cloned())
+            .collect()
+    }
+
+    /// Replaces the config with the contents of the config file. If the file is
+    /// invalid, the error is logged and the current config is kept.
+    ///
+    /// # Errors
+    ///
+    /// Will return `ConfigError` if the config file can't be loaded.
+    pub fn reload_config(&mut self) -> Result<(), ConfigError> {
+        match self.load_checked(&self.config_path) {
+            Ok(config) => {
+                tracing::info!("Reloaded config");
+                self.config = config;
+                self.recheck = true;
+                Ok(())
+            }
+            Err(err) => {
+                tracing::error!("Failed to reload config, keeping the old one: {err}");
+                Err(err)
+            }
+        }
+    }
+
+    /// Replaces the config with the one named `name` in the config directory
+    /// (see `named_config_file`), which is reloaded from then on. If it is
+    /// missing or invalid, the error is logged and the current config is kept.
+    ///
+    /// # Errors
+    ///
+    /// Will return `ConfigError` if the daemon has no config directory or the
+    /// config can't be loaded.
+    pub fn use_config(&mut self, name: &str) -> Result<(), ConfigError> {
+        let Some(dir) = &self.config_dir else {
+            return Err(ConfigError::NoConfigDir);
+        };
+
+        match named_config_file(dir, name).and_then(|path| Ok((self.load_checked(&path)?, path))) {
+            Ok((config, path)) => {
+                tracing::info!("Using config {name} ({})", path.display());
+                self.config = config;
+                self.config_path = path;
+                self.recheck = true;
+                Ok(())
+            }
+            Err(err) => {
+                tracing::error!("Failed to use config {name}, keeping the current one: {err}");
+                Err(err)
+            }
+        }
+    }
+
+    /// Loads the config file at `path` with the profile names of the connected
+    /// duckyPad (see `load_config`) and checks it against the duckyPad (see
+    /// `check_profiles`).
+    fn load_checked(&self, path: &Path) -> Result<Config, ConfigError> {
+        let no_names = HashMap::new();
+        let device_names = self
+            .duckypad
+            .as_ref()
+            .map_or(&no_names, hid::DuckyPad::profile_names);
+
+        load_config(path, self.strict, device_names).and_then(|config| {
+            match self.check_profiles(&config) {
+                Err(err) if self.strict => Err(err),
+                _ => Ok(config),
+            }
+        })
+    }
+
+    /// Checks that `config` only switches to profiles the connected duckyPad
+    /// has (see `Config::check_max_profiles`). Missing profiles are logged as
+    /// a warning, or as an error with `--strict`, where the caller has to
+    /// reject the config.
+    fn check_profiles(&self, config: &Config) -> Result<(), ConfigError> {
+        let Some(duckypad) = &self.duckypad else {
+            return Ok(());
+        };
+
+        config
+            .check_max_profiles(duckypad.max_profiles())
+            .inspect_err(|err| {
+                if self.strict {
+                    tracing::error!("{err}");
+                } else {
+                    tracing::warn!("{err}");
+                }
+            })
+    }
+
+    /// Returns the index of the rule that switched to the current profile, or
+    /// `None` if it was the `default_profile`, or if the profile is unknown or
+    /// was changed otherwise (on the duckyPad or on request).
+    #[cfg(all(
+        unix,
+        any(feature = "control-socket", feature = "dbus", feature = "systemd")
+    ))]
+    fn rule(&self) -> Option<usize> {
+        self.last_switch().and_then(|last_switch| last_switch.rule)
+    }
+
+    /// Returns the last autoswitch if the duckyPad is still on its profile,
+    /// `None` if the profile is unknown or was changed otherwise since.
+    fn last_switch(&self) -> Option<&LastSwitch> {
+        self.last_switch
+            .as_ref()
+            .filter(|last_switch| self.prev_profile == Some(last_switch.profile))
+    }
+
+    /// Writes the current state to the `--status-file` and sends it to the
+    /// subscribers of the control socket, if it changed.
+    fn write_status(&mut self) {
+        let status = self.current_status();
+
+        #[cfg(all(unix, feature = "control-socket"))]
+        self.subscribers.publish(&status);
+
+        if let Some(status_file) = &mut self.status_file {
+            status_file.write(status);
+        }
+    }
+
+    /// Returns the current state, as written to the `--status-file` and sent
+    /// to subscribers.
+    pub fn current_status(&self) -> Status {
+        let last_switch = self.last_switch();
+        Status {
+            profile: self.prev_profile,
+            profile_name: self
+                .prev_profile
+                .and_then(|profile| self.config.profile_name(profile))
+                .map(str::to_string),
+            app_name: last_switch.map(|last_switch| last_switch.app_name.clone()),
+            rule: last_switch.and_then(|last_switch| last_switch.rule),
+            paused: self.paused || self.locked() || self.overridden(),
+        }
+    }
+
+    /// Returns the current profile, the rule that switched to it and whether
+    /// autoswitching is active.
+    #[cfg(all(unix, any(feature = "control-socket", feature = "systemd")))]
+    fn status(&self) -> String {
+        format!(
+            "profile: {}\trule: {}\tautoswitch: {}",
+            self.prev_profile
+                .map_or("unknown".to_string(), |profile| profile.to_string()),
+            self.rule()
+                .map_or("none".to_string(), |rule| rule.to_string()),
+            if self.paused
+                || self.locked()
+                || self.overridden()
+                || !self.config.autoswitch_enabled()
+            {
+                "inactive"
+            } else {
+                "active"
+            }
+        )
+    }
+
+    /// Returns the connected duckyPad (as cached on connect, so no HID round
+    /// trip is needed), the current profile and whether autoswitching is
+    /// paused (on request, by the lock screen or by a manual override) as a
+    /// single-line JSON object. The device fields are `null`
+    /// while no duckyPad is connected.
+    #[cfg(all(unix, any(feature = "control-socket", feature = "dbus")))]
+    fn info_json(&self) -> String {
+        let info = self.info.as_ref();
+
+        serde_json::json!({
+            "connected": info.is_some(),
+            "model": info.map(|info| &info.model),
+            "detected_model": info.map(|info| info.detected_model.name),
+            "max_profiles": info.map(|info| info.detected_model.max_profiles),
+            "serial": info.map(|info| &info.serial),
+            "firmware": info.map(|info| &info.firmware),
+            "profile": self.prev_profile,
+            "rule": self.rule(),
+            "paused": self.paused,
+            "locked": self.locked(),
+            "overridden": self.overridden(),
+            "autoswitch_enabled": self.config.autoswitch_enabled(),
+            "config": self.config_path,
+            "dry_run": self.dry_run,
+        })
+        .to_string()
+    }
+
+    /// Switches to `profile` directly, e.g. on request of the control socket
+    /// or D-Bus. Returns whether the duckyPad was switched, which doesn't
+    /// happen in dry runs or without a duckyPad.
+    ///
+    /// # Errors
+    ///
+    /// Will return `HidError` if `profile` isn't a profile of the duckyPad
+    /// (see `check_requested`), even in dry runs, or if switching the
+    /// duckyPad failed.
+    pub fn switch_to(&mut self, profile: u32) -> Result<bool, HidError> {
+        check_requested(
+            profile,
+            self.duckypad
+                .as_ref()
+                .map_or(hid::MAX_PROFILES, DuckyPadTransport::max_profiles),
+        )?;
+
+        match &self.duckypad {
+            Some(duckypad) if !self.dry_run => {
+                goto_profile(duckypad, profile).inspect_err(|_| self.metrics.hid_error())?;
+                self.prev_profile = Some(profile);
+                self.metrics.switched(profile);
+                if let Some(min_interval) = &mut self.min_interval {
+                    min_interval.switched();
+                }
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// Executes a request received over the control socket and returns the
+    /// answer for the client.
+    #[cfg(all(unix, feature = "control-socket"))]
+    fn handle_request(&mut self, request: &control::Request) -> String {
+        match request {
+            control::Request::Switch(profile) => match self.switch_to(*profile) {
+                Ok(true) => format!("switched to profile {profile}"),
+                Ok(false) => format!("would switch to profile {profile} (dry run)"),
+                Err(err) => format!("error: {err}"),
+            },
+            control::Request::Status => self.status(),
+            control::Request::Info => self.info_json(),
+            control::Request::Pause => {
+                self.set_paused(true);
+                "paused".to_string()
+            }
+            control::Request::Resume => {
+                self.set_paused(false);
+                "resumed".to_string()
+            }
+            control::Request::Reload => match self.reload_config() {
+                Ok(()) => "reloaded".to_string(),
+                Err(err) => format!("error: {err}"),
+            },
+            control::Request::UseConfig(name) => match self.use_config(name) {
+                Ok(()) => format!("using config {name}"),
+                Err(err) => format!("error: {err}"),
+            },
+            // the client is added to the subscribers by the caller
+            control::Request::Subscribe => serde_json::to_string(&self.current_status())
+                .unwrap_or_else(|err| format!("error: {err}")),
+        }
+    }
+
+    /// Executes a method call received over D-Bus and returns the current
+    /// profile (or the info for `GetInfo`) or an error message.
+    #[cfg(all(unix, feature = "dbus"))]
+    fn handle_dbus_request(&mut self, request: &dbus::Request) -> Result<dbus::Reply, String> {
+        match request {
+            dbus::Request::SwitchProfile(profile) => {
+                self.switch_to(*profile).map_err(|err| err.to_string())?;
+            }
+            dbus::Request::GetCurrentProfile => {}
+            dbus::Request::SetPaused(paused) => self.set_paused(*paused),
+            dbus::Request::GetInfo => return Ok(dbus::Reply::Info(self.info_json())),
+            dbus::Request::UseConfig(name) => {
+                self.use_config(name).map_err(|err| err.to_string())?;
+            }
+        }
+
+        Ok(dbus::Reply::Profile(self.prev_profile))
+    }
+
+    /// Cleans up before `run` returns: notifies systemd and runs the
+    /// `on_disconnect` callback. The `shutdown_flag` is only checked between
+    /// ticks, so a profile switch is never interrupted.
+    fn shut_down(mut self) {
+        tracing::info!("Shutting down");
+
+        #[cfg(all(unix, feature = "systemd"))]
+        self.systemd.stopping();
+
+        if let (Some(on_disconnect), Some(info)) = (&self.on_disconnect, self.info.take()) {
+            run_device_callback(on_disconnect, &info);
+        }
+
+        // dropping the daemon closes the duckyPad and the history file and
+        // releases the lock
+    }
+
+    /// Waits `poll_interval` until the next tick. Windows of a streaming
+    /// window script (or `--stdin`) and focus changes on X11 end the wait
+    /// early, so every new window is handled right away, and so does a
+    /// request to shut down. Returns whether the active window changed.
+    fn wait(&self, poll_interval: Duration) -> bool {
+        let deadline = Instant::now() + poll_interval;
+
+        while !self.shutdown.load(Ordering::Relaxed) && !self.provider.closed() {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return false;
+            }
+
+            if self.provider.wait(remaining.min(SHUTDOWN_CHECK_INTERVAL)) {
+                return true;
+            }
+        }
+
+        false
+    }
+}
+
+/// Checks that `profile` requested via `Daemon::switch_to` is within
+/// `1..=max_profiles`. Putting the duckyPad to sleep (`SLEEP`) is only
+/// allowed as the target of rules.
+fn check_requested(profile: u32, max_profiles: u32) -> Result<(), HidError> {
+    if (1..=max_profiles).contains(&profile) {
+        return Ok(());
+    }
+
+    Err(HidError::HidApiError {
+        message: format!(
+            "Invalid profile {profile}: profiles of this duckyPad need to be within 1..={max_profiles}"
+        ),
+    })
+}
+
+/// Logs information about `duckypad` and returns it.
+fn print_info(duckypad: &hid::DuckyPad) -> hid::DuckyPadInfo {
+    let info = hid::info(duckypad);
+    tracing::info!(
+        "Model: {} ({}, {} profiles)\tSerial: {}\tFirmware: {}",
+        info.model,
+        info.detected_model.name,
+        info.detected_model.max_profiles,
+        info.serial,
+        info.firmware
+    );
+
+    info
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_requested_allows_the_profiles_of_the_duckypad() {
+        assert!(check_requested(1, 31).is_ok());
+        assert!(check_requested(31, 31).is_ok());
+        assert!(check_requested(64, hid::MAX_PROFILES).is_ok());
+    }
+
+    #[test]
+    fn check_requested_refuses_sleep_and_other_profiles() {
+        assert!(check_requested(SLEEP, 31).is_err());
+        assert!(check_requested(32, 31).is_err());
+        assert!(check_requested(u32::MAX, hid::MAX_PROFILES).is_err());
+    }
+
+    /// A window source that always reports the same window.
+    struct FakeWindow;
+
+    impl WindowProvider for FakeWindow {
+        fn active(&mut self) -> Result<crate::WindowInfo, provider::WindowError> {
+            Ok(crate::WindowInfo {
+                process_name: "firefox".to_string(),
+                window_id: "1".to_string(),
+                ..crate::WindowInfo::default()
+            })
+        }
+    }
+
+    #[test]
+    fn recreated_configs_are_reloaded() {
+        let path = std::env::temp_dir().join(format!(
+            "duckypad_daemon-test-{}-daemon-recreated.json",
+            std::process::id()
+        ));
+        std::fs::write(&path, r#"{"autoswitch_enabled": true, "rules_list": []}"#).unwrap();
+        let mut daemon = Daemon::builder(&path)
+            .connect(false)
+            .dry_run(true)
+            .watch_config(true)
+            .window_provider(Box::new(FakeWindow))
+            .build()
+            .unwrap();
+
+        // a dotfile manager deletes the config and writes it again later
+        std::fs::remove_file(&path).unwrap();
+        let missing = [daemon.tick(), daemon.tick()];
+        let kept = daemon.config.autoswitch_enabled();
+        std::fs::write(&path, r#"{"autoswitch_enabled": false, "rules_list": []}"#).unwrap();
+        let recreated = [daemon.tick(), daemon.tick()];
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(missing.iter().chain(&recreated).all(Result::is_ok));
+        assert!(kept);
+        assert!(!daemon.config.autoswitch_enabled());
+    }
+
+    /// A window source that reports focus changes like X11, counting how
+    /// often it was asked for the active window.
+    struct NotifyingWindow {
+        checks: std::rc::Rc<std::cell::Cell<usize>>,
+        changed: std::rc::Rc<std::cell::Cell<bool>>,
+    }
+
+    impl WindowProvider for NotifyingWindow {
+        fn active(&mut self) -> Result<crate::WindowInfo, provider::WindowError> {
+            self.checks.set(self.checks.get() + 1);
+            FakeWindow.active()
+        }
+
+        fn wait(&self, _timeout: Duration) -> bool {
+            self.changed.take()
+        }
+
+        fn notifies(&self) -> bool {
+            true
+        }
+    }
+
+    /// Returns how often the active window was checked in each of three pairs
+    /// of ticks of a daemon with `config`, the window changing before the
+    /// last one.
+    fn checks_per_tick(name: &str, config: &str) -> [usize; 3] {
+        let path = std::env::temp_dir().join(format!(
+            "duckypad_daemon-test-{}-daemon-{name}.json",
+            std::process::id()
+        ));
+        std::fs::write(&path, config).unwrap();
+        let checks = std::rc::Rc::default();
+        let changed = std::rc::Rc::default();
+        let mut daemon = Daemon::builder(&path)
+            .connect(false)
+            .dry_run(true)
+            .window_provider(Box::new(NotifyingWindow {
+                checks: std::rc::Rc::clone(&checks),
+                changed: std::rc::Rc::clone(&changed),
+            }))
+            .build()
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let mut counts = [0; 3];
+        for (i, count) in counts.iter_mut().enumerate() {
+            if i == 2 {
+                changed.set(true);
+            }
+            let before = checks.get();
+            daemon.tick().unwrap();
+            daemon.tick().unwrap();
+            *count = checks.get() - before;
+        }
+
+        counts
+    }
+
+    #[test]
+    fn unchanged_windows_are_only_checked_once() {
+        let counts = checks_per_tick(
+            "unchanged",
+            r#"{"autoswitch_enabled": true, "rules_list": [
+                {"enabled": true, "app_name": "", "process_name": "firefox", "switch_to": 3}
+            ]}"#,
+        );
+
+        assert_eq!(counts, [1, 0, 1]);
+    }
+
+    #[test]
+    fn title_rules_are_checked_on_every_tick() {
+        let counts = checks_per_tick(
+            "titles",
+            r#"{"autoswitch_enabled": true, "rules_list": [
+                {"enabled": true, "app_name": "", "title": "YouTube", "switch_to": 3}
+            ]}"#,
+        );
+
+        assert_eq!(counts, [2, 2, 2]);
+    }
+
+    #[cfg(all(unix, feature = "control-socket"))]
+    #[test]
+    fn manual_overrides_are_reported_as_inactive() {
+        let path = std::env::temp_dir().join(format!(
+            "duckypad_daemon-test-{}-daemon-overridden.json",
+            std::process::id()
+        ));
+        std::fs::write(&path, r#"{"autoswitch_enabled": true, "rules_list": []}"#).unwrap();
+        let mut daemon = Daemon::builder(&path)
+            .connect(false)
+            .dry_run(true)
+            .manual_override_grace(Some(Duration::from_secs(60)))
+            .window_provider(Box::new(FakeWindow))
+            .build()
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let active = daemon.status();
+        daemon
+            .manual_override
+            .as_mut()
+            .unwrap()
+            .start(Instant::now());
+        let info: serde_json::Value = serde_json::from_str(&daemon.info_json()).unwrap();
+
+        assert!(active.ends_with("autoswitch: active"), "{active}");
+        assert!(daemon.status().ends_with("autoswitch: inactive"));
+        assert!(daemon.current_status().paused);
+        assert_eq!(info["overridden"], true);
+        assert_eq!(info["paused"], false);
+    }
+}
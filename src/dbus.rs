@@ -0,0 +1,143 @@
+use std::{
+    sync::mpsc::{channel, Receiver, Sender},
+    time::Duration,
+};
+use zbus::{blocking::Connection, fdo, SignalContext};
+
+/// Well-known name the daemon owns on the session bus.
+pub const SERVICE_NAME: &str = "io.github.traumweh.DuckypadDaemon";
+/// Path of the object that implements `INTERFACE_NAME`.
+pub const OBJECT_PATH: &str = "/io/github/traumweh/DuckypadDaemon";
+/// Name of the interface with the methods and signals below.
+pub const INTERFACE_NAME: &str = "io.github.traumweh.DuckypadDaemon1";
+
+/// Time a method call waits for the daemon to answer it.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Method calls received over D-Bus.
+pub enum Request {
+    /// `SwitchProfile(u32)` - switch to a profile directly
+    SwitchProfile(u32),
+    /// `GetCurrentProfile() -> u32` - return the current profile (`0` if
+    /// unknown)
+    GetCurrentProfile,
+    /// `SetPaused(bool)` - pause or resume autoswitching
+    SetPaused(bool),
+    /// `GetInfo() -> s` - return the connected duckyPad, the current profile
+    /// and whether autoswitching is paused as a JSON object
+    GetInfo,
+    /// `UseConfig(s)` - load the named config from the config directory
+    UseConfig(String),
+}
+
+/// Answer of the daemon to a method call.
+pub enum Reply {
+    /// the current profile (`None` if unknown)
+    Profile(Option<u32>),
+    /// the JSON object for `GetInfo`
+    Info(String),
+}
+
+/// A method call received over D-Bus together with a way to answer it. The
+/// answer is either a `Reply` or an error message.
+pub struct Message {
+    pub request: Request,
+    reply: Sender<Result<Reply, String>>,
+}
+
+impl Message {
+    /// Sends `result` as the answer to the method call. The caller might have
+    /// timed out already, which is ignored.
+    pub fn reply(&self, result: Result<Reply, String>) {
+        let _: Result<_, _> = self.reply.send(result);
+    }
+}
+
+/// The D-Bus service of the daemon, used to emit signals.
+pub struct Service {
+    connection: Connection,
+}
+
+impl Service {
+    /// Emits the `ProfileChanged(u32)` signal.
+    pub fn profile_changed(&self, profile: u32) {
+        if let Err(err) = self.connection.emit_signal(
+            None::<()>,
+            OBJECT_PATH,
+            INTERFACE_NAME,
+            "ProfileChanged",
+            &(profile,),
+        ) {
+            tracing::warn!("Failed to emit ProfileChanged signal: {err}");
+        }
+    }
+}
+
+struct Interface {
+    sender: Sender<Message>,
+}
+
+impl Interface {
+    fn call(&self, request: Request) -> fdo::Result<Reply> {
+        let (reply, answer) = channel();
+
+        self.sender
+            .send(Message { request, reply })
+            .map_err(|_| fdo::Error::Failed("daemon is shutting down".to_string()))?;
+
+        answer
+            .recv_timeout(REPLY_TIMEOUT)
+            .map_err(|_| fdo::Error::Failed("daemon didn't answer".to_string()))?
+            .map_err(fdo::Error::Failed)
+    }
+}
+
+#[zbus::interface(name = "io.github.traumweh.DuckypadDaemon1")]
+impl Interface {
+    fn switch_profile(&self, profile: u32) -> fdo::Result<()> {
+        self.call(Request::SwitchProfile(profile)).map(|_| ())
+    }
+
+    fn get_current_profile(&self) -> fdo::Result<u32> {
+        match self.call(Request::GetCurrentProfile)? {
+            Reply::Profile(profile) => Ok(profile.unwrap_or_default()),
+            Reply::Info(_) => Err(fdo::Error::Failed("unexpected answer".to_string())),
+        }
+    }
+
+    fn get_info(&self) -> fdo::Result<String> {
+        match self.call(Request::GetInfo)? {
+            Reply::Info(info) => Ok(info),
+            Reply::Profile(_) => Err(fdo::Error::Failed("unexpected answer".to_string())),
+        }
+    }
+
+    fn set_paused(&self, paused: bool) -> fdo::Result<()> {
+        self.call(Request::SetPaused(paused)).map(|_| ())
+    }
+
+    fn use_config(&self, name: String) -> fdo::Result<()> {
+        self.call(Request::UseConfig(name)).map(|_| ())
+    }
+
+    #[zbus(signal)]
+    async fn profile_changed(ctxt: &SignalContext<'_>, profile: u32) -> zbus::Result<()>;
+}
+
+/// Connects to the session bus, claims `SERVICE_NAME` and serves the daemon's
+/// interface at `OBJECT_PATH`. Returns the `Service` for emitting signals and
+/// a `Receiver` for the method calls.
+///
+/// # Errors
+///
+/// Will return `zbus::Error` if the session bus isn't reachable or the name
+/// is already taken (e.g. by another running daemon).
+pub fn serve() -> zbus::Result<(Service, Receiver<Message>)> {
+    let (sender, receiver) = channel();
+    let connection = zbus::blocking::connection::Builder::session()?
+        .name(SERVICE_NAME)?
+        .serve_at(OBJECT_PATH, Interface { sender })?
+        .build()?;
+
+    Ok((Service { connection }, receiver))
+}
@@ -2,63 +2,526 @@
 #![allow(clippy::must_use_candidate)]
 
 extern crate hidapi;
-use hidapi::{HidApi, HidDevice, HidError};
+use hidapi::{DeviceInfo, HidApi, HidDevice, HidError};
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicU8, Ordering},
+    time::{Duration, Instant},
+};
 
 pub struct DuckyPadInfo {
+    /// product string reported by the device
     pub model: String,
+    /// model detected from the product id
+    pub detected_model: &'static Model,
     pub serial: String,
     pub firmware: String,
+    /// `firmware` parsed as (major, minor, patch), `None` if it is unknown
+    pub version: Option<Version>,
+}
+
+impl DuckyPadInfo {
+    /// Returns whether the firmware of the duckypad supports `feature` (see
+    /// `supports`).
+    pub fn supports(&self, feature: Feature) -> bool {
+        supports(self.version, feature)
+    }
+}
+
+/// A firmware version of the duckypad as (major, minor, patch).
+pub type Version = (u8, u8, u8);
+
+/// Parses a firmware version like `1.2.3`, as reported by the duckypad in
+/// `info`. Returns `None` if `firmware` doesn't consist of three numbers of at
+/// most 255 separated by dots, e.g. for `unknown`.
+pub fn parse_version(firmware: &str) -> Option<Version> {
+    let mut parts = firmware.trim().split('.').map(|part| part.parse().ok());
+
+    match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(Some(major)), Some(Some(minor)), Some(Some(patch)), None) => {
+            Some((major, minor, patch))
+        }
+        _ => None,
+    }
+}
+
+/// Formats `version` like the duckypad's own tools, e.g. `1.2.3`.
+pub fn format_version((major, minor, patch): Version) -> String {
+    format!("{major}.{minor}.{patch}")
+}
+
+/// Returns whether `version` is `min` or newer. An unknown version counts as
+/// new enough, so a command is still tried if the firmware couldn't be read.
+pub fn at_least(version: Option<Version>, min: Version) -> bool {
+    version.is_none_or(|version| version >= min)
+}
+
+/// Commands of the duckypad that older firmware doesn't support.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Feature {
+    /// Reading the active profile from the info reply (see
+    /// `read_current_profile`)
+    ReadProfile,
+    /// The sleep command (see `sleep_device`)
+    Sleep,
+}
+
+impl Feature {
+    /// Returns the first firmware version that supports the feature.
+    pub fn min_firmware(self) -> Version {
+        match self {
+            Self::ReadProfile => (0, 13, 0),
+            Self::Sleep => (0, 20, 0),
+        }
+    }
+}
+
+impl std::fmt::Display for Feature {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ReadProfile => write!(f, "reading the current profile"),
+            Self::Sleep => write!(f, "sleeping"),
+        }
+    }
+}
+
+/// Returns whether firmware `version` supports `feature`, which is assumed if
+/// the version is unknown (see `at_least`).
+pub fn supports(version: Option<Version>, feature: Feature) -> bool {
+    at_least(version, feature.min_firmware())
 }
 
 pub const PC_TO_DUCKYPAD_HID_BUF_SIZE: usize = 64;
 pub const DUCKYPAD_TO_PC_HID_BUF_SIZE: usize = 32;
 
-const VENDOR_ID: u16 = 0x0483;
-const PRODUCT_ID: u16 = 0xd11c;
-const USAGE_PAGE: u16 = 0x0001;
-const USAGE: u16 = 0x003a;
+/// Report id of every message sent to the duckypad (byte 0 of the frame, see
+/// `frame`).
+pub const REPORT_ID: u8 = 0x05;
+
+/// Report id of every reply of the duckypad (byte 0), which echoes the
+/// sequence number of the request in byte 1 and has its `Status` in byte 2.
+pub const REPLY_REPORT_ID: u8 = 0x04;
+
+/// Command byte of the info request, which is answered with the firmware
+/// version (bytes 3 to 5) and the current profile (byte 11).
+pub const INFO_COMMAND: u8 = 0x00;
+
+/// Command byte of the goto profile command (see `goto_profile_frame`).
+pub const GOTO_PROFILE_COMMAND: u8 = 0x01;
+
+/// Builds a message to the duckypad as described in its HID documentation:
+/// byte 0 is the `REPORT_ID`, byte 1 a sequence number that the reply echoes
+/// (left at `0`, except for info requests), byte 2 the `command` and the
+/// `payload` starts at byte 3. The rest of the frame is zero.
+///
+/// # Panics
+///
+/// The function will panic if `payload` doesn't fit into the frame.
+pub fn frame(command: u8, payload: &[u8]) -> [u8; PC_TO_DUCKYPAD_HID_BUF_SIZE] {
+    let mut buf = [0x00; PC_TO_DUCKYPAD_HID_BUF_SIZE];
+    buf[0] = REPORT_ID;
+    buf[2] = command;
+    buf[3..3 + payload.len()].copy_from_slice(payload);
+    buf
+}
+
+/// Builds the goto profile command for `profile`. The profile number is a
+/// single byte (byte 3), as no model has more than 255 profiles.
+pub fn goto_profile_frame(profile: u8) -> [u8; PC_TO_DUCKYPAD_HID_BUF_SIZE] {
+    frame(GOTO_PROFILE_COMMAND, &[profile])
+}
+
+/// A duckypad model and the ids of its HID interface.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Model {
+    pub name: &'static str,
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub usage_page: u16,
+    pub usage: u16,
+    /// highest profile id of the model
+    pub max_profiles: u32,
+}
+
+/// All known duckypad models.
+pub const MODELS: [Model; 2] = [
+    Model {
+        name: "duckyPad",
+        vendor_id: 0x0483,
+        product_id: 0xd11c,
+        usage_page: 0x0001,
+        usage: 0x003a,
+        max_profiles: 31,
+    },
+    Model {
+        name: "duckyPad Pro",
+        vendor_id: 0x0483,
+        product_id: 0xd11d,
+        usage_page: 0x0001,
+        usage: 0x003a,
+        max_profiles: 64,
+    },
+];
+
+/// Highest profile id of any known model.
+pub const MAX_PROFILES: u32 = 64;
+
+/// A connected duckypad, its model, its firmware version (see
+/// `query_version`) and the names of its profiles (see
+/// `query_profile_names`).
+pub struct DuckyPad {
+    device: HidDevice,
+    model: &'static Model,
+    version: Option<Version>,
+    profile_names: HashMap<String, u32>,
+}
+
+impl DuckyPad {
+    /// The HID device of the duckypad.
+    pub fn device(&self) -> &HidDevice {
+        &self.device
+    }
+
+    /// The model of the duckypad.
+    pub fn model(&self) -> &'static Model {
+        self.model
+    }
+
+    /// The firmware version of the duckypad, `None` if it wasn't or couldn't
+    /// be read.
+    pub fn version(&self) -> Option<Version> {
+        self.version
+    }
+
+    /// Reads the firmware version from the duckypad with an info request. It
+    /// isn't read when the duckypad is opened, as this round trip is the one
+    /// that hangs or fails first on flaky connections, so opening still works
+    /// without it (see `--no-firmware-query`).
+    pub fn query_version(&mut self) {
+        self.version = read_version(&self.device);
+    }
+
+    /// The names of the profiles on the duckypad with their numbers, empty if
+    /// they weren't or couldn't be read.
+    pub fn profile_names(&self) -> &HashMap<String, u32> {
+        &self.profile_names
+    }
+
+    /// Reads the names of the profiles from the duckypad (see
+    /// `read_profile_names`). If that fails, e.g. because the firmware can't
+    /// list its files, the duckypad has no names and only the ones from the
+    /// config's `profile_names` can be used.
+    pub fn query_profile_names(&mut self) {
+        self.profile_names = read_profile_names(&self.device).unwrap_or_else(|err| {
+            tracing::debug!("Couldn't read the profile names from the duckyPad: {err}");
+            HashMap::new()
+        });
+    }
+}
+
+/// How long `read` waits for a reply of the duckypad and how often it checks
+/// for one in the meantime.
+#[derive(Clone, Copy)]
+pub struct Timeout {
+    pub total: Duration,
+    pub poll: Duration,
+}
+
+/// Timeout for info requests, e.g. firmware information at startup. As the
+/// firmware version is requested up to `VERSION_ATTEMPTS` times, this is the
+/// timeout of every attempt.
+pub const INFO_TIMEOUT: Timeout = Timeout {
+    total: Duration::from_secs(2),
+    poll: Duration::from_millis(10),
+};
+
+/// Timeout for switching and reading the profile, which happens during the
+/// main loop and shouldn't block it for long.
+pub const PROFILE_TIMEOUT: Timeout = Timeout {
+    total: Duration::from_secs(1),
+    poll: Duration::from_millis(1),
+};
+
+/// How often `write` tries to write a report before giving up.
+const WRITE_ATTEMPTS: u32 = 3;
+/// Time `write` waits before trying again after a failed write.
+const WRITE_RETRY_DELAY: Duration = Duration::from_millis(50);
+
+/// Initializes a connection to the duckypad and returns it as `DuckyPad`.
+///
+/// # Arguments
+///
+/// * `api` - connection to the hid api
+///
+/// # Errors
+///
+/// Will return `HidError` if no duckypad is connected or if its `HidDevice`
+/// cannot be opened or set to non-blocking mode. The error message tells both
+/// cases apart.
+pub fn init(api: &HidApi) -> Result<DuckyPad, HidError> {
+    let items: Vec<_> = api
+        .device_list()
+        .filter(|item| model_of(item).is_some())
+        .collect();
+
+    if items.is_empty() {
+        let models: Vec<_> = MODELS
+            .iter()
+            .map(|model| {
+                format!(
+                    "{}: vendor_id {:#06x}, product_id {:#06x}, usage_page {:#06x}, usage {:#06x}",
+                    model.name, model.vendor_id, model.product_id, model.usage_page, model.usage
+                )
+            })
+            .collect();
+
+        return Err(HidError::HidApiError {
+            message: format!(
+                "No duckyPad found ({}). Is it connected?",
+                models.join("; ")
+            ),
+        });
+    }
+
+    open_first(api, items)
+}
+
+/// Initializes a connection to the duckypad with the serial number `serial`
+/// and returns it as `DuckyPad`.
+///
+/// # Arguments
+///
+/// * `api` - connection to the hid api
+/// * `serial` - serial number of the duckypad to connect to
+///
+/// # Errors
+///
+/// Will return `HidError` if no duckypad with a matching serial number is
+/// connected or if its `HidDevice` cannot be opened or set to non-blocking
+/// mode. The error message lists the serial numbers of all detected duckypads.
+pub fn init_by_serial(api: &HidApi, serial: &str) -> Result<DuckyPad, HidError> {
+    let mut serials = vec![];
+    let mut items = vec![];
+
+    for item in api.device_list().filter(|item| model_of(item).is_some()) {
+        let item_serial = item.serial_number().unwrap_or("unknown");
+
+        if item_serial == serial {
+            items.push(item);
+        } else if !serials.contains(&item_serial) {
+            serials.push(item_serial);
+        }
+    }
+
+    if !items.is_empty() {
+        return open_first(api, items);
+    }
+
+    Err(HidError::HidApiError {
+        message: format!(
+            "Couldn't find device with serial number {serial}. Detected serial numbers: [{}]",
+            serials.join(", ")
+        ),
+    })
+}
+
+/// Initializes a connection to the duckypad with the serial number `serial` or
+/// to the first duckypad found if `serial` is `None` (see `init` and
+/// `init_by_serial`).
+///
+/// # Errors
+///
+/// Will return `HidError` if the duckypad `HidDevice` cannot be found, opened
+/// or set to non-blocking mode.
+pub fn open(api: &HidApi, serial: Option<&str>) -> Result<DuckyPad, HidError> {
+    match serial {
+        Some(serial) => init_by_serial(api, serial),
+        None => init(api),
+    }
+}
+
+/// How `wait_for_device` retries connecting to the duckypad: the time between
+/// two attempts starts at `interval` and doubles after every failed attempt,
+/// up to `max_interval`.
+#[derive(Debug, Clone, Copy)]
+pub struct Retry {
+    pub interval: Duration,
+    pub max_interval: Duration,
+    /// number of failed attempts after which to give up (`None` retries
+    /// forever)
+    pub max_attempts: Option<u32>,
+}
+
+/// Longest time `wait_for_device_until` sleeps without calling `on_retry`.
+const RETRY_TICK: Duration = Duration::from_secs(1);
 
-/// Initializes a connection to the duckypad and returns an `HidDevice`.
+/// Blocks until a connection to the duckypad could be initialized (see `open`)
+/// and returns it. The list of hid devices is refreshed before
+/// every attempt, so devices plugged in after startup are found as well.
 ///
 /// # Arguments
 ///
 /// * `api` - connection to the hid api
+/// * `serial` - optional serial number of the duckypad to connect to
+/// * `retry` - time to wait between two attempts and when to give up
+/// * `on_retry` - called after every failed attempt and at least once a
+///   second while waiting, e.g. to keep a watchdog alive
 ///
 /// # Errors
 ///
-/// Will return `HidError` if the duckypad `HidDevice` cannot be opened or
-/// set to non-blocking mode.
-pub fn init(api: &HidApi) -> Result<HidDevice, HidError> {
-    for item in api.device_list() {
-        if item.vendor_id() == VENDOR_ID
-            && item.product_id() == PRODUCT_ID
-            && item.usage_page() == USAGE_PAGE
-            && item.usage() == USAGE
-        {
-            let device = api.open_path(item.path())?;
-            device.set_blocking_mode(false)?;
+/// Will return the `HidError` of the last attempt once `retry.max_attempts`
+/// attempts failed.
+pub fn wait_for_device(
+    api: &mut HidApi,
+    serial: Option<&str>,
+    retry: Retry,
+    mut on_retry: impl FnMut(),
+) -> Result<DuckyPad, HidError> {
+    loop {
+        // never cancelled, so this only returns with a device or an error
+        let cancel = || {
+            on_retry();
+            false
+        };
+        if let Some(device) = wait_for_device_until(api, serial, retry, cancel)? {
             return Ok(device);
         }
     }
+}
+
+/// Like `wait_for_device`, but `on_retry` returns whether to stop waiting,
+/// e.g. because the caller is shutting down. Returns `None` if it did.
+///
+/// # Errors
+///
+/// Will return the `HidError` of the last attempt once `retry.max_attempts`
+/// attempts failed.
+pub fn wait_for_device_until(
+    api: &mut HidApi,
+    serial: Option<&str>,
+    retry: Retry,
+    mut on_retry: impl FnMut() -> bool,
+) -> Result<Option<DuckyPad>, HidError> {
+    let mut interval = retry.interval;
+    let mut attempts = 0;
+
+    loop {
+        if let Err(err) = api.refresh_devices() {
+            tracing::warn!("Failed to refresh hid devices: {err}");
+        }
+
+        match open(api, serial) {
+            Ok(device) => return Ok(Some(device)),
+            Err(err) => {
+                attempts += 1;
+
+                if retry.max_attempts.is_some_and(|max| attempts >= max) {
+                    return Err(err);
+                }
+
+                if attempts == 1 {
+                    tracing::warn!(
+                        "Failed to connect to duckyPad: {err}\nRetrying in {:.2} seconds!",
+                        interval.as_secs_f64()
+                    );
+                } else {
+                    tracing::debug!(
+                        "Failed to connect to duckyPad: {err}, retrying in {:.2} seconds",
+                        interval.as_secs_f64()
+                    );
+                }
+            }
+        }
+
+        let mut remaining = interval;
+
+        while !remaining.is_zero() {
+            if on_retry() {
+                return Ok(None);
+            }
+            let tick = remaining.min(RETRY_TICK);
+            std::thread::sleep(tick);
+            remaining -= tick;
+        }
+
+        interval = (interval * 2).min(retry.max_interval.max(retry.interval));
+    }
+}
+
+/// Opens the first of the duckypad interfaces `items` that can be opened,
+/// preferring the ones with the duckypad's usage page and usage.
+fn open_first(api: &HidApi, mut items: Vec<&DeviceInfo>) -> Result<DuckyPad, HidError> {
+    items.sort_by_key(|item| !model_of(item).is_some_and(|model| has_usage(item, model)));
+    let mut error = None;
+
+    for item in items {
+        let Some(model) = model_of(item) else {
+            continue;
+        };
+
+        match api.open_path(item.path()) {
+            Ok(device) => {
+                device.set_blocking_mode(false)?;
+                return Ok(DuckyPad {
+                    device,
+                    model,
+                    version: None,
+                    profile_names: HashMap::new(),
+                });
+            }
+            Err(err) => error = Some(err),
+        }
+    }
 
     Err(HidError::HidApiError {
         message: format!(
-            "Couldn't find device: (\
-            vendor_id: {VENDOR_ID:#06x}, \
-            product_id: {PRODUCT_ID:#06x}, \
-            usage_page: {USAGE_PAGE:#06x}, \
-            usage: {USAGE:#06x}"
+            "Found a duckyPad, but couldn't open it (the hid backend reported: {}). \
+            Check that your user has permissions to access it!",
+            error.map_or_else(|| "no error".to_string(), |err| err.to_string())
         ),
     })
 }
 
+/// Returns the model of the duckypad if `item` is one of its interfaces.
+fn model_of(item: &DeviceInfo) -> Option<&'static Model> {
+    find_model(
+        item.vendor_id(),
+        item.product_id(),
+        item.usage_page(),
+        item.usage(),
+    )
+}
+
+/// Returns the model with the ids of an HID interface. The macOS backend of
+/// hidapi doesn't always report the usage page and usage of the duckypad's
+/// interface, so only the vendor and product id are checked there.
+pub fn find_model(
+    vendor_id: u16,
+    product_id: u16,
+    usage_page: u16,
+    usage: u16,
+) -> Option<&'static Model> {
+    MODELS.iter().find(|model| {
+        model.vendor_id == vendor_id
+            && model.product_id == product_id
+            && (cfg!(target_os = "macos") || (model.usage_page, model.usage) == (usage_page, usage))
+    })
+}
+
+fn has_usage(item: &DeviceInfo, model: &Model) -> bool {
+    item.usage_page() == model.usage_page && item.usage() == model.usage
+}
+
 /// Returns device and firmware information about the connected duckypad.
-/// Unavailable information will be replaced with "unknown".
+/// Unavailable information will be replaced with "unknown", which includes
+/// the firmware unless `DuckyPad::query_version` read it. The model and serial
+/// come from the USB descriptors, which need no round trip.
 ///
 /// # Arguments
 ///
-/// * `device` - connected duckypad hid device
-pub fn info(device: &HidDevice) -> DuckyPadInfo {
+/// * `duckypad` - connected duckypad
+pub fn info(duckypad: &DuckyPad) -> DuckyPadInfo {
+    let device = &duckypad.device;
     let model = device
         .get_product_string()
         .unwrap_or_else(|_| Some("unknown".to_string()))
@@ -68,66 +531,985 @@ pub fn info(device: &HidDevice) -> DuckyPadInfo {
         .unwrap_or_else(|_| Some("unknown".to_string()))
         .unwrap_or_else(|| "unknown".to_string());
 
-    let mut buf = [0x00; PC_TO_DUCKYPAD_HID_BUF_SIZE];
-    buf[0] = 0x05;
-    let firmware = match write(device, buf) {
-        Ok(opt) => match opt {
-            Some(buffer) => format!("{}.{}.{}", buffer[3], buffer[4], buffer[5]),
-            None => "unknown".to_string(),
-        },
-        Err(_) => "unknown".to_string(),
-    };
+    let firmware = duckypad
+        .version
+        .map_or_else(|| "unknown".to_string(), format_version);
 
     DuckyPadInfo {
         model,
+        detected_model: duckypad.model,
         serial,
         firmware,
+        version: duckypad.version,
+    }
+}
+
+/// How often `read_version` sends the info request before the firmware is
+/// reported as unknown, e.g. while a duckypad that was just plugged in is
+/// still starting.
+const VERSION_ATTEMPTS: u32 = 3;
+/// Time `read_version` waits before sending the info request again.
+const VERSION_RETRY_DELAY: Duration = Duration::from_millis(200);
+
+/// Sequence number of the last request that gets one (see `next_sequence`).
+static SEQUENCE: AtomicU8 = AtomicU8::new(0);
+
+/// Returns a new sequence number for a request, so its replies can be told
+/// apart from late ones to earlier requests (see `is_reply_to`).
+fn next_sequence() -> u8 {
+    SEQUENCE.fetch_add(1, Ordering::Relaxed).wrapping_add(1)
+}
+
+/// Returns whether `reply` is the reply to the request with the sequence
+/// number `sequence`, whatever its status.
+fn is_reply_to(reply: &[u8; DUCKYPAD_TO_PC_HID_BUF_SIZE], sequence: u8) -> bool {
+    reply[0] == REPLY_REPORT_ID && reply[1] == sequence
+}
+
+/// Returns the firmware version from bytes 3 to 5 of `reply`, or `None` if it
+/// isn't the successful reply to the info request with the sequence number
+/// `sequence`, e.g. a stray reply to an earlier request that timed out.
+pub fn reply_version(reply: &[u8; DUCKYPAD_TO_PC_HID_BUF_SIZE], sequence: u8) -> Option<Version> {
+    (is_reply_to(reply, sequence) && Status::of(reply) == Status::Ok)
+        .then_some((reply[3], reply[4], reply[5]))
+}
+
+/// Returns the current profile from byte 11 of `reply`, or `None` if it isn't
+/// the successful reply to the info request with the sequence number
+/// `sequence` (see `reply_version`).
+pub fn reply_profile(reply: &[u8; DUCKYPAD_TO_PC_HID_BUF_SIZE], sequence: u8) -> Option<u8> {
+    (is_reply_to(reply, sequence) && Status::of(reply) == Status::Ok).then_some(reply[11])
+}
+
+/// Sends an info request with a new sequence number and returns the reply to
+/// it together with the sequence number. Replies to other requests, e.g. late
+/// ones to a request that timed out, are skipped until `timeout.total`
+/// elapsed, after which `Ok(None)` is returned.
+///
+/// # Errors
+///
+/// Will return `HidError` if writing to or reading from the duckypad
+/// `HidDevice` fails.
+fn request_info<D: Reports + ?Sized>(
+    device: &D,
+    timeout: Timeout,
+) -> Result<Option<([u8; DUCKYPAD_TO_PC_HID_BUF_SIZE], u8)>, HidError> {
+    let sequence = next_sequence();
+    let mut buf = frame(INFO_COMMAND, &[]);
+    buf[1] = sequence;
+
+    let deadline = Instant::now() + timeout.total;
+    let mut reply = write(device, buf, timeout)?;
+
+    loop {
+        match reply {
+            Some(reply) if is_reply_to(&reply, sequence) => return Ok(Some((reply, sequence))),
+            Some(reply) => tracing::debug!(
+                "Ignoring a reply that doesn't belong to the info request: {:02x?}",
+                &reply[..6]
+            ),
+            None => return Ok(None),
+        }
+
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Ok(None);
+        }
+        reply = read(
+            device,
+            Timeout {
+                total: remaining,
+                poll: timeout.poll,
+            },
+        )?;
+    }
+}
+
+/// Returns the firmware version of the duckypad from the reply to an info
+/// request (see `reply_version`), or `None` if it doesn't reply with one. The
+/// request is sent up to `VERSION_ATTEMPTS` times, and replies to other
+/// requests are skipped (see `request_info`).
+fn read_version<D: Reports + ?Sized>(device: &D) -> Option<Version> {
+    for attempt in 1..=VERSION_ATTEMPTS {
+        if attempt > 1 {
+            tracing::debug!("Requesting the firmware version again ({attempt}/{VERSION_ATTEMPTS})");
+            std::thread::sleep(VERSION_RETRY_DELAY);
+        }
+
+        match request_info(device, INFO_TIMEOUT) {
+            Ok(Some((reply, sequence))) => {
+                if let Some(version) = reply_version(&reply, sequence) {
+                    return Some(version);
+                }
+                tracing::debug!("duckyPad refused the info request ({})", Status::of(&reply));
+            }
+            Ok(None) => tracing::debug!("duckyPad didn't reply to the info request"),
+            Err(err) if is_disconnect(&err) => return None,
+            Err(err) => tracing::debug!("Failed to request the firmware version: {err}"),
+        }
+    }
+
+    None
+}
+
+/// Returns the id of the profile that is currently active on the duckypad by
+/// issuing an info request (`0x05` report with command `0x00`) and reading the
+/// profile number from byte 11 of the reply (see `reply_profile`). Replies to
+/// other requests are skipped (see `request_info`).
+///
+/// # Arguments
+///
+/// * `device` - connected duckypad hid device
+///
+/// # Errors
+///
+/// Will return `HidError` if writing to or the follow-up reading from the
+/// duckypad `HidDevice` fails, if the duckypad doesn't reply in time or if it
+/// refuses the request, e.g. because it is busy. Only the first case can mean
+/// that the duckypad is gone (see `is_disconnect`).
+pub fn read_current_profile<D: Reports + ?Sized>(device: &D) -> Result<u8, HidError> {
+    match request_info(device, PROFILE_TIMEOUT)? {
+        Some((reply, sequence)) => {
+            reply_profile(&reply, sequence).ok_or_else(|| HidError::HidApiError {
+                message: format!("duckyPad refused the info request ({})", Status::of(&reply)),
+            })
+        }
+        None => Err(HidError::HidApiError {
+            message: "duckyPad didn't reply to info request".to_string(),
+        }),
+    }
+}
+
+/// Command byte of the list files command, which lists a directory on the SD
+/// card of the duckypad, the root directory with an empty payload (see
+/// `read_profile_names`).
+pub const LIST_FILES_COMMAND: u8 = 0x0a;
+
+/// Most replies `read_profile_names` reads, so a duckypad that never ends its
+/// listing can't keep it busy. The root directory of a duckypad holds little
+/// besides its profile folders.
+const MAX_LIST_ENTRIES: usize = 256;
+
+/// Returns the entry that `reply` to a list files request lists: whether it
+/// is a directory (byte 3 is `1`, `0` for files) and its name, which starts at
+/// byte 4 and is terminated by a zero byte.
+pub fn reply_entry(reply: &[u8; DUCKYPAD_TO_PC_HID_BUF_SIZE]) -> (bool, String) {
+    let name = &reply[4..];
+    let len = name
+        .iter()
+        .position(|&byte| byte == 0)
+        .unwrap_or(name.len());
+    (
+        reply[3] == 1,
+        String::from_utf8_lossy(&name[..len]).into_owned(),
+    )
+}
+
+/// Returns the number and name of the profile stored in the folder `folder`
+/// of the duckypad, which is named like `profile3_Firefox`, or `None` for any
+/// other folder.
+pub fn parse_profile_folder(folder: &str) -> Option<(u32, &str)> {
+    let (number, name) = folder.strip_prefix("profile")?.split_once('_')?;
+
+    if name.is_empty() || number.is_empty() || !number.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    Some((number.parse().ok()?, name))
+}
+
+/// Returns the names of the profiles on the duckypad with their numbers,
+/// read from the names of its profile folders (see `parse_profile_folder`)
+/// with the list files command. The duckypad replies with one entry of its
+/// root directory per reply, each echoing the sequence number of the
+/// request, and ends the listing with a reply with `Status::Eof`. Replies to
+/// other requests are skipped. If several folders have the same name, the
+/// lowest profile number is used.
+///
+/// # Arguments
+///
+/// * `device` - connected duckypad hid device
+///
+/// # Errors
+///
+/// Will return `HidError` if writing to or reading from the duckypad
+/// `HidDevice` fails, if the duckypad doesn't finish the listing in time or
+/// if it refuses the request, e.g. because its firmware can't list files.
+pub fn read_profile_names<D: Reports + ?Sized>(
+    device: &D,
+) -> Result<HashMap<String, u32>, HidError> {
+    let sequence = next_sequence();
+    let mut buf = frame(LIST_FILES_COMMAND, &[]);
+    buf[1] = sequence;
+
+    let mut names = HashMap::new();
+    let mut reply = write(device, buf, INFO_TIMEOUT)?;
+
+    for _ in 0..MAX_LIST_ENTRIES {
+        let Some(entry) = reply else {
+            return Err(HidError::HidApiError {
+                message: "duckyPad didn't finish listing its files".to_string(),
+            });
+        };
+
+        if is_reply_to(&entry, sequence) {
+            match Status::of(&entry) {
+                Status::Eof => return Ok(names),
+                Status::Ok => {
+                    let (is_dir, folder) = reply_entry(&entry);
+                    if let Some((profile, name)) = parse_profile_folder(&folder).filter(|_| is_dir)
+                    {
+                        names
+                            .entry(name.to_string())
+                            .and_modify(|known: &mut u32| *known = (*known).min(profile))
+                            .or_insert(profile);
+                    }
+                }
+                status => {
+                    return Err(HidError::HidApiError {
+                        message: format!("duckyPad refused the list files request ({status})"),
+                    })
+                }
+            }
+        } else {
+            tracing::debug!(
+                "Ignoring a reply that doesn't belong to the list files request: {:02x?}",
+                &entry[..6]
+            );
+        }
+
+        reply = read(device, INFO_TIMEOUT)?;
+    }
+
+    Err(HidError::HidApiError {
+        message: format!("duckyPad listed more than {MAX_LIST_ENTRIES} files"),
+    })
+}
+
+/// Command byte of the sleep command, which blanks the screen of the duckypad
+/// (see `sleep_device`).
+pub const SLEEP_COMMAND: u8 = 0x15;
+
+/// Puts the duckypad to sleep by issuing the sleep command (`0x05` report with
+/// command `0x15`), which blanks its screen until a key is pressed or it gets
+/// another command. Returns the status of its reply.
+///
+/// # Arguments
+///
+/// * `device` - connected duckypad hid device
+///
+/// # Errors
+///
+/// Will return `HidError` if writing to or the follow-up reading from the
+/// duckypad `HidDevice` fails or if the duckypad doesn't reply in time.
+pub fn sleep_device<D: Reports + ?Sized>(device: &D) -> Result<Status, HidError> {
+    match write(device, frame(SLEEP_COMMAND, &[]), PROFILE_TIMEOUT)? {
+        Some(reply) => Ok(Status::of(&reply)),
+        None => Err(HidError::HidApiError {
+            message: "duckyPad didn't reply to the sleep command".to_string(),
+        }),
+    }
+}
+
+/// Status of a reply of the duckypad, which is stored in its byte 2.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Status {
+    /// The command was executed
+    Ok,
+    /// The command failed
+    Error,
+    /// The duckypad is busy (e.g. still switching to a profile) and ignored
+    /// the command
+    Busy,
+    /// The last entry of a listing was sent (see `read_profile_names`)
+    Eof,
+    /// Any other status byte
+    Unknown(u8),
+}
+
+impl Status {
+    /// Returns the status of `reply`.
+    pub fn of(reply: &[u8; DUCKYPAD_TO_PC_HID_BUF_SIZE]) -> Self {
+        match reply[2] {
+            0x00 => Self::Ok,
+            0x01 => Self::Error,
+            0x02 => Self::Busy,
+            0x03 => Self::Eof,
+            status => Self::Unknown(status),
+        }
+    }
+}
+
+impl std::fmt::Display for Status {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Ok => write!(f, "ok"),
+            Self::Error => write!(f, "error"),
+            Self::Busy => write!(f, "busy"),
+            Self::Eof => write!(f, "end of listing"),
+            Self::Unknown(status) => write!(f, "unknown status {status:#04x}"),
+        }
+    }
+}
+
+/// Connection to a duckypad used for switching profiles. It is implemented for
+/// `DuckyPad` and allows replacing the device, e.g. with a fake one that
+/// records the switches.
+pub trait DuckyPadTransport {
+    /// Switches the duckypad to `profile`, which is already validated to be
+    /// within `1..=max_profiles()`, and returns the status of its reply.
+    ///
+    /// # Errors
+    ///
+    /// Will return `HidError` if communicating with the duckypad fails or it
+    /// doesn't reply in time.
+    fn write_profile(&self, profile: u32) -> Result<Status, HidError>;
+
+    /// Puts the duckypad to sleep (see `sleep_device`) and returns the status
+    /// of its reply.
+    ///
+    /// # Errors
+    ///
+    /// Will return `HidError` if communicating with the duckypad fails or it
+    /// doesn't reply in time.
+    fn write_sleep(&self) -> Result<Status, HidError>;
+
+    /// Returns the id of the profile that is currently active on the duckypad.
+    ///
+    /// # Errors
+    ///
+    /// Will return `HidError` if communicating with the duckypad fails.
+    fn current_profile(&self) -> Result<u8, HidError>;
+
+    /// Returns the highest profile id of the duckypad.
+    fn max_profiles(&self) -> u32;
+
+    /// Returns whether the duckypad supports `feature`, which is assumed by
+    /// default.
+    fn supports(&self, _feature: Feature) -> bool {
+        true
+    }
+}
+
+impl DuckyPadTransport for DuckyPad {
+    fn write_profile(&self, profile: u32) -> Result<Status, HidError> {
+        let buf =
+            u8::try_from(profile)
+                .map(goto_profile_frame)
+                .map_err(|_| HidError::HidApiError {
+                    message: format!("Profile {profile} doesn't fit into the goto profile command"),
+                })?;
+
+        match write(&self.device, buf, PROFILE_TIMEOUT)? {
+            Some(reply) => Ok(Status::of(&reply)),
+            None => Err(HidError::HidApiError {
+                message: format!("duckyPad didn't reply to switching to profile {profile}"),
+            }),
+        }
+    }
+
+    fn write_sleep(&self) -> Result<Status, HidError> {
+        sleep_device(&self.device)
+    }
+
+    fn current_profile(&self) -> Result<u8, HidError> {
+        read_current_profile(&self.device)
+    }
+
+    fn max_profiles(&self) -> u32 {
+        self.model.max_profiles
+    }
+
+    fn supports(&self, feature: Feature) -> bool {
+        supports(self.version, feature)
+    }
+}
+
+/// Raw reports exchanged with a duckypad. It is implemented for the
+/// non-blocking `HidDevice` of a `DuckyPad` and allows `read`, `write` and the
+/// requests built on them to run against a stub device.
+pub trait Reports {
+    /// Writes the report `buf` and returns the number of bytes written.
+    ///
+    /// # Errors
+    ///
+    /// Will return `HidError` if writing to the device fails.
+    fn write_report(&self, buf: &[u8]) -> Result<usize, HidError>;
+
+    /// Reads a report into `buf` without blocking and returns the number of
+    /// bytes read, 0 if no report is available yet.
+    ///
+    /// # Errors
+    ///
+    /// Will return `HidError` if reading from the device fails.
+    fn read_report(&self, buf: &mut [u8]) -> Result<usize, HidError>;
+}
+
+impl Reports for HidDevice {
+    fn write_report(&self, buf: &[u8]) -> Result<usize, HidError> {
+        self.write(buf)
+    }
+
+    fn read_report(&self, buf: &mut [u8]) -> Result<usize, HidError> {
+        self.read(buf)
     }
 }
 
 /// Returns a Result that either contains `DUCKYPAD_TO_PC_HID_BUF_SIZE` bytes
 /// (u8) read from the conencted duckypad or a `HidError` indicating something
-/// went wrong.
+/// went wrong. `Ok(None)` is returned if the duckypad didn't reply in time.
 ///
 /// # Arguments
 ///
 /// * `device` - connected duckypad hid device
+/// * `timeout` - how long to wait for a reply (e.g. `INFO_TIMEOUT`)
 ///
 /// # Errors
 ///
 /// Will return `HidError` if reading from the duckypad `HidDevice` fails.
-pub fn read(device: &HidDevice) -> Result<Option<[u8; DUCKYPAD_TO_PC_HID_BUF_SIZE]>, HidError> {
+pub fn read<D: Reports + ?Sized>(
+    device: &D,
+    timeout: Timeout,
+) -> Result<Option<[u8; DUCKYPAD_TO_PC_HID_BUF_SIZE]>, HidError> {
     let timer = std::time::Instant::now();
 
-    while timer.elapsed() <= std::time::Duration::from_secs(5) {
+    while timer.elapsed() <= timeout.total {
         let mut buf = [0x00; DUCKYPAD_TO_PC_HID_BUF_SIZE];
-        let res = device.read(&mut buf[..])?;
+        let res = device.read_report(&mut buf[..])?;
 
         if res > 0 {
             return Ok(Some(buf));
         }
 
-        std::thread::sleep(std::time::Duration::from_millis(10));
+        std::thread::sleep(timeout.poll);
     }
 
     Ok(None)
 }
 
-/// Writes to the duckypad and returns a reply (see `read`).
+/// Writes to the duckypad and returns a reply (see `read`). A failed write is
+/// retried up to `WRITE_ATTEMPTS` times in total, so a momentary USB hiccup
+/// doesn't abort e.g. a profile switch, unless the error means that the
+/// duckypad is gone (see `is_disconnect`).
 ///
 /// # Arguments
 ///
 /// * `device` - connected duckypad hid device
 /// * `buf` - `PC_TO_DUCKYPAD_HID_BUF_SIZE` bytes (u8) to write to `device`
+/// * `timeout` - how long to wait for a reply (see `read`)
 ///
 /// # Errors
 ///
-/// Will return `HidError` if writing to or the follow-up reading from the
-/// duckypad `HidDevice` fails.
-pub fn write(
-    device: &HidDevice,
+/// Will return `HidError` if writing to the duckypad `HidDevice` fails on
+/// every attempt (or with a disconnect) or if the follow-up reading fails.
+pub fn write<D: Reports + ?Sized>(
+    device: &D,
     buf: [u8; PC_TO_DUCKYPAD_HID_BUF_SIZE],
+    timeout: Timeout,
 ) -> Result<Option<[u8; DUCKYPAD_TO_PC_HID_BUF_SIZE]>, HidError> {
-    device.write(&buf)?;
-    read(device)
+    let mut attempt = 1;
+
+    while let Err(err) = device.write_report(&buf) {
+        if attempt >= WRITE_ATTEMPTS || is_disconnect(&err) {
+            return Err(err);
+        }
+
+        tracing::warn!(
+            "Writing to the duckyPad failed, retrying ({attempt}/{WRITE_ATTEMPTS}): {err}"
+        );
+        std::thread::sleep(WRITE_RETRY_DELAY);
+        attempt += 1;
+    }
+
+    read(device, timeout)
+}
+
+/// Returns whether `err` means that the duckypad is gone (e.g. unplugged), so
+/// retrying is pointless and reconnecting has to take over. Other errors
+/// might be transient, e.g. a timeout.
+pub fn is_disconnect(err: &HidError) -> bool {
+    /// `ENODEV`, which hidraw returns for unplugged devices
+    const NO_SUCH_DEVICE: i32 = 19;
+
+    match err {
+        HidError::IoError { error } => {
+            matches!(
+                error.kind(),
+                std::io::ErrorKind::NotFound
+                    | std::io::ErrorKind::NotConnected
+                    | std::io::ErrorKind::BrokenPipe
+            ) || error.raw_os_error() == Some(NO_SUCH_DEVICE)
+        }
+        // the C library only reports the error as text
+        HidError::HidApiError { message } => {
+            let message = message.to_lowercase();
+
+            [
+                "no such device",
+                "not connected",
+                "broken pipe",
+                "disconnected",
+            ]
+            .iter()
+            .any(|text| message.contains(text))
+        }
+        _ => false,
+    }
+}
+
+/// A duckypad for tests, which records the frames it would have sent and
+/// answers with scripted replies.
+#[cfg(test)]
+pub(crate) struct FakeTransport {
+    /// frames sent to the duckypad, in order
+    pub frames: std::cell::RefCell<Vec<[u8; PC_TO_DUCKYPAD_HID_BUF_SIZE]>>,
+    /// statuses of the replies to the next commands, `Status::Ok` once they
+    /// run out
+    pub replies: std::cell::RefCell<std::collections::VecDeque<Result<Status, HidError>>>,
+    /// replies to the next info requests, the profile last switched to once
+    /// they run out
+    pub profile_replies: std::cell::RefCell<std::collections::VecDeque<Result<u8, HidError>>>,
+    /// profile the duckypad is on
+    pub profile: std::cell::Cell<u8>,
+    pub max_profiles: u32,
+    pub version: Option<Version>,
+}
+
+#[cfg(test)]
+impl FakeTransport {
+    /// Returns a duckypad on profile 1 with the profiles of the original model
+    /// and a firmware that supports every command.
+    pub fn new() -> Self {
+        Self {
+            frames: std::cell::RefCell::default(),
+            replies: std::cell::RefCell::default(),
+            profile_replies: std::cell::RefCell::default(),
+            profile: std::cell::Cell::new(1),
+            max_profiles: MODELS[0].max_profiles,
+            version: None,
+        }
+    }
+
+    /// Queues the status of the reply to the next command.
+    pub fn reply(self, status: Result<Status, HidError>) -> Self {
+        self.replies.borrow_mut().push_back(status);
+        self
+    }
+
+    /// Queues the reply to the next info request.
+    pub fn profile_reply(self, profile: Result<u8, HidError>) -> Self {
+        self.profile_replies.borrow_mut().push_back(profile);
+        self
+    }
+
+    /// Returns the frames sent to the duckypad.
+    pub fn sent(&self) -> Vec<[u8; PC_TO_DUCKYPAD_HID_BUF_SIZE]> {
+        self.frames.borrow().clone()
+    }
+
+    fn command(&self, frame: [u8; PC_TO_DUCKYPAD_HID_BUF_SIZE]) -> Result<Status, HidError> {
+        self.frames.borrow_mut().push(frame);
+        self.replies
+            .borrow_mut()
+            .pop_front()
+            .unwrap_or(Ok(Status::Ok))
+    }
+}
+
+#[cfg(test)]
+impl DuckyPadTransport for FakeTransport {
+    fn write_profile(&self, profile: u32) -> Result<Status, HidError> {
+        let profile = u8::try_from(profile).expect("profile doesn't fit into a frame");
+        let status = self.command(goto_profile_frame(profile))?;
+        if status == Status::Ok {
+            self.profile.set(profile);
+        }
+        Ok(status)
+    }
+
+    fn write_sleep(&self) -> Result<Status, HidError> {
+        self.command(frame(SLEEP_COMMAND, &[]))
+    }
+
+    fn current_profile(&self) -> Result<u8, HidError> {
+        self.frames.borrow_mut().push(frame(INFO_COMMAND, &[]));
+        self.profile_replies
+            .borrow_mut()
+            .pop_front()
+            .unwrap_or(Ok(self.profile.get()))
+    }
+
+    fn max_profiles(&self) -> u32 {
+        self.max_profiles
+    }
+
+    fn supports(&self, feature: Feature) -> bool {
+        supports(self.version, feature)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{cell::RefCell, collections::VecDeque};
+
+    /// A device whose reports are answered by `respond`. Its replies can be
+    /// read right away.
+    struct StubDevice<F> {
+        respond: F,
+        /// reports written to the device, in order, including failed writes
+        written: RefCell<Vec<Vec<u8>>>,
+        replies: RefCell<VecDeque<[u8; DUCKYPAD_TO_PC_HID_BUF_SIZE]>>,
+        /// errors of the next writes
+        write_errors: RefCell<VecDeque<HidError>>,
+    }
+
+    impl<F: Fn(&[u8]) -> Vec<[u8; DUCKYPAD_TO_PC_HID_BUF_SIZE]>> StubDevice<F> {
+        fn new(respond: F) -> Self {
+            Self {
+                respond,
+                written: RefCell::new(Vec::new()),
+                replies: RefCell::new(VecDeque::new()),
+                write_errors: RefCell::new(VecDeque::new()),
+            }
+        }
+
+        /// Lets the next writes fail with the errors with `messages`.
+        fn failing(self, messages: &[&str]) -> Self {
+            self.write_errors
+                .borrow_mut()
+                .extend(messages.iter().map(|message| HidError::HidApiError {
+                    message: (*message).to_string(),
+                }));
+            self
+        }
+    }
+
+    impl<F: Fn(&[u8]) -> Vec<[u8; DUCKYPAD_TO_PC_HID_BUF_SIZE]>> Reports for StubDevice<F> {
+        fn write_report(&self, buf: &[u8]) -> Result<usize, HidError> {
+            self.written.borrow_mut().push(buf.to_vec());
+            if let Some(err) = self.write_errors.borrow_mut().pop_front() {
+                return Err(err);
+            }
+            self.replies.borrow_mut().extend((self.respond)(buf));
+            Ok(buf.len())
+        }
+
+        fn read_report(&self, buf: &mut [u8]) -> Result<usize, HidError> {
+            match self.replies.borrow_mut().pop_front() {
+                Some(reply) => {
+                    buf.copy_from_slice(&reply);
+                    Ok(reply.len())
+                }
+                None => Ok(0),
+            }
+        }
+    }
+
+    /// A timeout that would make the tests noticeably slow if it was waited for.
+    const SLOW: Timeout = Timeout {
+        total: Duration::from_secs(5),
+        poll: Duration::from_secs(1),
+    };
+
+    /// Returns a reply of the duckypad to the request `sequence` with `status`
+    /// and the current `profile` in byte 11.
+    fn info_reply(sequence: u8, status: u8, profile: u8) -> [u8; DUCKYPAD_TO_PC_HID_BUF_SIZE] {
+        let mut reply = [0; DUCKYPAD_TO_PC_HID_BUF_SIZE];
+        reply[..3].copy_from_slice(&[REPLY_REPORT_ID, sequence, status]);
+        reply[11] = profile;
+        reply
+    }
+
+    fn version_reply(
+        sequence: u8,
+        status: u8,
+        version: Version,
+    ) -> [u8; DUCKYPAD_TO_PC_HID_BUF_SIZE] {
+        let mut reply = info_reply(sequence, status, 1);
+        reply[3..6].copy_from_slice(&[version.0, version.1, version.2]);
+        reply
+    }
+
+    #[test]
+    fn parse_version_reads_three_numbers() {
+        assert_eq!(parse_version("1.2.3"), Some((1, 2, 3)));
+        assert_eq!(parse_version(" 0.20.1\n"), Some((0, 20, 1)));
+        assert_eq!(format_version((0, 20, 1)), "0.20.1");
+    }
+
+    #[test]
+    fn parse_version_refuses_other_versions() {
+        for firmware in [
+            "unknown", "", "1.2", "1.2.3.4", "1.256.0", "1.-2.3", "v1.2.3",
+        ] {
+            assert_eq!(parse_version(firmware), None, "{firmware:?}");
+        }
+    }
+
+    #[test]
+    fn at_least_compares_versions() {
+        assert!(at_least(Some((1, 2, 3)), (1, 2, 3)));
+        // numerically, not as text
+        assert!(at_least(Some((1, 10, 0)), (1, 9, 5)));
+        assert!(!at_least(Some((0, 20, 1)), (1, 0, 0)));
+        assert!(!at_least(Some((1, 2, 2)), (1, 2, 3)));
+        assert!(at_least(None, (1, 0, 0)));
+    }
+
+    #[test]
+    fn features_need_their_firmware() {
+        assert!(!supports(Some((0, 12, 9)), Feature::ReadProfile));
+        assert!(supports(Some((0, 13, 0)), Feature::ReadProfile));
+        assert!(!supports(Some((0, 19, 0)), Feature::Sleep));
+        assert!(supports(Some((0, 20, 0)), Feature::Sleep));
+        // an unknown firmware is tried anyway
+        assert!(supports(None, Feature::Sleep));
+    }
+
+    #[test]
+    fn reply_profile_reads_byte_11() {
+        assert_eq!(reply_profile(&info_reply(3, 0x00, 7), 3), Some(7));
+    }
+
+    #[test]
+    fn reply_profile_checks_the_reply() {
+        // a late reply to an earlier request
+        assert_eq!(reply_profile(&info_reply(2, 0x00, 7), 3), None);
+        // busy
+        assert_eq!(reply_profile(&info_reply(3, 0x02, 7), 3), None);
+
+        let mut reply = info_reply(3, 0x00, 7);
+        reply[0] = REPORT_ID;
+        assert_eq!(reply_profile(&reply, 3), None);
+    }
+
+    #[test]
+    fn is_disconnect_only_matches_lost_devices() {
+        let error = |message: &str| HidError::HidApiError {
+            message: message.to_string(),
+        };
+
+        assert!(is_disconnect(&error("hid_write: No such device")));
+        assert!(is_disconnect(&HidError::IoError {
+            error: std::io::Error::from_raw_os_error(19),
+        }));
+        assert!(!is_disconnect(&error(
+            "duckyPad didn't reply to info request"
+        )));
+        assert!(!is_disconnect(&error(
+            "duckyPad refused the info request (busy)"
+        )));
+    }
+
+    #[test]
+    fn read_returns_replies_right_away() {
+        let device = StubDevice::new(|_: &[u8]| vec![info_reply(1, 0x00, 4)]);
+        let start = Instant::now();
+
+        let reply = write(&device, frame(INFO_COMMAND, &[]), SLOW).unwrap();
+        assert_eq!(reply, Some(info_reply(1, 0x00, 4)));
+        assert!(start.elapsed() < SLOW.poll);
+        assert_eq!(device.written.borrow().len(), 1);
+    }
+
+    #[test]
+    fn read_gives_up_after_the_timeout() {
+        let device = StubDevice::new(|_: &[u8]| Vec::new());
+        let timeout = Timeout {
+            total: Duration::from_millis(20),
+            poll: Duration::from_millis(1),
+        };
+        let start = Instant::now();
+
+        assert_eq!(read(&device, timeout).unwrap(), None);
+        assert!(start.elapsed() >= timeout.total);
+    }
+
+    #[test]
+    fn read_current_profile_answers_right_away() {
+        let device = StubDevice::new(|buf: &[u8]| vec![info_reply(buf[1], 0x00, 6)]);
+        let start = Instant::now();
+
+        assert_eq!(read_current_profile(&device).unwrap(), 6);
+        assert!(start.elapsed() < PROFILE_TIMEOUT.total);
+    }
+
+    #[test]
+    fn find_model_detects_each_model() {
+        for model in &MODELS {
+            // interfaces of other devices and the keyboard interface of the duckypad
+            let devices = [
+                (0x046d, 0xc52b, model.usage_page, model.usage),
+                (model.vendor_id, 0xffff, model.usage_page, model.usage),
+                (model.vendor_id, model.product_id, 0x0001, 0x0006),
+                (
+                    model.vendor_id,
+                    model.product_id,
+                    model.usage_page,
+                    model.usage,
+                ),
+            ];
+            let found: Vec<_> = devices
+                .iter()
+                .filter_map(|&(vendor_id, product_id, usage_page, usage)| {
+                    find_model(vendor_id, product_id, usage_page, usage)
+                })
+                .map(|found| found.name)
+                .collect();
+
+            let expected = if cfg!(target_os = "macos") { 2 } else { 1 };
+            assert_eq!(found, vec![model.name; expected]);
+        }
+    }
+
+    #[test]
+    fn models_have_distinct_product_ids() {
+        for (i, model) in MODELS.iter().enumerate() {
+            assert!(MODELS[i + 1..]
+                .iter()
+                .all(|other| (other.vendor_id, other.product_id)
+                    != (model.vendor_id, model.product_id)));
+            assert!(model.max_profiles <= MAX_PROFILES);
+        }
+    }
+
+    #[test]
+    fn write_retries_transient_errors() {
+        let device = StubDevice::new(|_: &[u8]| vec![info_reply(0, 0x00, 2)])
+            .failing(&["hid_write: Connection timed out"]);
+
+        let reply = write(&device, goto_profile_frame(2), PROFILE_TIMEOUT).unwrap();
+        assert_eq!(reply, Some(info_reply(0, 0x00, 2)));
+        assert_eq!(
+            *device.written.borrow(),
+            vec![goto_profile_frame(2).to_vec(); 2]
+        );
+    }
+
+    #[test]
+    fn write_gives_up_after_the_last_attempt() {
+        let device = StubDevice::new(|_: &[u8]| Vec::new()).failing(&["timed out"; 3]);
+
+        assert!(write(&device, goto_profile_frame(2), PROFILE_TIMEOUT).is_err());
+        assert_eq!(device.written.borrow().len(), 3);
+    }
+
+    #[test]
+    fn write_does_not_retry_disconnects() {
+        let device = StubDevice::new(|_: &[u8]| Vec::new()).failing(&["hid_write: No such device"]);
+
+        let err = write(&device, goto_profile_frame(2), PROFILE_TIMEOUT).unwrap_err();
+        assert!(is_disconnect(&err));
+        assert_eq!(device.written.borrow().len(), 1);
+    }
+
+    #[test]
+    fn goto_profile_frame_carries_the_profile() {
+        for profile in [1, 10, 31, u8::MAX] {
+            let frame = goto_profile_frame(profile);
+            assert_eq!(frame.len(), PC_TO_DUCKYPAD_HID_BUF_SIZE);
+            assert_eq!(frame[..4], [REPORT_ID, 0x00, GOTO_PROFILE_COMMAND, profile]);
+            assert!(frame[4..].iter().all(|byte| *byte == 0));
+        }
+    }
+
+    #[test]
+    fn reply_version_reads_bytes_3_to_5() {
+        assert_eq!(
+            reply_version(&version_reply(7, 0x00, (1, 2, 0)), 7),
+            Some((1, 2, 0))
+        );
+    }
+
+    #[test]
+    fn reply_version_checks_the_reply() {
+        // a late reply to an earlier request
+        assert_eq!(reply_version(&version_reply(6, 0x00, (9, 9, 9)), 7), None);
+        // busy
+        assert_eq!(reply_version(&version_reply(7, 0x02, (1, 2, 0)), 7), None);
+
+        let mut reply = version_reply(7, 0x00, (1, 2, 0));
+        reply[0] = REPORT_ID;
+        assert_eq!(reply_version(&reply, 7), None);
+    }
+
+    #[test]
+    fn read_version_skips_late_replies() {
+        let device = StubDevice::new(|buf: &[u8]| {
+            vec![
+                version_reply(buf[1].wrapping_sub(1), 0x00, (9, 9, 9)),
+                version_reply(buf[1], 0x00, (1, 2, 0)),
+            ]
+        });
+
+        assert_eq!(read_version(&device), Some((1, 2, 0)));
+        assert_eq!(device.written.borrow().len(), 1);
+    }
+
+    /// Returns a reply to the list files request `sequence` that lists the
+    /// directory (or file) `name`.
+    fn entry_reply(sequence: u8, is_dir: bool, name: &str) -> [u8; DUCKYPAD_TO_PC_HID_BUF_SIZE] {
+        let mut reply = info_reply(sequence, 0x00, 0);
+        reply[3] = u8::from(is_dir);
+        reply[4..4 + name.len()].copy_from_slice(name.as_bytes());
+        reply
+    }
+
+    #[test]
+    fn profile_folders_are_parsed() {
+        assert_eq!(
+            parse_profile_folder("profile3_Firefox"),
+            Some((3, "Firefox"))
+        );
+        assert_eq!(
+            parse_profile_folder("profile12_My_Game"),
+            Some((12, "My_Game"))
+        );
+
+        for folder in [
+            "profile3",
+            "profile3_",
+            "profile_Firefox",
+            "profile+3_Firefox",
+            "keymaps",
+        ] {
+            assert_eq!(parse_profile_folder(folder), None, "{folder:?}");
+        }
+    }
+
+    #[test]
+    fn profile_names_are_read_from_the_folders() {
+        let device = StubDevice::new(|buf: &[u8]| {
+            let sequence = buf[1];
+            vec![
+                info_reply(sequence.wrapping_sub(1), 0x00, 2),
+                entry_reply(sequence, true, "profile1_Welcome"),
+                entry_reply(sequence, false, "profile2_Notes.txt"),
+                entry_reply(sequence, true, "keymaps"),
+                entry_reply(sequence, true, "profile4_Firefox"),
+                entry_reply(sequence, true, "profile3_Firefox"),
+                info_reply(sequence, 0x03, 0),
+            ]
+        });
+
+        let names = read_profile_names(&device).unwrap();
+
+        assert_eq!(device.written.borrow()[0][2], LIST_FILES_COMMAND);
+        assert_eq!(
+            names,
+            HashMap::from([("Welcome".to_string(), 1), ("Firefox".to_string(), 3)])
+        );
+    }
+
+    #[test]
+    fn refused_listings_fail() {
+        let device = StubDevice::new(|buf: &[u8]| vec![info_reply(buf[1], 0x01, 0)]);
+
+        let err = read_profile_names(&device).unwrap_err().to_string();
+
+        assert!(
+            err.contains("refused the list files request (error)"),
+            "{err}"
+        );
+    }
 }
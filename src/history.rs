@@ -0,0 +1,87 @@
+use crate::SwitchEvent;
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    path::PathBuf,
+};
+
+/// Appends a JSON line for every profile switch to a history file, so the
+/// switches can be analysed later. Unlike the log output, the format is meant
+/// to be machine-readable:
+/// ```json
+/// {"app_name":"firefox","profile":3,"rule":0,"time":"2024-01-01T12:00:00+01:00","title":"..."}
+/// ```
+/// `rule` is the index of the matching rule in the config file or `null` if
+/// the `default_profile` was used.
+pub struct History {
+    path: PathBuf,
+    file: File,
+    max_size: Option<u64>,
+}
+
+impl History {
+    /// Opens the history file at `path` for appending. If `max_size` is set,
+    /// the file gets rotated to `<path>.1` (replacing an older one) once it is
+    /// larger than `max_size` bytes.
+    ///
+    /// # Errors
+    ///
+    /// Will return `std::io::Error` if the file cannot be opened.
+    pub fn open(path: PathBuf, max_size: Option<u64>) -> std::io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+
+        Ok(Self {
+            path,
+            file,
+            max_size,
+        })
+    }
+
+    /// Appends `event` to the history file. Errors are logged, as a broken
+    /// history file shouldn't stop the daemon from switching profiles.
+    pub fn record(&mut self, event: &SwitchEvent) {
+        if let Err(err) = self.rotate() {
+            tracing::warn!(
+                "Failed to rotate history file {}: {err}",
+                self.path.display()
+            );
+        }
+
+        let line = serde_json::json!({
+            "time": chrono::Local::now().to_rfc3339(),
+            "profile": event.profile,
+            "app_name": event.window.app_name,
+            "title": event.window.title,
+            "rule": event.rule,
+        });
+
+        if let Err(err) = writeln!(self.file, "{line}").and_then(|()| self.file.flush()) {
+            tracing::warn!(
+                "Failed to write history file {}: {err}",
+                self.path.display()
+            );
+        }
+    }
+
+    /// Moves the history file to `<path>.1` and starts a new one if it is
+    /// larger than `max_size`.
+    fn rotate(&mut self) -> std::io::Result<()> {
+        let Some(max_size) = self.max_size else {
+            return Ok(());
+        };
+
+        if self.file.metadata()?.len() < max_size {
+            return Ok(());
+        }
+
+        let mut rotated = self.path.clone().into_os_string();
+        rotated.push(".1");
+        std::fs::rename(&self.path, rotated)?;
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+
+        Ok(())
+    }
+}
@@ -1,82 +1,1301 @@
 #![warn(clippy::pedantic)]
 #![allow(clippy::must_use_candidate)]
 
+#[cfg(all(unix, feature = "control-socket"))]
+pub mod control;
+pub mod daemon;
+#[cfg(all(unix, feature = "dbus"))]
+pub mod dbus;
 pub mod hid;
+pub mod history;
+pub mod lock;
+pub mod metrics;
+pub mod notification;
+pub mod provider;
+pub mod schedule;
+pub mod screen_lock;
+pub mod status;
+#[cfg(all(unix, feature = "systemd"))]
+pub mod systemd;
+pub mod uwp;
+pub mod watch;
+pub mod window_script;
+pub mod x11;
 
-use active_win_pos_rs::{get_active_window, ActiveWindow, WindowPosition};
-use hidapi::HidApi;
+use active_win_pos_rs::{ActiveWindow, WindowPosition};
+use hid::DuckyPadTransport;
+use hidapi::HidError;
+use provider::{WindowError, WindowProvider};
+use regex::{Regex, RegexBuilder};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::{
+    collections::HashMap,
     fs::File,
     io::prelude::Write,
-    path::PathBuf,
+    path::{Path, PathBuf},
     process::{Command, Stdio},
+    time::{Duration, Instant},
 };
-use sysinfo::{Pid, ProcessExt, ProcessRefreshKind, System, SystemExt};
+use sysinfo::{Pid, ProcessExt, ProcessRefreshKind, RefreshKind, System, SystemExt};
+use unicode_normalization::UnicodeNormalization;
 
 pub mod enums {
-    pub enum LinuxServer {
-        WAYLAND(std::path::PathBuf),
-        XORG,
+    /// How the patterns of a rule are compared to the active window.
+    #[derive(serde::Serialize, serde::Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+    #[serde(rename_all = "snake_case")]
+    pub enum MatchType {
+        /// The pattern is contained in the value
+        #[default]
+        Contains,
+        /// The pattern is equal to the value
+        Exact,
+        /// The value begins with the pattern
+        StartsWith,
+        /// The value ends with the pattern, e.g. `.exe`
+        EndsWith,
+        /// The pattern is a regular expression matching the value
+        Regex,
     }
 
-    pub enum OSIdent {
-        MACOS,
-        WINDOWS,
-        LINUX(LinuxServer),
-        UNSUPPORTED(std::path::PathBuf),
+    /// Target profile of a rule, either its id or a name from the config's
+    /// `profile_names`.
+    #[derive(serde::Serialize, serde::Deserialize, Clone)]
+    #[serde(untagged)]
+    pub enum SwitchTo {
+        Profile(u32),
+        Name(String),
+    }
+
+    /// Patterns of a rule, either a single one or a list of which any has to
+    /// match.
+    #[derive(serde::Serialize, serde::Deserialize, Clone)]
+    #[serde(untagged)]
+    pub enum Patterns {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    impl Default for Patterns {
+        fn default() -> Self {
+            Self::One(String::new())
+        }
+    }
+
+    impl Patterns {
+        /// Returns the patterns in the order of the config file.
+        pub fn iter(&self) -> impl Iterator<Item = &str> {
+            let patterns = match self {
+                Self::One(pattern) => std::slice::from_ref(pattern),
+                Self::Many(patterns) => patterns.as_slice(),
+            };
+
+            patterns.iter().map(String::as_str)
+        }
+    }
+}
+
+/// Range of valid profile ids on any duckypad model. Whether the connected
+/// duckypad has a profile is checked when switching to it (see
+/// `goto_profile`).
+pub const PROFILES: std::ops::RangeInclusive<u32> = 1..=hid::MAX_PROFILES;
+
+/// Normalizes a window title to NFC before it is matched, so an app that
+/// reports decomposed characters (e.g. `e` followed by a combining accent)
+/// matches the same rules as one reporting the precomposed character. With
+/// `max_length`, the title is also cut off after that many characters.
+pub fn normalize_title(title: &str, max_length: Option<usize>) -> String {
+    let title = title.nfc();
+
+    match max_length {
+        Some(max_length) => title.take(max_length).collect(),
+        None => title.collect(),
+    }
+}
+
+/// Pseudo profile id of a duckypad that a rule with `switch_to: "sleep"`
+/// (`Target::Sleep`) put to sleep instead of switching its profile, e.g. in
+/// `SwitchEvent`. It is outside of `PROFILES`, so it can't be confused with
+/// an actual profile.
+pub const SLEEP: u32 = 0;
+
+/// What a rule switches to: the profile of its `switch_to` or what one of the
+/// keywords `"sleep"`, `"next"`, `"previous"` and `"toggle"` stands for. Only
+/// the keywords result in the targets other than `Profile`, so no profile id
+/// is mistaken for one of them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Target {
+    /// The profile with this id (see `PROFILES`)
+    Profile(u32),
+    /// `"sleep"`: put the duckypad to sleep instead of switching its profile
+    Sleep,
+    /// `"next"`: the profile after the current one (see `relative_profile`)
+    Next,
+    /// `"previous"`: the profile before the current one
+    Previous,
+    /// `"toggle"`: the profile the duckypad was on before the current one
+    Toggle,
+}
+
+impl Target {
+    /// Returns the target the `switch_to` keyword `name` stands for, or `None`
+    /// if it isn't a keyword.
+    pub fn keyword(name: &str) -> Option<Self> {
+        match name {
+            "sleep" => Some(Self::Sleep),
+            "next" => Some(Self::Next),
+            "previous" => Some(Self::Previous),
+            "toggle" => Some(Self::Toggle),
+            _ => None,
+        }
+    }
+
+    /// Returns whether the target is relative to the current profile (`Next`,
+    /// `Previous` and `Toggle`), so it is resolved when switching.
+    pub fn is_relative(self) -> bool {
+        matches!(self, Self::Next | Self::Previous | Self::Toggle)
+    }
+}
+
+/// Describes switching to the target, for log messages.
+impl std::fmt::Display for Target {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Profile(profile) => write!(f, "switching to profile {profile}"),
+            Self::Sleep => write!(f, "putting the duckyPad to sleep"),
+            Self::Next => write!(f, "switching to the next profile"),
+            Self::Previous => write!(f, "switching to the previous profile"),
+            Self::Toggle => write!(f, "switching back to the profile before the current one"),
+        }
+    }
+}
+
+/// Resolves `target` against the `current` profile of a duckypad with
+/// `max_profiles` profiles. `Next` and `Previous` wrap around at the first
+/// and last profile, `Toggle` returns the profile `before` the current one.
+/// Returns `None` for `Sleep`, if there is nothing to toggle back to or if
+/// the result isn't within `1..=max_profiles`.
+pub fn relative_profile(
+    target: Target,
+    current: u32,
+    before: Option<u32>,
+    max_profiles: u32,
+) -> Option<u32> {
+    let profile = match target {
+        Target::Next if current >= max_profiles => 1,
+        Target::Next => current + 1,
+        Target::Previous if current <= 1 => max_profiles,
+        Target::Previous => (current - 1).min(max_profiles),
+        Target::Toggle => before?,
+        Target::Profile(profile) => profile,
+        Target::Sleep => return None,
+    };
+
+    (1..=max_profiles).contains(&profile).then_some(profile)
+}
+
+/// Reasons why a `--window-script` couldn't provide active window information.
+#[derive(Debug)]
+pub enum WindowScriptError {
+    /// The script couldn't be run (path of the script, error)
+    Spawn(PathBuf, std::io::Error),
+    /// The script exited unsuccessfully (exit status, stderr of the script)
+    Failed(std::process::ExitStatus, String),
+    /// The output of the script isn't valid utf8
+    NonUtf8(std::string::FromUtf8Error),
+    /// The output of the script isn't valid JSON
+    Json(serde_json::Error),
+    /// A required field is missing from the output
+    MissingField(&'static str),
+    /// A field of the output has the wrong type (field name, expected type)
+    InvalidField(&'static str, &'static str),
+    /// A streaming script hasn't printed an active window yet
+    NoOutput,
+}
+
+impl std::fmt::Display for WindowScriptError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Spawn(script, err) => write!(
+                f,
+                "Couldn't run window script {}: {err}{}",
+                script.display(),
+                spawn_hint(err)
+            ),
+            Self::Failed(status, stderr) if stderr.is_empty() => {
+                write!(f, "Window script failed ({status})")
+            }
+            Self::Failed(status, stderr) => write!(f, "Window script failed ({status}): {stderr}"),
+            Self::NonUtf8(err) => write!(f, "Window script output needs to be valid utf8: {err}"),
+            Self::Json(err) => write!(f, "Window script output needs to be a JSON object: {err}"),
+            Self::MissingField(field) => {
+                write!(f, "Window script output field \"{field}\" is missing!")
+            }
+            Self::InvalidField(field, expected) => {
+                write!(
+                    f,
+                    "Window script output field \"{field}\" needs to be {expected}!"
+                )
+            }
+            Self::NoOutput => write!(f, "Window script hasn't reported an active window yet"),
+        }
+    }
+}
+
+impl std::error::Error for WindowScriptError {}
+
+/// Returns a hint on how to fix the usual reasons for a script failing to
+/// spawn with `err`, or an empty string.
+fn spawn_hint(err: &std::io::Error) -> &'static str {
+    /// `ENOEXEC`, returned for scripts without a shebang
+    #[cfg(unix)]
+    const EXEC_FORMAT_ERROR: i32 = 8;
+
+    match err.kind() {
+        std::io::ErrorKind::NotFound => {
+            " (check that it exists and that the interpreter in its shebang is installed)"
+        }
+        std::io::ErrorKind::PermissionDenied => " (check that it is executable, e.g. chmod +x)",
+        #[cfg(unix)]
+        _ if err.raw_os_error() == Some(EXEC_FORMAT_ERROR) => {
+            " (scripts need a shebang like #!/bin/sh)"
+        }
+        _ => "",
+    }
+}
+
+/// Reasons why the config file couldn't be loaded.
+#[derive(Debug)]
+pub enum ConfigError {
+    /// The config file couldn't be read
+    Io(std::io::Error),
+    /// The config file couldn't be parsed and deserialized (includes the line
+    /// and column of the error)
+    Parse(serde_json::Error),
+    /// The config file isn't valid JSON and couldn't be parsed as JSON5
+    /// either (includes the line and column of the error)
+    Json5(json5::Error),
+    /// A field references an environment variable that isn't set (location
+    /// in the config, variable)
+    EnvVar(String, String),
+    /// The TOML config file couldn't be parsed and deserialized (includes the
+    /// location of the error)
+    Toml(toml::de::Error),
+    /// A profile id is outside of `PROFILES` (location in the config, profile)
+    Profile(String, u32),
+    /// A rule references a profile name that isn't defined in
+    /// `profile_names` (location in the config, name)
+    UnknownProfileName(String, String),
+    /// `profile_names` defines a name that is a keyword of `switch_to`, e.g.
+    /// `next` (name)
+    ReservedProfileName(String),
+    /// A rule references a group that isn't defined in `groups` (location in
+    /// the config, name)
+    UnknownGroup(String, String),
+    /// The config directory has no config with this name (name, directory)
+    UnknownConfig(String, PathBuf),
+    /// A named config was requested, but the daemon has no config directory
+    NoConfigDir,
+    /// A file listed in `include` couldn't be loaded (file, error)
+    Include(PathBuf, Box<ConfigError>),
+    /// Config files include each other (the files of the cycle, starting and
+    /// ending with the same file)
+    IncludeCycle(Vec<PathBuf>),
+    /// A rule has an `active_time` that can't be parsed (rule, `active_time`)
+    ActiveTime(usize, String),
+    /// Rules or the `default_profile` switch to profiles the connected
+    /// duckypad doesn't have (locations in the config, highest profile of the
+    /// duckypad)
+    MissingProfiles(Vec<String>, u32),
+    /// The config has warnings (see `Config::warnings`) and the caller
+    /// treats them as errors
+    Warnings(Vec<String>),
+    /// A rule contains an invalid regular expression
+    Regex {
+        location: String,
+        pattern: String,
+        error: regex::Error,
+    },
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "Error reading config file:\n{err}"),
+            Self::Parse(err) => write!(
+                f,
+                "Error parsing and deserialize config file at line {}, column {}:\n{err}",
+                err.line(),
+                err.column()
+            ),
+            Self::Json5(err) => write!(f, "Error parsing and deserialize config file as JSON5:\n{err}"),
+            Self::EnvVar(location, name) => write!(
+                f,
+                "Unknown environment variable ${{{name}}} in {location}: it isn't set"
+            ),
+            Self::Toml(err) => write!(f, "Error parsing and deserialize config file:\n{err}"),
+            Self::Profile(location, profile) => write!(
+                f,
+                "Invalid profile {profile} in {location}: profiles need to be within 1..={}",
+                hid::MAX_PROFILES
+            ),
+            Self::UnknownProfileName(location, name) => write!(
+                f,
+                "Unknown profile name {name:?} in {location}: no profile on the duckyPad has this name and profile_names doesn't define it"
+            ),
+            Self::ReservedProfileName(name) => write!(
+                f,
+                "Invalid name {name:?} in profile_names: sleep, next, previous and toggle are keywords of switch_to"
+            ),
+            Self::UnknownGroup(location, name) => write!(
+                f,
+                "Unknown app_group {name:?} in {location}: define it in groups first"
+            ),
+            Self::UnknownConfig(name, dir) => write!(
+                f,
+                "No config named {name:?} in {}: expected {name}.json, {name}.json5 or {name}.toml",
+                dir.display()
+            ),
+            Self::NoConfigDir => write!(f, "Named configs require --config-dir"),
+            Self::Include(path, err) => write!(f, "In included file {}: {err}", path.display()),
+            Self::IncludeCycle(files) => write!(
+                f,
+                "Config files include each other: {}",
+                files
+                    .iter()
+                    .map(|file| file.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(" -> ")
+            ),
+            Self::ActiveTime(rule, active_time) => write!(
+                f,
+                "Invalid active_time {active_time:?} in rule {rule}: expected weekdays and/or a time range like \"Mon-Fri 09:00-17:00\""
+            ),
+            Self::MissingProfiles(locations, max_profiles) => write!(
+                f,
+                "The duckyPad only has profiles 1..={max_profiles}, but these switch to other profiles: {}",
+                locations.join(", ")
+            ),
+            Self::Warnings(warnings) => write!(f, "{}", warnings.join("\n")),
+            Self::Regex {
+                location,
+                pattern,
+                error,
+            } => write!(f, "Invalid regex {pattern:?} in {location}:\n{error}"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Patterns that are compared to the active window, used by rules, their
+/// exclusions and the members of groups.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RuleMatcher {
+    #[serde(default)]
+    app_name: enums::Patterns,
+    process_name: Option<enums::Patterns>,
+    #[serde(default, alias = "title")]
+    window_title: enums::Patterns,
+    process_path: Option<String>,
+    /// patterns for the arguments of the process (see `matches_cmdline`)
+    cmdline: Option<enums::Patterns>,
+    url: Option<enums::Patterns>,
+    wm_instance: Option<enums::Patterns>,
+    wm_role: Option<enums::Patterns>,
+    #[serde(default)]
+    match_type: enums::MatchType,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    case_insensitive: Option<bool>,
+    /// `case_insensitive` of the matcher, its rule or the config
+    #[serde(skip)]
+    ignore_case: bool,
+    #[serde(skip)]
+    app_name_regex: Vec<Regex>,
+    #[serde(skip)]
+    window_title_regex: Vec<Regex>,
+    #[serde(skip)]
+    process_name_regex: Vec<Regex>,
+    #[serde(skip)]
+    process_path_regex: Option<Regex>,
+    #[serde(skip)]
+    cmdline_regex: Vec<Regex>,
+    #[serde(skip)]
+    url_regex: Vec<Regex>,
+    #[serde(skip)]
+    wm_instance_regex: Vec<Regex>,
+    #[serde(skip)]
+    wm_role_regex: Vec<Regex>,
+}
+
+impl RuleMatcher {
+    /// Expands environment variables in the `process_path` of the matcher at
+    /// `location` in the config (e.g. `rule 3`, see `expand_env`) and compiles
+    /// its patterns if the `match_type` is `regex`. `ignore_case` is used
+    /// unless the matcher sets `case_insensitive` itself.
+    fn compile(&mut self, location: &str, ignore_case: bool) -> Result<(), ConfigError> {
+        self.ignore_case = self.case_insensitive.unwrap_or(ignore_case);
+
+        if let Some(process_path) = &mut self.process_path {
+            *process_path = expand_env(process_path)
+                .map_err(|name| ConfigError::EnvVar(location.to_string(), name))?;
+        }
+
+        // titles are normalized before matching (see `normalize_title`)
+        self.window_title = match &self.window_title {
+            enums::Patterns::One(pattern) => enums::Patterns::One(pattern.nfc().collect()),
+            enums::Patterns::Many(patterns) => enums::Patterns::Many(
+                patterns
+                    .iter()
+                    .map(|pattern| pattern.nfc().collect())
+                    .collect(),
+            ),
+        };
+
+        if self.match_type == enums::MatchType::Regex {
+            let regex = |pattern: &str| {
+                RegexBuilder::new(pattern)
+                    .case_insensitive(self.ignore_case)
+                    .build()
+                    .map_err(|error| ConfigError::Regex {
+                        location: location.to_string(),
+                        pattern: pattern.to_string(),
+                        error,
+                    })
+            };
+
+            self.app_name_regex = self.app_name.iter().map(regex).collect::<Result<_, _>>()?;
+            self.window_title_regex = self
+                .window_title
+                .iter()
+                .map(regex)
+                .collect::<Result<_, _>>()?;
+            self.process_name_regex = self
+                .process_name
+                .iter()
+                .flat_map(enums::Patterns::iter)
+                .map(regex)
+                .collect::<Result<_, _>>()?;
+            self.process_path_regex = self.process_path.as_deref().map(regex).transpose()?;
+            self.cmdline_regex = self
+                .cmdline
+                .iter()
+                .flat_map(enums::Patterns::iter)
+                .map(regex)
+                .collect::<Result<_, _>>()?;
+            self.url_regex = self
+                .url
+                .iter()
+                .flat_map(enums::Patterns::iter)
+                .map(regex)
+                .collect::<Result<_, _>>()?;
+            self.wm_instance_regex = self
+                .wm_instance
+                .iter()
+                .flat_map(enums::Patterns::iter)
+                .map(regex)
+                .collect::<Result<_, _>>()?;
+            self.wm_role_regex = self
+                .wm_role
+                .iter()
+                .flat_map(enums::Patterns::iter)
+                .map(regex)
+                .collect::<Result<_, _>>()?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns whether the matcher depends on what can change within the same
+    /// window: its title or URL.
+    fn uses_title(&self) -> bool {
+        self.window_title.iter().any(|pattern| !pattern.is_empty())
+            || self
+                .url
+                .as_ref()
+                .is_some_and(|patterns| patterns.iter().any(|pattern| !pattern.is_empty()))
+    }
+
+    /// Returns whether `value` is matched by any of the `patterns` (with their
+    /// compiled `regexes`). An empty list matches everything, like an empty
+    /// pattern.
+    fn matches_any(&self, patterns: &enums::Patterns, regexes: &[Regex], value: &str) -> bool {
+        let mut patterns = patterns.iter().enumerate().peekable();
+
+        patterns.peek().is_none()
+            || patterns.any(|(i, pattern)| self.matches_value(pattern, regexes.get(i), value))
+    }
+
+    /// Returns whether `value` is matched by `pattern` according to the
+    /// `match_type`. Empty patterns match everything.
+    fn matches_value(&self, pattern: &str, regex: Option<&Regex>, value: &str) -> bool {
+        pattern.is_empty()
+            || match self.match_type {
+                enums::MatchType::Contains if self.ignore_case => {
+                    value.to_lowercase().contains(&pattern.to_lowercase())
+                }
+                enums::MatchType::Contains => value.contains(pattern),
+                enums::MatchType::Exact if self.ignore_case => {
+                    value.to_lowercase() == pattern.to_lowercase()
+                }
+                enums::MatchType::Exact => value == pattern,
+                enums::MatchType::StartsWith if self.ignore_case => {
+                    value.to_lowercase().starts_with(&pattern.to_lowercase())
+                }
+                enums::MatchType::StartsWith => value.starts_with(pattern),
+                enums::MatchType::EndsWith if self.ignore_case => {
+                    value.to_lowercase().ends_with(&pattern.to_lowercase())
+                }
+                enums::MatchType::EndsWith => value.ends_with(pattern),
+                enums::MatchType::Regex => regex.is_some_and(|regex| regex.is_match(value)),
+            }
+    }
+
+    /// Returns whether any of the `patterns` matches any argument of the
+    /// command line `cmdline` or the whole command line (its arguments joined
+    /// with spaces), e.g. `server.py` as well as `python server.py`. An empty
+    /// command line (unknown process) only matches empty patterns.
+    fn matches_cmdline(&self, patterns: &enums::Patterns, cmdline: &[String]) -> bool {
+        let joined = cmdline.join(" ");
+
+        std::iter::once(joined.as_str())
+            .chain(cmdline.iter().map(String::as_str))
+            .any(|value| self.matches_any(patterns, &self.cmdline_regex, value))
+    }
+
+    /// Returns whether all patterns match the active `window`. Missing
+    /// optional patterns match everything.
+    fn matches(&self, window: &WindowInfo) -> bool {
+        self.mismatch(window).is_none()
+    }
+
+    /// Returns the key of the first pattern that doesn't match the active
+    /// `window` (e.g. `title`), or `None` if all of them match.
+    fn mismatch(&self, window: &WindowInfo) -> Option<&'static str> {
+        let optional = |patterns: Option<&enums::Patterns>, regexes: &[Regex], value: &str| {
+            patterns.is_none_or(|patterns| self.matches_any(patterns, regexes, value))
+        };
+
+        if !self.matches_any(&self.app_name, &self.app_name_regex, &window.app_name) {
+            return Some("app_name");
+        }
+        if !self.matches_any(&self.window_title, &self.window_title_regex, &window.title) {
+            return Some("title");
+        }
+        if !optional(
+            self.process_name.as_ref(),
+            &self.process_name_regex,
+            &window.process_name,
+        ) {
+            return Some("process_name");
+        }
+        if !self.process_path.as_ref().is_none_or(|process_path| {
+            self.matches_value(
+                process_path,
+                self.process_path_regex.as_ref(),
+                &window.process_path.to_string_lossy(),
+            )
+        }) {
+            return Some("process_path");
+        }
+        if !self
+            .cmdline
+            .as_ref()
+            .is_none_or(|patterns| self.matches_cmdline(patterns, &window.cmdline))
+        {
+            return Some("cmdline");
+        }
+        if !optional(self.url.as_ref(), &self.url_regex, &window.url) {
+            return Some("url");
+        }
+        if !optional(
+            self.wm_instance.as_ref(),
+            &self.wm_instance_regex,
+            &window.wm_instance,
+        ) {
+            return Some("wm_instance");
+        }
+        if !optional(self.wm_role.as_ref(), &self.wm_role_regex, &window.wm_role) {
+            return Some("wm_role");
+        }
+
+        None
+    }
+
+    /// Returns whether this matcher matches at least every window `other`
+    /// matches. This only catches the obvious cases: each of its patterns
+    /// either matches everything or contains all patterns of `other` (with
+    /// the same `match_type` and case sensitivity).
+    fn covers(&self, other: &Self) -> bool {
+        fn optional(patterns: Option<&enums::Patterns>) -> Vec<&str> {
+            patterns
+                .into_iter()
+                .flat_map(enums::Patterns::iter)
+                .collect()
+        }
+
+        let same_mode =
+            self.match_type == other.match_type && self.ignore_case == other.ignore_case;
+        let everything = |patterns: &[&str]| patterns.is_empty() || patterns.contains(&"");
+        let covers = |patterns: Vec<&str>, others: Vec<&str>| {
+            everything(&patterns)
+                || (same_mode
+                    && !everything(&others)
+                    && others.iter().all(|p| patterns.contains(p)))
+        };
+
+        covers(
+            self.app_name.iter().collect(),
+            other.app_name.iter().collect(),
+        ) && covers(
+            self.window_title.iter().collect(),
+            other.window_title.iter().collect(),
+        ) && covers(
+            optional(self.process_name.as_ref()),
+            optional(other.process_name.as_ref()),
+        ) && covers(
+            self.process_path.as_deref().into_iter().collect(),
+            other.process_path.as_deref().into_iter().collect(),
+        ) && covers(
+            optional(self.cmdline.as_ref()),
+            optional(other.cmdline.as_ref()),
+        ) && covers(optional(self.url.as_ref()), optional(other.url.as_ref()))
+            && covers(
+                optional(self.wm_instance.as_ref()),
+                optional(other.wm_instance.as_ref()),
+            )
+            && covers(
+                optional(self.wm_role.as_ref()),
+                optional(other.wm_role.as_ref()),
+            )
     }
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct Rules {
-    app_name: String,
-    process_name: Option<String>,
-    #[serde(alias = "title")]
-    window_title: String,
+    #[serde(flatten)]
+    matcher: RuleMatcher,
     enabled: bool,
-    switch_to: u32,
+    switch_to: enums::SwitchTo,
+    /// name of a group in the config's `groups`, one of whose members has to
+    /// match as well
+    #[serde(skip_serializing_if = "Option::is_none")]
+    app_group: Option<String>,
+    priority: Option<i32>,
+    active_time: Option<String>,
+    idle_after: Option<u64>,
+    monitor: Option<u32>,
+    fullscreen: Option<bool>,
+    /// whether the rule only matches while no window is focused
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    empty_desktop: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    exclude: Vec<RuleMatcher>,
+    /// members of the `app_group`, compiled with the case sensitivity of the
+    /// rule
+    #[serde(skip)]
+    group: Vec<RuleMatcher>,
+    #[serde(skip)]
+    schedule: Option<schedule::ActiveTime>,
+    /// the `switch_to` of the rule, resolved by `Config::prepare`
+    #[serde(skip, default = "unresolved")]
+    target: Target,
+    /// position of the rule in the config file, as rules get sorted by priority
+    #[serde(skip)]
+    index: usize,
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct Config {
     autoswitch_enabled: Option<bool>,
     rules_list: Vec<Rules>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    default_profile: Option<u32>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    profile_names: HashMap<String, u32>,
+    /// families of apps (e.g. all terminals) that rules can match at once via
+    /// `app_group`
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    groups: HashMap<String, Vec<RuleMatcher>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    case_insensitive: Option<bool>,
+    /// number of characters window titles are cut off after before matching
+    /// (see `normalize_title`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_title_length: Option<usize>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    ignore_list: Vec<RuleMatcher>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    include: Vec<PathBuf>,
+    /// paths of all files merged via `include`, in the order of their rules
+    #[serde(skip)]
+    included: Vec<PathBuf>,
+    /// names of the profiles on the duckypad that names in `switch_to` were
+    /// resolved with (see `read_config_with`)
+    #[serde(skip)]
+    device_names: HashMap<String, u32>,
 }
 
-fn create_default_config(path: &PathBuf) {
-    eprintln!("Creating default config, because file doesn't exist");
-    let mut file =
-        File::create(path).unwrap_or_else(|error| panic!("Couldn't create config file:\n{error}"));
+/// A file listed in `include`, which only contains rules and can include
+/// further files.
+#[derive(Deserialize)]
+struct IncludedConfig {
+    #[serde(default)]
+    rules_list: Vec<Rules>,
+    #[serde(default)]
+    include: Vec<PathBuf>,
+}
 
-    file.write_all(
-        serde_json::to_string(&Config {
-            autoswitch_enabled: Some(false),
-            rules_list: vec![],
-        })
-        .expect("Failed to serialize default config.")
-        .as_bytes(),
-    )
-    .expect("Couldn't write to config file!");
+/// The first condition of a rule that failed for the active window.
+enum Mismatch {
+    Disabled,
+    /// the rule only matches while no window is focused
+    EmptyDesktop,
+    /// no window is focused, but the rule isn't an `empty_desktop` rule
+    Focused,
+    ActiveTime,
+    IdleAfter(u64),
+    Monitor(u32),
+    Fullscreen(bool),
+    /// the pattern with this key doesn't match
+    Pattern(&'static str),
+    AppGroup,
+    /// the `exclude` entry with this index matches
+    Excluded(usize),
+}
+
+impl std::fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Disabled => write!(f, "it is disabled"),
+            Self::EmptyDesktop => write!(f, "it only matches while no window is focused"),
+            Self::Focused => write!(f, "no window is focused and it isn't an empty_desktop rule"),
+            Self::ActiveTime => write!(f, "it is outside of its active_time"),
+            Self::IdleAfter(idle_after) => {
+                write!(f, "there was input within the last {idle_after} seconds")
+            }
+            Self::Monitor(monitor) => write!(f, "the window isn't on monitor {monitor}"),
+            Self::Fullscreen(true) => write!(f, "the window isn't fullscreen"),
+            Self::Fullscreen(false) => write!(f, "the window is fullscreen"),
+            Self::Pattern(key) => write!(f, "its {key} doesn't match"),
+            Self::AppGroup => write!(f, "no member of its app_group matches"),
+            Self::Excluded(entry) => write!(f, "its exclude entry {entry} matches"),
+        }
+    }
+}
+
+impl Rules {
+    /// Checks the conditions of the rule for the active `window` at `now` and
+    /// returns the first one that fails.
+    fn check(
+        &self,
+        window: &WindowInfo,
+        desktop: &DesktopState,
+        now: chrono::NaiveDateTime,
+    ) -> Result<(), Mismatch> {
+        if !self.enabled {
+            return Err(Mismatch::Disabled);
+        }
+        match (self.empty_desktop, desktop.empty) {
+            (true, false) => return Err(Mismatch::EmptyDesktop),
+            (false, true) => return Err(Mismatch::Focused),
+            _ => {}
+        }
+        if !self
+            .schedule
+            .as_ref()
+            .is_none_or(|schedule| schedule.contains(now))
+        {
+            return Err(Mismatch::ActiveTime);
+        }
+        if let Some(idle_after) = self.idle_after {
+            if desktop
+                .idle
+                .is_none_or(|idle| idle < Duration::from_secs(idle_after))
+            {
+                return Err(Mismatch::IdleAfter(idle_after));
+            }
+        }
+        if let Some(monitor) = self.monitor {
+            if desktop.monitor != Some(monitor) {
+                return Err(Mismatch::Monitor(monitor));
+            }
+        }
+        if let Some(fullscreen) = self.fullscreen {
+            if window
+                .fullscreen
+                .is_some_and(|is_fullscreen| is_fullscreen != fullscreen)
+            {
+                return Err(Mismatch::Fullscreen(fullscreen));
+            }
+        }
+
+        // patterns don't apply to the empty desktop
+        if desktop.empty {
+            return Ok(());
+        }
+        if let Some(key) = self.matcher.mismatch(window) {
+            return Err(Mismatch::Pattern(key));
+        }
+        if self.app_group.is_some() && !self.group.iter().any(|member| member.matches(window)) {
+            return Err(Mismatch::AppGroup);
+        }
+        if let Some(entry) = self
+            .exclude
+            .iter()
+            .position(|exclude| exclude.matches(window))
+        {
+            return Err(Mismatch::Excluded(entry));
+        }
+
+        Ok(())
+    }
+
+    /// Returns whether this rule matches every window the `later` rule
+    /// matches, so `later` never matches if this rule is checked first. Like
+    /// `RuleMatcher::covers`, this only catches the obvious cases: every
+    /// condition of this rule is either unset or the same as in `later`, and
+    /// all of its exclusions are exclusions of `later` as well.
+    fn shadows(&self, later: &Self) -> bool {
+        let later_exclusions: Vec<_> = later
+            .exclude
+            .iter()
+            .map(|exclude| serde_json::to_value(exclude).ok())
+            .collect();
+
+        self.empty_desktop == later.empty_desktop
+            && self
+                .app_group
+                .as_ref()
+                .is_none_or(|app_group| later.app_group.as_ref() == Some(app_group))
+            && self
+                .active_time
+                .as_ref()
+                .is_none_or(|active_time| later.active_time.as_ref() == Some(active_time))
+            && self
+                .idle_after
+                .is_none_or(|idle_after| later.idle_after == Some(idle_after))
+            && self
+                .monitor
+                .is_none_or(|monitor| later.monitor == Some(monitor))
+            && self
+                .fullscreen
+                .is_none_or(|fullscreen| later.fullscreen == Some(fullscreen))
+            && self.exclude.iter().all(|exclude| {
+                serde_json::to_value(exclude)
+                    .is_ok_and(|exclude| later_exclusions.contains(&Some(exclude)))
+            })
+            && self.matcher.covers(&later.matcher)
+    }
+}
+
+/// Target of a rule until `Config::prepare` resolves its `switch_to`.
+fn unresolved() -> Target {
+    Target::Profile(0)
+}
+
+impl Config {
+    /// Validates the profile ids of the config, resolves the `switch_to`
+    /// profile names and `app_group`s of its rules, compiles their patterns
+    /// and sorts them by descending priority.
+    fn prepare(&mut self) -> Result<(), ConfigError> {
+        if let Some(profile) = self.default_profile {
+            if !PROFILES.contains(&profile) {
+                return Err(ConfigError::Profile("default_profile".to_string(), profile));
+            }
+        }
+
+        for (name, profile) in &self.profile_names {
+            if Target::keyword(name).is_some() {
+                return Err(ConfigError::ReservedProfileName(name.clone()));
+            }
+            if !PROFILES.contains(profile) {
+                return Err(ConfigError::Profile(
+                    format!("profile_names.{name}"),
+                    *profile,
+                ));
+            }
+        }
+
+        for (i, rule) in self.rules_list.iter_mut().enumerate() {
+            rule.index = i;
+            rule.target = match &rule.switch_to {
+                enums::SwitchTo::Profile(profile) => Target::Profile(*profile),
+                enums::SwitchTo::Name(name) => match Target::keyword(name) {
+                    Some(target) => target,
+                    // `profile_names` is the fallback for names the duckypad
+                    // doesn't have, e.g. without a connected device
+                    None => Target::Profile(
+                        *self
+                            .device_names
+                            .get(name)
+                            .or_else(|| self.profile_names.get(name))
+                            .ok_or_else(|| {
+                                ConfigError::UnknownProfileName(format!("rule {i}"), name.clone())
+                            })?,
+                    ),
+                },
+            };
+
+            // only the keywords switch to something else than a profile, e.g.
+            // a `switch_to` of 0 is a typo, not "sleep"
+            if let Target::Profile(profile) = rule.target {
+                if !PROFILES.contains(&profile) {
+                    return Err(ConfigError::Profile(format!("rule {i}"), profile));
+                }
+            }
+
+            if let Some(active_time) = &rule.active_time {
+                rule.schedule = Some(
+                    active_time
+                        .parse()
+                        .map_err(|()| ConfigError::ActiveTime(i, active_time.clone()))?,
+                );
+            }
+
+            // exclusions inherit the case sensitivity of their rule
+            let ignore_case = rule
+                .matcher
+                .case_insensitive
+                .or(self.case_insensitive)
+                .unwrap_or(false);
+
+            if let Some(name) = &rule.app_group {
+                rule.group = self
+                    .groups
+                    .get(name)
+                    .ok_or_else(|| ConfigError::UnknownGroup(format!("rule {i}"), name.clone()))?
+                    .clone();
+            }
+
+            for matcher in std::iter::once(&mut rule.matcher)
+                .chain(&mut rule.exclude)
+                .chain(&mut rule.group)
+            {
+                matcher.compile(&format!("rule {i}"), ignore_case)?;
+            }
+        }
+
+        for (i, matcher) in self.ignore_list.iter_mut().enumerate() {
+            matcher.compile(
+                &format!("ignore_list entry {i}"),
+                self.case_insensitive.unwrap_or(false),
+            )?;
+        }
+
+        // stable, so rules with equal priority keep their order from the file
+        self.rules_list
+            .sort_by_key(|rule| std::cmp::Reverse(rule.priority.unwrap_or_default()));
+
+        Ok(())
+    }
+
+    /// Checks that the enabled rules and the `default_profile` only switch to
+    /// profiles within `1..=max_profiles`, the profiles of the connected
+    /// duckypad model.
+    ///
+    /// # Errors
+    ///
+    /// Will return `ConfigError::MissingProfiles` listing the rules (by their
+    /// position in the config file) and the `default_profile` with higher
+    /// profiles.
+    pub fn check_max_profiles(&self, max_profiles: u32) -> Result<(), ConfigError> {
+        let mut rules: Vec<_> = self
+            .rules_list
+            .iter()
+            .filter(|rule| rule.enabled)
+            .filter_map(|rule| match rule.target {
+                Target::Profile(profile) if profile > max_profiles => Some((rule.index, profile)),
+                _ => None,
+            })
+            .collect();
+        rules.sort_unstable();
+
+        let mut locations: Vec<_> = rules
+            .into_iter()
+            .map(|(rule, profile)| format!("rule {rule} (profile {profile})"))
+            .collect();
+
+        if let Some(profile) = self
+            .default_profile
+            .filter(|profile| *profile > max_profiles)
+        {
+            locations.push(format!("default_profile (profile {profile})"));
+        }
+
+        if locations.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError::MissingProfiles(locations, max_profiles))
+        }
+    }
+
+    /// Returns `title` as it is matched against the rules: normalized and cut
+    /// off after the `max_title_length` (see `normalize_title`).
+    pub fn normalized_title(&self, title: &str) -> String {
+        normalize_title(title, self.max_title_length)
+    }
+
+    /// Returns whether any rule depends on the idle time of the user.
+    fn uses_idle(&self) -> bool {
+        self.rules_list
+            .iter()
+            .any(|rule| rule.enabled && rule.idle_after.is_some())
+    }
+
+    /// Returns whether any rule depends on the monitor of the active window.
+    fn uses_monitor(&self) -> bool {
+        self.rules_list
+            .iter()
+            .any(|rule| rule.enabled && rule.monitor.is_some())
+    }
+
+    /// Returns whether any rule matches an empty desktop.
+    fn uses_empty_desktop(&self) -> bool {
+        self.rules_list
+            .iter()
+            .any(|rule| rule.enabled && rule.empty_desktop)
+    }
+
+    /// Returns whether any rule depends on the fullscreen state of the active
+    /// window.
+    fn uses_fullscreen(&self) -> bool {
+        self.rules_list
+            .iter()
+            .any(|rule| rule.enabled && rule.fullscreen.is_some())
+    }
+
+    /// Returns whether rules can start or stop matching while the same window
+    /// stays active, so the window has to be checked on every poll even if
+    /// the `WindowProvider` reports focus changes: rules with an
+    /// `active_time`, `idle_after`, `monitor` or `fullscreen` condition and
+    /// title or URL patterns (also in `exclude`, groups and the
+    /// `ignore_list`).
+    pub fn needs_polling(&self) -> bool {
+        self.rules_list
+            .iter()
+            .filter(|rule| rule.enabled)
+            .any(|rule| {
+                rule.active_time.is_some()
+                    || rule.idle_after.is_some()
+                    || rule.monitor.is_some()
+                    || rule.fullscreen.is_some()
+                    || std::iter::once(&rule.matcher)
+                        .chain(&rule.exclude)
+                        .chain(&rule.group)
+                        .any(RuleMatcher::uses_title)
+            })
+            || self.ignore_list.iter().any(RuleMatcher::uses_title)
+    }
+
+    /// Returns problems of the config that don't prevent using it: enabled
+    /// rules that never match, because an earlier checked rule has the same
+    /// patterns and conditions or is broader (see `Rules::shadows`). The
+    /// daemon logs them as warnings (or refuses the config with `--strict`),
+    /// the `validate` subcommand reports them as errors.
+    pub fn warnings(&self) -> Vec<String> {
+        // everything but the profile and the order decides whether rules match
+        let conditions = |rule: &Rules| {
+            let mut value = serde_json::to_value(rule).ok()?;
+            let object = value.as_object_mut()?;
+            object.remove("switch_to");
+            object.remove("priority");
+            Some(value)
+        };
+        let rules: Vec<_> = self
+            .rules_list
+            .iter()
+            .filter(|rule| rule.enabled)
+            .map(|rule| (rule, conditions(rule)))
+            .collect();
+
+        rules
+            .iter()
+            .enumerate()
+            .filter_map(|(i, (rule, conditions))| {
+                let index = rule.index;
+
+                if let Some((first, _)) = rules[..i]
+                    .iter()
+                    .find(|(_, other)| conditions.is_some() && other == conditions)
+                {
+                    return Some(format!(
+                        "Rule {index} never matches, as rule {} has the same patterns and conditions and is checked first",
+                        first.index
+                    ));
+                }
+
+                let (first, _) = rules[..i].iter().find(|(other, _)| other.shadows(rule))?;
+
+                Some(format!(
+                    "Rule {index} ({}) never matches, as rule {} ({}) matches every window it does and is checked first",
+                    rule.target,
+                    first.index,
+                    first.target
+                ))
+            })
+            .collect()
+    }
+
+    /// Returns the position of the first `ignore_list` entry that matches
+    /// `window`, or `None` if the window isn't ignored.
+    pub fn ignored(&self, window: &WindowInfo) -> Option<usize> {
+        self.ignore_list
+            .iter()
+            .position(|matcher| matcher.matches(window))
+    }
+
+    /// Returns whether autoswitching is enabled (a missing
+    /// `autoswitch_enabled` field counts as enabled).
+    pub fn autoswitch_enabled(&self) -> bool {
+        self.autoswitch_enabled != Some(false)
+    }
+
+    /// Returns the name of `profile` on the duckypad (see `device_names`), or
+    /// else from `profile_names`, the alphabetically first one if it has
+    /// several.
+    pub fn profile_name(&self, profile: u32) -> Option<&str> {
+        [&self.device_names, &self.profile_names]
+            .into_iter()
+            .find_map(|names| {
+                names
+                    .iter()
+                    .filter(|(_, id)| **id == profile)
+                    .map(|(name, _)| name.as_str())
+                    .min()
+            })
+    }
+
+    /// Returns the names of the profiles on the duckypad that the config was
+    /// read with (see `read_config_with`), empty if it was read without a
+    /// duckypad.
+    pub fn device_names(&self) -> &HashMap<String, u32> {
+        &self.device_names
+    }
+
+    /// Returns the paths of the files merged via `include` (including the
+    /// ones included by included files), in the order of their rules.
+    pub fn included_files(&self) -> &[PathBuf] {
+        &self.included
+    }
+}
+
+/// Replaces `${NAME}` references in `value` with the environment variable
+/// `NAME`, e.g. `${HOME}/.local/bin/app`. A `$` that isn't followed by `{` is
+/// kept, so regular expressions can still use it as an anchor.
+///
+/// # Errors
+///
+/// Will return the name of the first referenced variable that isn't set (or
+/// isn't valid unicode).
+fn expand_env(value: &str) -> Result<String, String> {
+    let mut expanded = String::with_capacity(value.len());
+    let mut rest = value;
+
+    while let Some(start) = rest.find("${") {
+        let Some(end) = rest[start..].find('}') else {
+            break;
+        };
+        let name = &rest[start + 2..start + end];
+
+        expanded.push_str(&rest[..start]);
+        expanded.push_str(&std::env::var(name).map_err(|_| name.to_string())?);
+        rest = &rest[start + end + 1..];
+    }
+
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+/// Parses the JSON config `raw`. Strict JSON is tried first, so valid JSON
+/// configs behave exactly as before, and JSON5 (comments, trailing commas,
+/// unquoted keys, ...) only if that fails with a syntax error.
+fn parse_json<T: serde::de::DeserializeOwned>(raw: &str) -> Result<T, ConfigError> {
+    match serde_json::from_str(raw) {
+        Err(err) if err.is_syntax() || err.is_eof() => {
+            json5::from_str(raw).map_err(ConfigError::Json5)
+        }
+        result => result.map_err(ConfigError::Parse),
+    }
+}
+
+/// Whether the config file at `path` is written in TOML instead of JSON.
+fn is_toml(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("toml"))
+}
+
+fn create_default_config(path: &Path) {
+    tracing::info!("Creating default config, because file doesn't exist");
+
+    let config = Config {
+        autoswitch_enabled: Some(true),
+        rules_list: vec![],
+        default_profile: None,
+        profile_names: HashMap::new(),
+        groups: HashMap::new(),
+        case_insensitive: None,
+        max_title_length: None,
+        ignore_list: vec![],
+        include: vec![],
+        included: vec![],
+        device_names: HashMap::new(),
+    };
+    let raw = if is_toml(path) {
+        toml::to_string(&config).expect("Failed to serialize default config.")
+    } else {
+        serde_json::to_string(&config).expect("Failed to serialize default config.")
+    };
+
+    write_config(path, &raw)
+        .unwrap_or_else(|error| panic!("Couldn't create config file:\n{error}"));
+}
+
+/// Writes `contents` to the config file at `path` atomically: they are written
+/// to a temporary file in the same directory first, which then replaces the
+/// config file. This way a crash while writing never leaves a truncated config.
+///
+/// # Errors
+///
+/// Will return `std::io::Error` if the temporary file cannot be written or
+/// renamed to `path`.
+fn write_config(path: &Path, contents: &str) -> std::io::Result<()> {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".tmp");
+    let tmp = path.with_file_name(name);
+
+    let mut file = File::create(&tmp)?;
+    file.write_all(contents.as_bytes())?;
+    file.sync_all()?;
+    drop(file);
+
+    std::fs::rename(&tmp, path).inspect_err(|_| {
+        let _: Result<_, _> = std::fs::remove_file(&tmp);
+    })
 }
 
 /// Returns a `PathBuf` for the config file path and creates a default config if
 /// no config file exists yet.
 ///
-/// Default config path is `XDG_CONFIG_DIR/duckypad_autoswitcher/config.txt`
+/// Default config path is `duckypad_daemon/config.json` in the platform's
+/// config directory (`$XDG_CONFIG_HOME` or `~/.config` on Linux). If it
+/// doesn't exist yet, the config of the python-based autoswitcher is imported
+/// instead of creating an empty one (see `autoswitcher_config_file`).
 ///
 /// # Arguments
 ///
-/// * `path` - Override directory in which the config file exists or should be created.
+/// * `path` - Override path of the config file, which is created if it
+///   doesn't exist
 ///
 /// # Panics
 ///
-/// The function will panic if `path`/config.txt isn't a file or couldn't be created.
+/// The function will panic if `path` isn't a file or couldn't be created.
 ///
 /// # Examples
 ///
-/// ```
+/// ```no_run
+/// use duckypad_daemon::config_file;
+///
 /// let config = config_file(None);
 /// ```
 pub fn config_file(path: Option<PathBuf>) -> PathBuf {
@@ -89,9 +1308,7 @@ pub fn config_file(path: Option<PathBuf>) -> PathBuf {
         return config;
     }
 
-    let mut config = dirs_next::config_dir()
-        .expect("Unable to determine platform specific default for config files!");
-    config.push("duckypad_daemon/config.json");
+    let config = default_config_path();
 
     if !config.exists() {
         let parent = config
@@ -103,88 +1320,741 @@ pub fn config_file(path: Option<PathBuf>) -> PathBuf {
                 .unwrap_or_else(|err| panic!("Unable to create config directory: {err}"));
         }
 
-        create_default_config(&config);
+        if !import_autoswitcher_config(&config) {
+            create_default_config(&config);
+        }
     }
 
     config
 }
 
-/// Returns a serde Value object that represents the current contents of the
-/// configuration file.
-///
-/// # Arguments
-///
-/// * `path` - Path to the config file
+/// Extensions of the config files in a config directory, in the order
+/// `named_config_file` looks for them.
+const CONFIG_EXTENSIONS: [&str; 3] = ["json", "json5", "toml"];
+
+/// Name of the config in a config directory that is used on startup.
+pub const DEFAULT_CONFIG_NAME: &str = "default";
+
+/// Returns the path of the config named `name` in the config directory `dir`,
+/// i.e. the first existing file of `<dir>/<name>.json`, `<dir>/<name>.json5`
+/// and `<dir>/<name>.toml`. Unlike `config_file`, no config is created.
 ///
-/// # Examples
+/// # Errors
 ///
-/// ```
-/// let config = read_config(config_file(None));
-/// ```
+/// Will return `ConfigError::UnknownConfig` if none of the files exists or
+/// `name` isn't a plain file name (e.g. contains a path separator).
+pub fn named_config_file(dir: &Path, name: &str) -> Result<PathBuf, ConfigError> {
+    let plain = !name.is_empty()
+        && !name.starts_with('.')
+        && Path::new(name).file_name() == Some(name.as_ref());
+
+    CONFIG_EXTENSIONS
+        .iter()
+        .map(|ext| dir.join(format!("{name}.{ext}")))
+        .find(|path| plain && path.is_file())
+        .ok_or_else(|| ConfigError::UnknownConfig(name.to_string(), dir.to_path_buf()))
+}
+
+/// Returns the default config path (see `config_file`) without creating the
+/// config file.
 ///
 /// # Panics
 ///
-/// This function will panic either if the config file at `path` cannot be read
-/// from or if it cannot be parsed as a JSON.
-pub fn read_config(path: &PathBuf) -> Config {
-    let file =
-        File::open(path).unwrap_or_else(|error| panic!("Error reading config file:\n{error}"));
-    let reader = std::io::BufReader::new(file);
-    let config: Config = serde_json::from_reader(reader)
-        .unwrap_or_else(|error| panic!("Error parsing and deserialize config file:\n{error}"));
-
+/// The function will panic if the platform has no config directory.
+pub fn default_config_path() -> PathBuf {
+    let mut config = dirs_next::config_dir()
+        .expect("Unable to determine platform specific default for config files!");
+    config.push("duckypad_daemon/config.json");
     config
 }
 
+/// Returns the path of the config file of dekuNukem's python-based duckypad
+/// autoswitcher: `duckypad_autoswitcher/config.txt` in the documents directory
+/// on Windows and in the data directory (`$XDG_DATA_HOME` or `~/.local/share`
+/// on Linux) elsewhere.
+fn autoswitcher_config_file() -> Option<PathBuf> {
+    let dir = if cfg!(windows) {
+        dirs_next::document_dir()
+    } else {
+        dirs_next::data_dir()
+    };
+
+    dir.map(|dir| dir.join("duckypad_autoswitcher").join("config.txt"))
+}
+
+/// Copies the config of the python-based autoswitcher to `config`, if it
+/// exists and is a valid config. Returns whether it was imported.
+fn import_autoswitcher_config(config: &Path) -> bool {
+    let Some(autoswitcher_config) = autoswitcher_config_file().filter(|path| path.is_file()) else {
+        return false;
+    };
+
+    if let Err(err) = read_config(&autoswitcher_config) {
+        tracing::warn!(
+            "Not importing the config of the duckyPad autoswitcher at {}: {err}",
+            autoswitcher_config.display()
+        );
+        return false;
+    }
+
+    match std::fs::copy(&autoswitcher_config, config) {
+        Ok(_) => {
+            tracing::info!(
+                "Imported the config of the duckyPad autoswitcher from {}",
+                autoswitcher_config.display()
+            );
+            true
+        }
+        Err(err) => {
+            tracing::warn!(
+                "Failed to import the config of the duckyPad autoswitcher at {}: {err}",
+                autoswitcher_config.display()
+            );
+            false
+        }
+    }
+}
+
+/// Returns a `Config` that represents the current contents of the
+/// configuration file. Files with a `.toml` extension are parsed as TOML, all
+/// others as JSON.
+///
+/// # Arguments
+///
+/// * `path` - Path to the config file
+///
+/// # Examples
+///
+/// ```no_run
+/// use duckypad_daemon::{config_file, read_config};
+///
+/// let config = read_config(&config_file(None)).expect("Invalid config");
+/// ```
+///
+/// # Errors
+///
+/// Will return `ConfigError` either if the config file at `path` cannot be
+/// read from, if it cannot be parsed as JSON (or TOML) or if a rule contains an
+/// invalid regular expression, if a profile id isn't within `PROFILES` or if a
+/// rule references a profile name that `profile_names` doesn't define.
+pub fn read_config(path: &Path) -> Result<Config, ConfigError> {
+    read_config_with(path, &HashMap::new())
+}
+
+/// Reads the config file at `path` like `read_config`, but resolves the
+/// profile names in `switch_to` with `device_names`, the names of the
+/// profiles on the connected duckypad (see `hid::DuckyPad::profile_names`).
+/// The config's `profile_names` are only used for names the duckypad doesn't
+/// have.
+///
+/// # Errors
+///
+/// Will return `ConfigError` like `read_config`, including for names that
+/// neither the duckypad nor `profile_names` has.
+#[allow(clippy::implicit_hasher)]
+pub fn read_config_with(
+    path: &Path,
+    device_names: &HashMap<String, u32>,
+) -> Result<Config, ConfigError> {
+    let mut config: Config = parse_file(path)?;
+    let includes = std::mem::take(&mut config.include);
+    let mut stack = vec![path.canonicalize().map_err(ConfigError::Io)?];
+
+    for include in &includes {
+        merge_include(&mut config, &resolve_include(path, include), &mut stack)?;
+    }
+
+    config.include = includes;
+    config.device_names.clone_from(device_names);
+    config.prepare()?;
+
+    Ok(config)
+}
+
+/// Reads the config file at `path` with the profile names of the duckypad (see
+/// `read_config_with`) and logs its warnings. With `strict`, a config with
+/// warnings is refused instead.
+///
+/// # Errors
+///
+/// Will return `ConfigError` if the config can't be read (see
+/// `read_config_with`), or if it has warnings with `strict`.
+#[allow(clippy::implicit_hasher)]
+pub fn load_config(
+    path: &Path,
+    strict: bool,
+    device_names: &HashMap<String, u32>,
+) -> Result<Config, ConfigError> {
+    let config = read_config_with(path, device_names)?;
+    let warnings = config.warnings();
+
+    if strict && !warnings.is_empty() {
+        return Err(ConfigError::Warnings(warnings));
+    }
+
+    for warning in warnings {
+        tracing::warn!("{warning}");
+    }
+
+    Ok(config)
+}
+
+/// Reads and deserializes the config file at `path` as TOML or JSON,
+/// depending on its extension.
+fn parse_file<T: serde::de::DeserializeOwned>(path: &Path) -> Result<T, ConfigError> {
+    let raw = std::fs::read_to_string(path).map_err(ConfigError::Io)?;
+
+    if is_toml(path) {
+        toml::from_str(&raw).map_err(ConfigError::Toml)
+    } else {
+        parse_json(&raw)
+    }
+}
+
+/// Resolves the path `include` listed in the config file at `path` relative
+/// to the directory of that file.
+fn resolve_include(path: &Path, include: &Path) -> PathBuf {
+    path.parent().unwrap_or(Path::new("")).join(include)
+}
+
+/// Appends the rules of the included file at `path` to the rules of
+/// `config`, followed by the rules of the files it includes in turn (depth
+/// first, in the order of their `include`). `stack` holds the canonical paths
+/// of the files including this one, to detect cycles.
+fn merge_include(
+    config: &mut Config,
+    path: &Path,
+    stack: &mut Vec<PathBuf>,
+) -> Result<(), ConfigError> {
+    let include_error = |err| ConfigError::Include(path.to_path_buf(), Box::new(err));
+    let canonical = path
+        .canonicalize()
+        .map_err(|err| include_error(ConfigError::Io(err)))?;
+
+    if let Some(start) = stack.iter().position(|file| *file == canonical) {
+        let mut cycle = stack[start..].to_vec();
+        cycle.push(canonical);
+        return Err(ConfigError::IncludeCycle(cycle));
+    }
+
+    let included: IncludedConfig = parse_file(path).map_err(include_error)?;
+    config.rules_list.extend(included.rules_list);
+    config.included.push(path.to_path_buf());
+
+    stack.push(canonical);
+    for include in &included.include {
+        merge_include(config, &resolve_include(path, include), stack)?;
+    }
+    stack.pop();
+
+    Ok(())
+}
+
+/// A profile switch performed by `switch_profile`.
+pub struct SwitchEvent {
+    /// id of the profile that was switched to
+    pub profile: u32,
+    /// active window that caused the switch
+    pub window: WindowInfo,
+    /// index of the matching rule in the config file, `None` if the
+    /// `default_profile` was used
+    pub rule: Option<usize>,
+}
+
+/// Outcome of a call to `switch_profile`.
+pub enum SwitchResult {
+    /// No profile switch was necessary, contains the current profile
+    Kept(Option<u32>),
+    /// No rule matches the active window and there is no `default_profile`,
+    /// contains the current profile
+    Unmatched(Option<u32>),
+    /// The duckypad switched to a new profile
+    Switched(Box<SwitchEvent>),
+}
+
+impl SwitchResult {
+    /// Returns the id of the profile the duckypad is on after the switch.
+    pub fn profile(&self) -> Option<u32> {
+        match self {
+            Self::Kept(profile) | Self::Unmatched(profile) => *profile,
+            Self::Switched(event) => Some(event.profile),
+        }
+    }
+}
+
+/// Delays profile switches until the matched profile stayed the same for a
+/// minimum duration, so quickly switching between windows doesn't cause a
+/// profile switch for every window.
+pub struct Debounce {
+    delay: Duration,
+    candidate: Option<(Option<Target>, Instant)>,
+}
+
+impl Debounce {
+    pub fn new(delay: Duration) -> Self {
+        Self {
+            delay,
+            candidate: None,
+        }
+    }
+
+    /// Returns whether `target` has been matched for at least the delay.
+    fn settled(&mut self, target: Option<Target>) -> bool {
+        match self.candidate {
+            Some((candidate, since)) if candidate == target => since.elapsed() >= self.delay,
+            _ => {
+                self.candidate = Some((target, Instant::now()));
+                self.delay.is_zero()
+            }
+        }
+    }
+}
+
+/// Enforces a minimum time between two profile switches, so focus alternating
+/// between windows with different profiles doesn't make the duckypad switch
+/// back and forth. Switches within the interval are skipped, the profile
+/// matched once it elapsed is applied.
+pub struct MinInterval {
+    interval: Duration,
+    last_switch: Option<Instant>,
+    /// whether a switch was skipped since the last one
+    skipped: bool,
+}
+
+impl MinInterval {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            last_switch: None,
+            skipped: false,
+        }
+    }
+
+    /// Returns the time until the interval since the last switch elapsed.
+    fn remaining(&self) -> Duration {
+        self.last_switch.map_or(Duration::ZERO, |last_switch| {
+            self.interval.saturating_sub(last_switch.elapsed())
+        })
+    }
+
+    /// Returns whether switching is allowed now and remembers a skipped
+    /// switch otherwise.
+    fn allows(&mut self) -> bool {
+        self.skipped = !self.remaining().is_zero();
+        !self.skipped
+    }
+
+    /// Records that the profile was switched now.
+    pub fn switched(&mut self) {
+        self.last_switch = Some(Instant::now());
+        self.skipped = false;
+    }
+
+    /// Returns the time until a skipped switch can be applied, or `None` if
+    /// no switch was skipped.
+    pub fn pending(&self) -> Option<Duration> {
+        Some(self.remaining()).filter(|remaining| self.skipped && !remaining.is_zero())
+    }
+}
+
+/// Pauses autoswitching for a grace period after the profile was changed by
+/// hand on the duckypad (`--manual-override-grace`), so the daemon doesn't
+/// switch right back to the profile of the active window.
+pub struct ManualOverride {
+    grace: Duration,
+    /// end of the grace period, if it is running
+    until: Option<Instant>,
+}
+
+impl ManualOverride {
+    pub fn new(grace: Duration) -> Self {
+        Self { grace, until: None }
+    }
+
+    /// Returns the grace period.
+    pub fn grace(&self) -> Duration {
+        self.grace
+    }
+
+    /// Starts (or restarts) the grace period at `now`, when the profile on
+    /// the duckypad diverged from the one the daemon switched to.
+    pub fn start(&mut self, now: Instant) {
+        self.until = Some(now + self.grace);
+    }
+
+    /// Returns whether the grace period is running at `now`.
+    pub fn active(&self, now: Instant) -> bool {
+        self.until.is_some_and(|until| now < until)
+    }
+
+    /// Returns whether the grace period ended at `now` and forgets it, so
+    /// this is only `true` once per grace period.
+    pub fn expire(&mut self, now: Instant) -> bool {
+        let expired = self.until.is_some_and(|until| now >= until);
+        if expired {
+            self.until = None;
+        }
+        expired
+    }
+}
+
+/// State of rules with relative targets (see `Target::is_relative`). As their
+/// target changes with every switch, they are only applied once when they
+/// start matching instead of on every poll. `Target::Toggle` needs the profile
+/// before the current one, which is tracked across all profile changes.
+#[derive(Default)]
+pub struct RelativeTargets {
+    /// rule of the last match that was applied or already active, `None` for
+    /// the `default_profile` or if nothing matched
+    applied: Option<usize>,
+    current: Option<u32>,
+    before: Option<u32>,
+}
+
+impl RelativeTargets {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Remembers the `profile` the duckypad is on, if it is known.
+    fn observe(&mut self, profile: Option<u32>) {
+        if let Some(profile) = profile.filter(|profile| self.current != Some(*profile)) {
+            self.before = self.current.replace(profile);
+        }
+    }
+}
+
+/// What `active_window` found on the desktop.
+enum Focus {
+    /// the focused window with the information rules are matched against
+    Window(Box<WindowInfo>),
+    /// no window is focused (see `WindowInfo::is_unfocused`)
+    Nothing,
+}
+
+/// Returns the focused window, `Focus::Nothing` if no window is focused or
+/// `None` if it can't be determined.
+fn active_window(
+    processes: &mut Processes,
+    config: &Config,
+    provider: &mut dyn WindowProvider,
+) -> Option<Focus> {
+    let mut window = match provider.active() {
+        Ok(window) => window,
+        // a streaming script that just started hasn't printed a window yet
+        Err(err @ WindowError::Script(WindowScriptError::NoOutput)) => {
+            tracing::trace!("{err}");
+            return None;
+        }
+        Err(err @ WindowError::Script(_)) => {
+            tracing::warn!("{err}");
+            return None;
+        }
+        Err(WindowError::Native) => return None,
+    };
+
+    let xorg = provider.is_xorg();
+    if window.is_unfocused(xorg) {
+        return Some(Focus::Nothing);
+    }
+
+    window.title = config.normalized_title(&window.title);
+
+    // window scripts (e.g. on Wayland) often can't tell the process, so its
+    // name is all there is; pid 0 would be the scheduler on Linux
+    #[allow(clippy::cast_possible_truncation)]
+    let (app_name, process_path, cmdline) = if window.process_id == 0 {
+        (window.process_name.clone(), PathBuf::new(), Vec::new())
+    } else {
+        processes
+            .lookup(Pid::from(window.process_id as usize), &window.window_id)
+            .unwrap_or(("unknown".to_string(), PathBuf::new(), Vec::new()))
+    };
+
+    window.app_name = app_name;
+    window.cmdline = cmdline;
+    if window.process_path.as_os_str().is_empty() {
+        window.process_path = process_path;
+    }
+
+    if xorg && config.uses_fullscreen() {
+        window.fullscreen = x11::is_fullscreen(&window.window_id);
+    }
+
+    tracing::trace!(
+        app_name = window.app_name,
+        title = window.title,
+        process_name = window.process_name,
+        "Active window"
+    );
+
+    Some(Focus::Window(Box::new(window)))
+}
+
 /// Switches to the next profile if it is different from the previous one and
 /// returns it.
 ///
 /// # Arguments
 ///
-/// * `api` - valid api connection
+/// * `device` - connected duckypad hid device (or any other transport) or
+///   `None` for a dry run, which
+///   only logs the profile that would be switched to
+/// * `processes` - used to look up the process of the active window
 /// * `config` - current configuration
-/// * `prev_profile` - id of the profile on the duckypad (1 <= id <= 31)
-/// * `callback` - optional command to spawn
-/// * `os` - enum value of the running operating system
-pub fn switch_profile(
-    api: &HidApi,
-    sys: &mut Option<System>,
+/// * `prev_profile` - id of the profile on the duckypad (see `PROFILES`), no
+///   switch happens if it is equal to the profile of the matching rule
+/// * `callback` - optional executable to run on change (see `run_callback`),
+///   which `switch_profile` waits for if the callback is synchronous
+/// * `provider` - source of the active window, chosen on startup
+/// * `paused` - whether autoswitching is paused at runtime
+/// * `debounce` - optional state to only switch once the matched profile
+///   settled, returning `SwitchResult::Kept(prev_profile)` until then
+/// * `min_interval` - optional state to skip switches that would happen too
+///   soon after the previous one, returning `SwitchResult::Kept(prev_profile)`
+///   instead
+/// * `relative` - optional state to apply rules with relative targets only
+///   once when they start matching and to toggle back (see
+///   `RelativeTargets`). Without it, they are applied on every call. They
+///   are resolved against the profile read from the duckypad if it supports
+///   that, else against `prev_profile`
+///
+/// Returns `SwitchResult::Kept(prev_profile)` if autoswitching is paused or
+/// disabled via the `autoswitch_enabled` config field (a missing field counts
+/// as enabled), if the active window matches an entry of the config's
+/// `ignore_list`, or if no window is focused and there are no `empty_desktop`
+/// rules. `SwitchResult::Unmatched(prev_profile)` is returned if no rule
+/// matches and there is no `default_profile` to fall back to.
+///
+/// # Errors
+///
+/// Will return `HidError` if communicating with the duckypad fails, which
+/// usually means that it has been disconnected.
+#[allow(clippy::too_many_arguments)]
+pub fn switch_profile<T: DuckyPadTransport + ?Sized>(
+    device: Option<&T>,
+    processes: &mut Processes,
     config: &Config,
     prev_profile: Option<u32>,
-    callback: &mut Option<Command>,
-    os: &enums::OSIdent,
-) -> Option<u32> {
-    let window = match os {
-        enums::OSIdent::UNSUPPORTED(script)
-        | enums::OSIdent::LINUX(enums::LinuxServer::WAYLAND(script)) => {
-            custom_active_window(script)
+    callback: Option<&Callback>,
+    provider: &mut dyn WindowProvider,
+    paused: bool,
+    debounce: Option<&mut Debounce>,
+    mut min_interval: Option<&mut MinInterval>,
+    mut relative: Option<&mut RelativeTargets>,
+) -> Result<SwitchResult, HidError> {
+    if let Some(relative) = relative.as_deref_mut() {
+        relative.observe(prev_profile);
+    }
+
+    if paused || !config.autoswitch_enabled() {
+        return Ok(SwitchResult::Kept(prev_profile));
+    }
+
+    let xorg = provider.is_xorg();
+
+    if let Some(focus) = active_window(processes, config, provider) {
+        let (window, empty) = match focus {
+            Focus::Window(window) => (*window, false),
+            Focus::Nothing if config.uses_empty_desktop() => (WindowInfo::default(), true),
+            Focus::Nothing => {
+                // matching the root or an empty window would switch to its profile
+                tracing::trace!("No window is focused, keeping the profile");
+                return Ok(SwitchResult::Kept(prev_profile));
+            }
+        };
+
+        // ignored windows keep the profile and don't count for the debounce
+        if let Some(entry) = config.ignored(&window).filter(|_| !empty) {
+            tracing::trace!("Active window is ignored by ignore_list entry {entry}");
+            return Ok(SwitchResult::Kept(prev_profile));
+        }
+
+        let desktop = desktop_state(config, &window, xorg, empty);
+        let matched = next_match(config, &window, &desktop);
+        let target = matched.as_ref().map(|matched| matched.target);
+        let rule = matched.as_ref().and_then(|matched| matched.rule);
+        tracing::trace!("Matched target: {target:?}");
+        let settled = debounce.is_none_or(|debounce| debounce.settled(target));
+
+        if !settled {
+            return Ok(SwitchResult::Kept(prev_profile));
+        }
+
+        // see `resolve_relative` for rules with relative targets
+        if let Some(relative) = relative
+            .as_deref_mut()
+            .filter(|_| !target.is_some_and(Target::is_relative))
+        {
+            relative.applied = rule;
         }
-        _ => get_active_window(),
-    };
 
-    if let Ok(window) = window {
-        #[allow(clippy::cast_possible_truncation)]
-        let app_name = get_app_name(sys, Pid::from(window.process_id as usize))
-            .unwrap_or("unknown".to_string());
-
-        if let Some(profile) = next_profile(config, &window, &app_name) {
-            if match prev_profile {
-                Some(prev_profile) => profile != prev_profile,
-                None => true,
-            } {
-                if let Ok(duckypad) = hid::init(api) {
-                    if goto_profile(&duckypad, profile).is_ok() {
-                        if let Some(callback) = callback {
-                            run_callback(callback, profile, window, &app_name);
-                        }
-                        return Some(profile);
+        if let Some(target) = target {
+            // relative targets are resolved against the profile read from the
+            // duckypad already
+            let read = target.is_relative();
+            let profile = match target {
+                Target::Profile(profile) => profile,
+                Target::Sleep => SLEEP,
+                _ => match resolve_relative(
+                    device,
+                    prev_profile,
+                    relative.as_deref_mut(),
+                    target,
+                    rule,
+                )? {
+                    Some(profile) => profile,
+                    None => return Ok(SwitchResult::Kept(prev_profile)),
+                },
+            };
+
+            // the cached profile is kept in sync with manual profile changes on
+            // the duckypad by the caller (see `hid::read_current_profile`), and
+            // the duckypad is only asked before switching (see `already_on`)
+            if prev_profile == Some(profile) {
+                return Ok(SwitchResult::Kept(prev_profile));
+            }
+
+            if let Some(min_interval) = min_interval.as_deref_mut() {
+                if !min_interval.allows() {
+                    tracing::trace!("Delaying switch to profile {profile} (see --min-interval)");
+                    // tried again once the interval elapsed
+                    if let Some(relative) = relative {
+                        relative.applied = None;
                     }
+                    return Ok(SwitchResult::Kept(prev_profile));
+                }
+            }
+
+            if let Some(device) = device {
+                if !read && already_on(device, profile)? {
+                    tracing::debug!("The duckyPad is on profile {profile} already");
+                    return Ok(SwitchResult::Kept(Some(profile)));
                 }
+                goto_profile(device, profile)?;
+            } else {
+                let action = if profile == SLEEP {
+                    "put the duckyPad to sleep".to_string()
+                } else {
+                    format!("switch to profile {profile}")
+                };
+                tracing::info!(
+                    app_name = window.app_name,
+                    title = window.title,
+                    process_name = window.process_name,
+                    "Would {action}"
+                );
+            }
+
+            if let Some(min_interval) = min_interval {
+                min_interval.switched();
             }
+            if let Some(callback) = callback {
+                run_callback(callback, profile, prev_profile, rule, &window);
+            }
+            return Ok(SwitchResult::Switched(Box::new(SwitchEvent {
+                profile,
+                window,
+                rule,
+            })));
+        }
+
+        return Ok(SwitchResult::Unmatched(prev_profile));
+    }
+
+    Ok(SwitchResult::Kept(prev_profile))
+}
+
+/// Returns whether the `device` is on `profile` already, e.g. because it was
+/// picked on the duckypad by hand since the last sync, so the switch can be
+/// skipped. It is only read right before a switch, as comparing with the
+/// cached profile spares the round trip on every other poll. A failed read
+/// counts as not being on the profile, unless the duckypad is gone.
+///
+/// # Errors
+///
+/// Will return `HidError` if reading the profile fails with a disconnect (see
+/// `hid::is_disconnect`).
+fn already_on<T: DuckyPadTransport + ?Sized>(device: &T, profile: u32) -> Result<bool, HidError> {
+    // a sleeping duckypad still reports the profile it was on
+    if profile == SLEEP || !device.supports(hid::Feature::ReadProfile) {
+        return Ok(false);
+    }
+
+    match device.current_profile() {
+        Ok(current) => Ok(u32::from(current) == profile),
+        Err(err) if hid::is_disconnect(&err) => Err(err),
+        Err(err) => {
+            tracing::debug!("Failed to read the profile before switching: {err}");
+            Ok(false)
         }
     }
+}
+
+/// Returns the state of the desktop that rules are matched against besides
+/// the `window`. The idle time and monitor are only queried via X11 (with
+/// `xorg`) and if a rule depends on them.
+fn desktop_state(config: &Config, window: &WindowInfo, xorg: bool, empty: bool) -> DesktopState {
+    DesktopState {
+        idle: (xorg && config.uses_idle()).then(x11::idle_time).flatten(),
+        empty,
+        monitor: window
+            .position
+            .as_ref()
+            .filter(|_| xorg && config.uses_monitor())
+            .and_then(|position| {
+                x11::monitor_at(
+                    position.x + position.width / 2.0,
+                    position.y + position.height / 2.0,
+                )
+            }),
+    }
+}
+
+/// Resolves the relative `target` of the matching `rule` (see
+/// `relative_profile`) against the profile read from the `device`, or
+/// `prev_profile` if it can't be read. Returns `None` if the rule was already
+/// applied since it started matching or the target can't be resolved.
+///
+/// # Errors
+///
+/// Will return `HidError` if reading the current profile fails.
+fn resolve_relative<T: DuckyPadTransport + ?Sized>(
+    device: Option<&T>,
+    prev_profile: Option<u32>,
+    mut relative: Option<&mut RelativeTargets>,
+    target: Target,
+    rule: Option<usize>,
+) -> Result<Option<u32>, HidError> {
+    if relative
+        .as_deref()
+        .is_some_and(|relative| relative.applied == rule)
+    {
+        return Ok(None);
+    }
+
+    let current = match device.filter(|device| device.supports(hid::Feature::ReadProfile)) {
+        Some(device) => Some(u32::from(device.current_profile()?)),
+        None => prev_profile,
+    };
+    if let Some(relative) = relative.as_deref_mut() {
+        relative.observe(current);
+        relative.applied = rule;
+    }
+
+    let max_profiles = device.map_or(hid::MAX_PROFILES, DuckyPadTransport::max_profiles);
+    let before = relative.and_then(|relative| relative.before);
+    let resolved =
+        current.and_then(|current| relative_profile(target, current, before, max_profiles));
+
+    if resolved.is_none() {
+        tracing::warn!(
+            "Can't resolve the target of rule {} ({}), as the current profile or the one before it is unknown or out of range, keeping the profile",
+            rule.unwrap_or_default(),
+            target
+        );
+    }
 
-    prev_profile
+    Ok(resolved)
 }
 
 /// Gets information about the active window by calling a script that is passed
@@ -210,188 +2080,2017 @@ pub fn switch_profile(
 ///          "y": f64,
 ///          "w": f64,
 ///          "h": f64
-///     }
+///     },
+///     "fullscreen": bool,
+///     "url": str,
+///     "wm_instance": str,
+///     "wm_role": str
 /// }
 /// ```
 ///
 /// # Arguments
 ///
 /// * `script` - path of executable for custom window information
-fn custom_active_window(script: &PathBuf) -> Result<ActiveWindow, ()> {
-    let output = Command::new(script).stdout(Stdio::piped()).output();
-
-    if let Ok(output) = output {
-        let raw =
-            String::from_utf8(output.stdout).expect("Window script output needs to be valid utf8!");
-        let json: Value =
-            serde_json::from_str(&raw).expect("Window script needs to be a JSON object!");
-        let title = json
-            .get("title")
-            .expect("Window script output field \"title\" is missing!")
-            .as_str()
-            .expect("Window script output field \"title\" needs to be a string!")
-            .to_string();
-        let process_name = json
-            .get("process_name")
-            .expect("Window script output field \"process_name\" is missing!")
-            .as_str()
-            .expect("Window script output field \"process_name\" needs to be a string!")
-            .to_string();
-        let window_id = json
-            .get("window_id")
-            .unwrap_or(&Value::String(String::new()))
+///
+/// # Errors
+///
+/// Will return `WindowScriptError` if the script cannot be run or its output
+/// isn't a JSON object of the structure above.
+fn custom_active_window(script: &PathBuf) -> Result<WindowInfo, WindowScriptError> {
+    let output = Command::new(script)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .output()
+        .map_err(|err| WindowScriptError::Spawn(script.clone(), err))?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        return Err(WindowScriptError::Failed(output.status, stderr));
+    }
+
+    let raw = String::from_utf8(output.stdout).map_err(WindowScriptError::NonUtf8)?;
+
+    parse_window(&raw)
+}
+
+/// Information about the active window that rules are matched against. The
+/// native APIs (see `active_win_pos_rs::get_active_window`) and window scripts
+/// are both converted into it, so rules match the same on every platform.
+#[derive(Clone, Debug, Default)]
+pub struct WindowInfo {
+    /// name of the application, i.e. the command of the window's process
+    pub app_name: String,
+    /// name/category of the process (on X11 the `WM_CLASS` property)
+    pub process_name: String,
+    /// title of the window (on X11 the `_NET_WM_NAME` property)
+    pub title: String,
+    /// full path of the executable of the process, empty if unknown
+    pub process_path: PathBuf,
+    /// command line of the process (the executable and its arguments), empty
+    /// if unknown. It may contain secrets passed as arguments, so it is only
+    /// matched against and never logged
+    pub cmdline: Vec<String>,
+    /// id of the process, `0` if unknown, in which case the `app_name` is the
+    /// `process_name`
+    pub process_id: u64,
+    pub window_id: String,
+    /// position and size of the window, if known
+    pub position: Option<WindowPosition>,
+    /// whether the window is fullscreen (see `fullscreen`). An unknown
+    /// fullscreen state matches every `fullscreen` rule
+    pub fullscreen: Option<bool>,
+    /// URL of the page shown in a browser window, empty if unknown (only
+    /// reported by window scripts)
+    pub url: String,
+    /// instance name from the `WM_CLASS` property of the window (the class is
+    /// the `process_name`), empty if unknown. Only X11 and window scripts
+    /// report it
+    pub wm_instance: String,
+    /// `WM_WINDOW_ROLE` property of the window, empty if unknown. Only X11 and
+    /// window scripts report it
+    pub wm_role: String,
+}
+
+impl WindowInfo {
+    /// Returns whether this isn't a focused window: the root window or a
+    /// `None`/`PointerRoot` focus on X11 (with `xorg`, see `x11::is_no_window`),
+    /// or an empty window like the bundled window scripts print if nothing
+    /// is focused.
+    fn is_unfocused(&self, xorg: bool) -> bool {
+        (xorg && x11::is_no_window(&self.window_id))
+            || (self.title.is_empty()
+                && self.process_name.is_empty()
+                && self.window_id.is_empty()
+                && self.process_id == 0)
+    }
+}
+
+impl From<ActiveWindow> for WindowInfo {
+    /// Converts the window reported by a native API. The `app_name` is left
+    /// empty, as it is taken from the process list.
+    fn from(window: ActiveWindow) -> Self {
+        Self {
+            app_name: String::new(),
+            process_name: window.app_name,
+            title: window.title,
+            process_path: window.process_path,
+            cmdline: Vec::new(),
+            process_id: window.process_id,
+            window_id: window.window_id,
+            position: Some(window.position),
+            fullscreen: None,
+            url: String::new(),
+            wm_instance: String::new(),
+            wm_role: String::new(),
+        }
+    }
+}
+
+/// Parses the JSON object printed by a window script (see
+/// `custom_active_window`) into a `WindowInfo`.
+///
+/// # Errors
+///
+/// Will return `WindowScriptError` if `raw` isn't a JSON object of the
+/// expected structure.
+pub fn parse_window(raw: &str) -> Result<WindowInfo, WindowScriptError> {
+    let json: Value = serde_json::from_str(raw).map_err(WindowScriptError::Json)?;
+
+    let title = json_str(&json, "title")?.ok_or(WindowScriptError::MissingField("title"))?;
+    let process_name =
+        json_str(&json, "process_name")?.ok_or(WindowScriptError::MissingField("process_name"))?;
+    let window_id = json_str(&json, "window_id")?.unwrap_or_default();
+    let url = json_str(&json, "url")?.unwrap_or_default();
+    let wm_instance = json_str(&json, "wm_instance")?.unwrap_or_default();
+    let wm_role = json_str(&json, "wm_role")?.unwrap_or_default();
+    let process_id = match json.get("process_id") {
+        Some(process_id) => process_id.as_u64().ok_or(WindowScriptError::InvalidField(
+            "process_id",
+            "an unsigned int (u64)",
+        ))?,
+        None => 0,
+    };
+    let position = if let Some(pos) = json.get("position") {
+        if !pos.is_object() {
+            return Err(WindowScriptError::InvalidField("position", "a JSON object"));
+        }
+
+        Some(WindowPosition::new(
+            json_f64(pos, "x")?,
+            json_f64(pos, "y")?,
+            json_f64(pos, "w")?,
+            json_f64(pos, "h")?,
+        ))
+    } else {
+        None
+    };
+    let fullscreen = match json.get("fullscreen") {
+        Some(fullscreen) => Some(
+            fullscreen
+                .as_bool()
+                .ok_or(WindowScriptError::InvalidField("fullscreen", "a bool"))?,
+        ),
+        None => None,
+    };
+
+    Ok(WindowInfo {
+        app_name: String::new(),
+        process_name,
+        title,
+        process_path: PathBuf::new(), // TODO: Ignore path for now
+        cmdline: Vec::new(),
+        process_id,
+        window_id,
+        position,
+        fullscreen,
+        url,
+        wm_instance,
+        wm_role,
+    })
+}
+
+/// Returns the string value of `field` in `json` or `None` if it is missing.
+fn json_str(json: &Value, field: &'static str) -> Result<Option<String>, WindowScriptError> {
+    match json.get(field) {
+        Some(value) => value
             .as_str()
-            .expect("Window script output field \"window_id\" needs to be a string!")
-            .to_string();
-        let process_id = json
-            .get("process_id")
-            .unwrap_or(&Value::Number(serde_json::Number::from_f64(0.0).unwrap()))
-            .as_u64()
-            .expect("Window script output field \"process_id\" needs to be an unsigned int (u64)!");
-        let position = if let Some(pos) = json.get("position") {
-            let pos = pos
-                .as_object()
-                .expect("Window script output field \"position\" needs to be a JSON array!");
-            let x = pos
-                .get("x")
-                .expect("Window script output field \"x\" is missing!")
-                .as_f64()
-                .expect("Window script output field \"x\" needs to be a float (f64)!");
-            let y = pos
-                .get("y")
-                .expect("Window script output field \"y\" is missing!")
-                .as_f64()
-                .expect("Window script output field \"y\" needs to be a float (f64)!");
-            let w = pos
-                .get("w")
-                .expect("Window script output field \"w\" is missing!")
-                .as_f64()
-                .expect("Window script output field \"w\" needs to be a float (f64)!");
-            let h = pos
-                .get("h")
-                .expect("Window script output field \"h\" is missing!")
-                .as_f64()
-                .expect("Window script output field \"h\" needs to be a float (f64)!");
-            WindowPosition::new(x, y, w, h)
-        } else {
-            WindowPosition::new(0.0, 0.0, 0.0, 0.0)
-        };
-        let active_window: ActiveWindow = ActiveWindow {
-            title,
-            process_path: PathBuf::new(), // TODO: Ignore path for now
-            app_name: process_name,
-            window_id,
-            process_id,
-            position,
-        };
-        return Ok(active_window);
+            .map(|value| Some(value.to_string()))
+            .ok_or(WindowScriptError::InvalidField(field, "a string")),
+        None => Ok(None),
     }
+}
 
-    Err(())
+/// Returns the required float value of `field` in `json`.
+fn json_f64(json: &Value, field: &'static str) -> Result<f64, WindowScriptError> {
+    json.get(field)
+        .ok_or(WindowScriptError::MissingField(field))?
+        .as_f64()
+        .ok_or(WindowScriptError::InvalidField(field, "a float (f64)"))
 }
 
-/// Runs a callback executable if `callback.is_some()` by spawning a child with
-/// the following arguments:
-/// ```
+/// How often a synchronous callback is checked for having exited.
+const CALLBACK_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// A callback executable to run when the profile changes (see `run_callback`).
+pub struct Callback {
+    /// path of the executable
+    pub path: PathBuf,
+    /// `None` to run it in the background or the time to wait for it to exit
+    /// before killing it, so a switch only completes once the callback did
+    /// (see `--callback-sync`)
+    pub timeout: Option<Duration>,
+}
+
+/// Runs a callback executable by spawning a child with the following
+/// arguments:
+/// ```text
 /// -p <PROFILE> [-a <APP_NAME>] [-t <TITLE>] [-n <PROCESS_NAME>]
 /// ```
+/// The same information and more is available to the child in the environment
+/// variables `DUCKYPAD_PROFILE`, `DUCKYPAD_PREV_PROFILE` (empty if unknown),
+/// `DUCKYPAD_RULE` (index of the matching rule in the config file, empty for
+/// the `default_profile`), `DUCKYPAD_APP_NAME`, `DUCKYPAD_TITLE`, `DUCKYPAD_PROCESS_NAME`,
+/// `DUCKYPAD_PROCESS_PATH`, `DUCKYPAD_PROCESS_ID`, `DUCKYPAD_WINDOW_ID` and
+/// `DUCKYPAD_WINDOW_X`, `DUCKYPAD_WINDOW_Y`, `DUCKYPAD_WINDOW_WIDTH`,
+/// `DUCKYPAD_WINDOW_HEIGHT` (all `0` if the position is unknown).
 ///
 /// # Arguments
 ///
-/// * `callback` - optional callback script to run on change
-/// * `profile` - id of the profile on the duckypad (1 <= id <= 31)
+/// * `callback` - callback executable to run on change, waited for if it has a
+///   timeout
+/// * `profile` - id of the profile on the duckypad (see `PROFILES`)
+/// * `prev_profile` - id of the profile before the switch, if known
+/// * `rule` - index of the matching rule (see `Match::rule`)
 /// * `window` - information about the active window
-pub fn run_callback(callback: &mut Command, profile: u32, window: ActiveWindow, app_name: &String) {
-    let mut callback = callback.arg("-p").arg(profile.to_string());
+pub fn run_callback(
+    callback: &Callback,
+    profile: u32,
+    prev_profile: Option<u32>,
+    rule: Option<usize>,
+    window: &WindowInfo,
+) {
+    let mut command = Command::new(&callback.path);
+    command.arg("-p").arg(profile.to_string());
 
-    if !app_name.is_empty() {
-        callback = callback.arg("-a").arg(app_name);
+    if !window.app_name.is_empty() {
+        command.arg("-a").arg(&window.app_name);
     }
     if !window.title.is_empty() {
-        callback = callback.arg("-t").arg(window.title);
+        command.arg("-t").arg(&window.title);
     }
-    if !window.app_name.is_empty() {
-        callback = callback.arg("-n").arg(window.app_name);
+    if !window.process_name.is_empty() {
+        command.arg("-n").arg(&window.process_name);
     }
 
-    match callback.spawn() {
+    let position = window
+        .position
+        .clone()
+        .unwrap_or(WindowPosition::new(0.0, 0.0, 0.0, 0.0));
+    command
+        .env("DUCKYPAD_PROFILE", profile.to_string())
+        .env(
+            "DUCKYPAD_PREV_PROFILE",
+            prev_profile
+                .map(|profile| profile.to_string())
+                .unwrap_or_default(),
+        )
+        .env(
+            "DUCKYPAD_RULE",
+            rule.map(|rule| rule.to_string()).unwrap_or_default(),
+        )
+        .env("DUCKYPAD_APP_NAME", &window.app_name)
+        .env("DUCKYPAD_TITLE", &window.title)
+        .env("DUCKYPAD_PROCESS_NAME", &window.process_name)
+        .env("DUCKYPAD_PROCESS_PATH", &window.process_path)
+        .env("DUCKYPAD_PROCESS_ID", window.process_id.to_string())
+        .env("DUCKYPAD_WINDOW_ID", &window.window_id)
+        .env("DUCKYPAD_WINDOW_X", position.x.to_string())
+        .env("DUCKYPAD_WINDOW_Y", position.y.to_string())
+        .env("DUCKYPAD_WINDOW_WIDTH", position.width.to_string())
+        .env("DUCKYPAD_WINDOW_HEIGHT", position.height.to_string());
+
+    match callback.timeout {
+        Some(timeout) => run_with_timeout(&mut command, "callback", timeout),
+        None => spawn_detached(&mut command, "callback"),
+    }
+}
+
+/// Runs the executable `callback` when the duckypad with the information
+/// `info` got connected or disconnected by spawning a child with the following
+/// arguments:
+/// ```text
+/// -s <SERIAL> -m <MODEL>
+/// ```
+/// `MODEL` is the detected model (e.g. `duckyPad Pro`).
+pub fn run_device_callback(callback: &Path, info: &hid::DuckyPadInfo) {
+    let mut command = Command::new(callback);
+    command
+        .arg("-s")
+        .arg(&info.serial)
+        .arg("-m")
+        .arg(info.detected_model.name);
+
+    spawn_detached(&mut command, "device callback");
+}
+
+/// Spawns `command` without waiting for it to finish. A thread reaps the child
+/// once it exits and failing to spawn it is only logged.
+fn spawn_detached(command: &mut Command, name: &str) {
+    match command.spawn() {
         Ok(mut child) => {
             std::thread::spawn(move || {
                 let _: Result<_, _> = child.wait();
             });
         }
         Err(err) => {
-            eprintln!("Failed to run callback: {err}");
+            tracing::error!("Failed to run {name}: {err}");
         }
-    };
+    }
 }
 
-fn get_app_name(sys: &mut Option<System>, pid: Pid) -> Option<String> {
-    if let Some(sys) = sys {
-        sys.refresh_process_specifics(pid, ProcessRefreshKind::new());
-        let process = sys.process(pid);
+/// Runs `command` and waits up to `timeout` for it to exit, killing it
+/// afterwards. Its exit status is logged, failing to spawn or wait for it is
+/// only logged.
+fn run_with_timeout(command: &mut Command, name: &str, timeout: Duration) {
+    let mut child = match command.spawn() {
+        Ok(child) => child,
+        Err(err) => {
+            tracing::error!("Failed to run {name}: {err}");
+            return;
+        }
+    };
 
-        if let Some(process) = process {
-            return Some(process.name().to_string());
+    let deadline = Instant::now() + timeout;
+    loop {
+        match child.try_wait() {
+            Ok(Some(status)) if status.success() => {
+                tracing::debug!("The {name} exited with {status}");
+                return;
+            }
+            Ok(Some(status)) => {
+                tracing::warn!("The {name} exited with {status}");
+                return;
+            }
+            Ok(None) if Instant::now() >= deadline => break,
+            Ok(None) => std::thread::sleep(CALLBACK_POLL_INTERVAL),
+            Err(err) => {
+                tracing::error!("Failed to wait for the {name}: {err}");
+                return;
+            }
         }
     }
 
-    None
+    tracing::warn!(
+        "The {name} didn't exit within {} seconds, killing it",
+        timeout.as_secs_f64()
+    );
+    if let Err(err) = child.kill() {
+        tracing::error!("Failed to kill the {name}: {err}");
+    }
+    let _: Result<_, _> = child.wait();
 }
 
-/// Switch to the `profile` by sending a HID message to the duckypad.
-///
+/// How long `Processes::lookup` reuses the process of the same window before
+/// refreshing it, e.g. to notice an `exec` of the process.
+const PROCESS_CACHE_TTL: Duration = Duration::from_secs(5);
+
+/// Looks up the processes of active windows via `sysinfo` (if the platform
+/// supports it). The focused window rarely changes between two polls, so the
+/// last lookup is reused until the window or its process id changes (a
+/// process id can be reused by the OS once the process exited) or
+/// `PROCESS_CACHE_TTL` elapsed.
+pub struct Processes {
+    sys: Option<System>,
+    cached: Option<CachedProcess>,
+}
+
+/// The process of a window looked up by `Processes::lookup`.
+struct CachedProcess {
+    pid: Pid,
+    window_id: String,
+    name: String,
+    path: PathBuf,
+    cmdline: Vec<String>,
+    since: Instant,
+}
+
+impl Processes {
+    pub fn new() -> Self {
+        let sys = System::IS_SUPPORTED.then(|| {
+            System::new_with_specifics(RefreshKind::new().with_processes(ProcessRefreshKind::new()))
+        });
+
+        Self { sys, cached: None }
+    }
+
+    /// Returns the name, the path of the executable and the command line of
+    /// the process `pid`, which owns the window `window_id`.
+    fn lookup(&mut self, pid: Pid, window_id: &str) -> Option<(String, PathBuf, Vec<String>)> {
+        if let Some(cached) = self.cached.as_ref().filter(|cached| {
+            cached.pid == pid
+                && cached.window_id == window_id
+                && cached.since.elapsed() < PROCESS_CACHE_TTL
+        }) {
+            return Some((
+                cached.name.clone(),
+                cached.path.clone(),
+                cached.cmdline.clone(),
+            ));
+        }
+
+        let sys = self.sys.as_mut()?;
+        sys.refresh_process_specifics(pid, ProcessRefreshKind::new());
+        let process = sys.process(pid);
+        let Some(process) = process else {
+            self.cached = None;
+            return None;
+        };
+
+        let (name, path, cmdline) = (
+            process.name().to_string(),
+            process.exe().to_path_buf(),
+            process.cmd().to_vec(),
+        );
+        self.cached = Some(CachedProcess {
+            pid,
+            window_id: window_id.to_string(),
+            name: name.clone(),
+            path: path.clone(),
+            cmdline: cmdline.clone(),
+            since: Instant::now(),
+        });
+        Some((name, path, cmdline))
+    }
+}
+
+impl Default for Processes {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Time to wait before retrying a profile switch the duckypad was too busy for.
+const BUSY_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// Switch to the `profile` by sending a HID message to the duckypad, or put
+/// the duckypad to sleep if `profile` is `SLEEP`. If the duckypad replies that
+/// it is busy (e.g. still switching to the previous profile), the switch is
+/// retried once after `BUSY_RETRY_DELAY`. If its firmware doesn't support the
+/// sleep command (see `hid::Feature`), sleeping is skipped with a warning.
+///
 /// # Arguments
 ///
-/// * `device` - connected duckypad hid device
-/// * `profile` - id of the profile on the duckypad (1 <= id <= 31)
+/// * `device` - connected duckypad hid device (or any other transport)
+/// * `profile` - id of the profile on the duckypad (see `PROFILES`) or `SLEEP`
 ///
 /// # Errors
 ///
-/// Will return `HidError` if writing to or the follow-up reading from the
-/// duckypad `HidDevice` fails.
-///
-/// # Panics
-///
-/// The function will panic if `profile` is not a value in `(1..=31)`.
-pub fn goto_profile(device: &hidapi::HidDevice, profile: u32) -> Result<(), hidapi::HidError> {
-    println!("Switching to profile {profile}");
-    let mut buf = [0x00; hid::PC_TO_DUCKYPAD_HID_BUF_SIZE];
-    let profile_buf = profile.to_le_bytes();
+/// Will return `HidError` if `profile` is not a value in
+/// `1..=device.max_profiles()` (or `SLEEP`), if
+/// writing to or the follow-up reading from the duckypad `HidDevice` fails or
+/// if the duckypad doesn't acknowledge the switch.
+pub fn goto_profile<T: DuckyPadTransport + ?Sized>(
+    device: &T,
+    profile: u32,
+) -> Result<(), HidError> {
+    if profile == SLEEP {
+        if !device.supports(hid::Feature::Sleep) {
+            tracing::warn!(
+                "The firmware of the duckyPad doesn't support sleeping (requires {} or newer), keeping the profile",
+                hid::format_version(hid::Feature::Sleep.min_firmware())
+            );
+            return Ok(());
+        }
+
+        tracing::info!("Putting the duckyPad to sleep");
+        return acknowledged(|| device.write_sleep(), "go to sleep");
+    }
 
-    buf[0] = 0x05;
-    buf[2] = 0x01;
+    let max_profiles = device.max_profiles();
 
-    for (i, p) in profile_buf.iter().enumerate() {
-        buf[3 + i] = *p;
+    if !(1..=max_profiles).contains(&profile) {
+        return Err(HidError::HidApiError {
+            message: format!(
+                "Invalid profile {profile}: profiles of this duckyPad need to be within 1..={max_profiles}"
+            ),
+        });
     }
 
-    hid::write(device, buf)?;
-    Ok(())
+    tracing::info!("Switching to profile {profile}");
+
+    acknowledged(
+        || device.write_profile(profile),
+        &format!("switch to profile {profile}"),
+    )
+}
+
+/// Sends a command to the duckypad with `write` and checks the status of its
+/// reply. A busy duckypad gets the command again once after
+/// `BUSY_RETRY_DELAY`. `action` describes the command in errors.
+fn acknowledged(
+    mut write: impl FnMut() -> Result<hid::Status, HidError>,
+    action: &str,
+) -> Result<(), HidError> {
+    let mut status = write()?;
+    if status == hid::Status::Busy {
+        tracing::debug!("duckyPad is busy, retrying to {action}");
+        std::thread::sleep(BUSY_RETRY_DELAY);
+        status = write()?;
+    }
+
+    match status {
+        hid::Status::Ok => Ok(()),
+        hid::Status::Busy => Err(HidError::HidApiError {
+            message: format!("duckyPad is still busy, couldn't {action}"),
+        }),
+        status => Err(HidError::HidApiError {
+            message: format!("duckyPad refused to {action} ({status})"),
+        }),
+    }
+}
+
+/// State of the desktop besides the active window that rules can depend on.
+/// An unknown idle time or monitor never matches the rules depending on it.
+#[derive(Default)]
+pub struct DesktopState {
+    /// time since the last user input (see `idle_after`)
+    pub idle: Option<Duration>,
+    /// whether no window is focused (see `empty_desktop`)
+    pub empty: bool,
+    /// index of the monitor the active window is on (see `monitor`)
+    pub monitor: Option<u32>,
 }
 
 /// Returns the id of the profile to switch to based on the active X11 window
-/// and the config entries.
+/// and the config entries. Rules are checked by descending `priority` (rules
+/// without one have a priority of 0) and in file order for equal priorities.
+/// Rules with an `active_time` only match within that time window and rules
+/// with `idle_after` only once there was no input for that many seconds, rules
+/// with a `monitor` only if the active window is on that monitor, rules with
+/// `fullscreen` only if the window's fullscreen state is equal to it. A rule
+/// whose patterns match is still skipped if any of its `exclude` matchers
+/// matches as well. If no rule matches, the `default_profile` of the config is
+/// returned instead.
+///
+/// While no window is focused (see `DesktopState::empty`), only rules with
+/// `empty_desktop` are checked, ignoring their patterns and exclusions, and
+/// the `default_profile` isn't returned, so the profile is kept if none of them
+/// matches. Rules with `empty_desktop` never match a window.
 ///
 /// # Arguments
 ///
 /// * `config` - serde Value of the current configuration
 /// * `window` - information about the active window
-pub fn next_profile(config: &Config, window: &ActiveWindow, app_name: &str) -> Option<u32> {
+/// * `desktop` - state of the desktop that rules can depend on besides the
+///   active window
+pub fn next_profile(
+    config: &Config,
+    window: &WindowInfo,
+    desktop: &DesktopState,
+) -> Option<Target> {
+    next_match(config, window, desktop).map(|matched| matched.target)
+}
+
+/// A profile returned by `next_match`.
+pub struct Match {
+    /// what to switch to
+    pub target: Target,
+    /// index of the matching rule in the config file, `None` if it is the
+    /// `default_profile`
+    pub rule: Option<usize>,
+}
+
+/// Target of the log messages explaining for every rule why it did or didn't
+/// match (see `--explain`), logged at debug level.
+pub const EXPLAIN_TARGET: &str = "duckypad_daemon::explain";
+
+/// Returns the profile to switch to like `next_profile` together with the
+/// rule that matched. Why each checked rule did or didn't match is logged at
+/// debug level with the target `EXPLAIN_TARGET`.
+pub fn next_match(config: &Config, window: &WindowInfo, desktop: &DesktopState) -> Option<Match> {
+    let now = chrono::Local::now().naive_local();
+
     for rule in &config.rules_list {
-        if rule.enabled
-            && (rule.app_name.is_empty() || app_name.contains(&rule.app_name))
-            && (rule.window_title.is_empty() || window.title.contains(&rule.window_title))
-            && match &rule.process_name {
-                Some(process_name) => {
-                    process_name.is_empty() || window.app_name.contains(process_name)
-                }
-                None => true,
+        match rule.check(window, desktop, now) {
+            Ok(()) => {
+                tracing::debug!(
+                    target: EXPLAIN_TARGET,
+                    "Rule {} matches ({})",
+                    rule.index,
+                    rule.target
+                );
+                return Some(Match {
+                    target: rule.target,
+                    rule: Some(rule.index),
+                });
             }
-        {
-            return Some(rule.switch_to);
+            Err(mismatch) => {
+                tracing::debug!(
+                    target: EXPLAIN_TARGET,
+                    "Rule {} doesn't match, as {mismatch}",
+                    rule.index
+                );
+            }
+        }
+    }
+
+    if let Some(profile) = config.default_profile.filter(|_| !desktop.empty) {
+        tracing::debug!(
+            target: EXPLAIN_TARGET,
+            "No rule matches, using the default_profile {profile}"
+        );
+    }
+
+    config
+        .default_profile
+        .filter(|_| !desktop.empty)
+        .map(|profile| Match {
+            target: Target::Profile(profile),
+            rule: None,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hid::{goto_profile_frame, FakeTransport, Status};
+    use Target::Profile;
+
+    /// Parses and prepares the JSON config `raw` like `read_config` does.
+    fn config(raw: &str) -> Config {
+        let mut config: Config = parse_json(raw).expect("invalid config");
+        config.prepare().expect("invalid config");
+        config
+    }
+
+    /// Returns a path in the temporary directory that no other test uses.
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "duckypad_daemon-test-{}-{name}",
+            std::process::id()
+        ))
+    }
+
+    /// A config that switches to profile 3 for firefox.
+    fn firefox_config() -> Config {
+        config(
+            r#"{"autoswitch_enabled": true, "rules_list": [
+                {"enabled": true, "app_name": "firefox", "title": "", "switch_to": 3}
+            ]}"#,
+        )
+    }
+
+    /// A window source that always reports the same window.
+    struct FakeWindow(WindowInfo);
+
+    impl WindowProvider for FakeWindow {
+        fn active(&mut self) -> Result<WindowInfo, WindowError> {
+            Ok(self.0.clone())
         }
     }
 
-    None
+    /// Returns a window of `app_name` without a process, so its process name
+    /// is used as the app name.
+    fn window(app_name: &str) -> FakeWindow {
+        FakeWindow(WindowInfo {
+            process_name: app_name.to_string(),
+            title: format!("{app_name} window"),
+            window_id: "1".to_string(),
+            ..WindowInfo::default()
+        })
+    }
+
+    /// Returns a window of the app `app_name`, as `next_match` sees it.
+    fn app(app_name: &str) -> WindowInfo {
+        WindowInfo {
+            app_name: app_name.to_string(),
+            ..window(app_name).0
+        }
+    }
+
+    fn info_frame() -> [u8; hid::PC_TO_DUCKYPAD_HID_BUF_SIZE] {
+        hid::frame(hid::INFO_COMMAND, &[])
+    }
+
+    fn switch(
+        device: &FakeTransport,
+        config: &Config,
+        prev_profile: Option<u32>,
+        window: &mut FakeWindow,
+    ) -> Result<SwitchResult, HidError> {
+        switch_profile(
+            Some(device),
+            &mut Processes::new(),
+            config,
+            prev_profile,
+            None,
+            window,
+            false,
+            None,
+            None,
+            None,
+        )
+    }
+
+    #[test]
+    fn switch_profile_switches_to_the_matching_profile() {
+        let device = FakeTransport::new();
+        let result = switch(&device, &firefox_config(), Some(1), &mut window("firefox")).unwrap();
+
+        assert!(matches!(&result, SwitchResult::Switched(event) if event.rule == Some(0)));
+        assert_eq!(result.profile(), Some(3));
+        assert_eq!(device.sent(), [info_frame(), goto_profile_frame(3)]);
+    }
+
+    #[test]
+    fn switch_profile_skips_the_profile_the_device_is_on() {
+        let device = FakeTransport::new().profile_reply(Ok(3));
+        let result = switch(&device, &firefox_config(), Some(1), &mut window("firefox")).unwrap();
+
+        assert!(matches!(result, SwitchResult::Kept(Some(3))));
+        assert_eq!(device.sent(), [info_frame()]);
+    }
+
+    #[test]
+    fn switch_profile_switches_if_the_profile_can_not_be_read() {
+        let device = FakeTransport::new().profile_reply(Err(HidError::HidApiError {
+            message: "duckyPad didn't reply to info request".to_string(),
+        }));
+        let result = switch(&device, &firefox_config(), Some(1), &mut window("firefox")).unwrap();
+        assert_eq!(result.profile(), Some(3));
+        assert_eq!(device.sent(), [info_frame(), goto_profile_frame(3)]);
+
+        let device = FakeTransport {
+            version: Some((0, 12, 0)),
+            ..FakeTransport::new()
+        };
+        let result = switch(&device, &firefox_config(), Some(1), &mut window("firefox")).unwrap();
+        assert_eq!(result.profile(), Some(3));
+        assert_eq!(device.sent(), [goto_profile_frame(3)]);
+    }
+
+    #[test]
+    fn switch_profile_returns_disconnects_while_reading() {
+        let device = FakeTransport::new().profile_reply(Err(HidError::HidApiError {
+            message: "No such device".to_string(),
+        }));
+
+        assert!(switch(&device, &firefox_config(), Some(1), &mut window("firefox")).is_err());
+        assert_eq!(device.sent(), [info_frame()]);
+    }
+
+    #[test]
+    fn switch_profile_keeps_the_current_profile() {
+        let device = FakeTransport::new();
+        let config = firefox_config();
+
+        let result = switch(&device, &config, Some(3), &mut window("firefox")).unwrap();
+        assert!(matches!(result, SwitchResult::Kept(Some(3))));
+
+        let result = switch(&device, &config, Some(1), &mut window("thunderbird")).unwrap();
+        assert!(matches!(result, SwitchResult::Unmatched(Some(1))));
+
+        assert!(device.sent().is_empty());
+    }
+
+    #[test]
+    fn switch_profile_tells_unmatched_windows_from_kept_profiles() {
+        let device = FakeTransport::new();
+        let ignoring = config(
+            r#"{"rules_list": [
+                {"enabled": true, "app_name": "", "title": "", "switch_to": 3}
+            ], "ignore_list": [{"app_name": "steam"}]}"#,
+        );
+        let result = switch(&device, &ignoring, Some(1), &mut window("steam")).unwrap();
+        assert!(matches!(result, SwitchResult::Kept(Some(1))));
+
+        let default = config(r#"{"rules_list": [], "default_profile": 2}"#);
+        let result = switch(&device, &default, Some(2), &mut window("steam")).unwrap();
+        assert!(matches!(result, SwitchResult::Kept(Some(2))));
+
+        let empty = config(r#"{"rules_list": []}"#);
+        let result = switch(&device, &empty, Some(2), &mut window("steam")).unwrap();
+        assert!(matches!(result, SwitchResult::Unmatched(Some(2))));
+    }
+
+    #[test]
+    fn switch_profile_respects_autoswitch_enabled() {
+        let device = FakeTransport::new();
+        let rules = r#""rules_list": [
+            {"enabled": true, "app_name": "firefox", "title": "", "switch_to": 3}
+        ]"#;
+
+        let disabled = config(&format!(r#"{{"autoswitch_enabled": false, {rules}}}"#));
+        let result = switch(&device, &disabled, Some(1), &mut window("firefox")).unwrap();
+        assert!(matches!(result, SwitchResult::Kept(Some(1))));
+        assert!(device.sent().is_empty());
+
+        // a missing field counts as enabled
+        let missing = config(&format!("{{{rules}}}"));
+        let result = switch(&device, &missing, Some(1), &mut window("firefox")).unwrap();
+        assert_eq!(result.profile(), Some(3));
+    }
+
+    #[test]
+    fn default_config_enables_autoswitching() {
+        let path = temp_path("default.json");
+        create_default_config(&path);
+        let config = read_config(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(config.autoswitch_enabled());
+        assert!(config.rules_list.is_empty());
+    }
+
+    #[test]
+    fn parse_window_reports_missing_and_invalid_fields() {
+        assert!(matches!(
+            parse_window(r#"{"process_name": "firefox"}"#),
+            Err(WindowScriptError::MissingField("title"))
+        ));
+        assert!(matches!(
+            parse_window(r#"{"title": "Mozilla Firefox"}"#),
+            Err(WindowScriptError::MissingField("process_name"))
+        ));
+        assert!(matches!(
+            parse_window(r#"{"title": "", "process_name": "", "process_id": -1}"#),
+            Err(WindowScriptError::InvalidField("process_id", _))
+        ));
+        assert!(matches!(
+            parse_window("Traceback (most recent call last):"),
+            Err(WindowScriptError::Json(_))
+        ));
+
+        let window = parse_window(r#"{"title": "Inbox", "process_name": "thunderbird"}"#).unwrap();
+        assert_eq!(
+            (window.title.as_str(), window.process_name.as_str()),
+            ("Inbox", "thunderbird")
+        );
+    }
+
+    /// Writes an executable shell script with `body` to the temporary
+    /// directory.
+    #[cfg(unix)]
+    fn script(name: &str, body: &str) -> PathBuf {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = temp_path(name);
+        std::fs::write(&path, format!("#!/bin/sh\n{body}\n")).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn custom_active_window_reports_malformed_output() {
+        // all written before any is run, as running a script while another
+        // one is being written can fail with "text file busy"
+        let non_utf8 = script("non-utf8.sh", r"printf '\377\376'");
+        let failing = script("failing.sh", "echo 'swaymsg: no socket' >&2; exit 3");
+        let missing = script("missing.sh", r#"echo '{"title": "Inbox"}'"#);
+
+        assert!(matches!(
+            custom_active_window(&non_utf8),
+            Err(WindowScriptError::NonUtf8(_))
+        ));
+        assert!(matches!(
+            custom_active_window(&failing),
+            Err(WindowScriptError::Failed(_, stderr)) if stderr == "swaymsg: no socket"
+        ));
+        assert!(matches!(
+            custom_active_window(&missing),
+            Err(WindowScriptError::MissingField("process_name"))
+        ));
+
+        // the daemon keeps the profile instead of crashing
+        let device = FakeTransport::new();
+        let result = switch_profile(
+            Some(&device),
+            &mut Processes::new(),
+            &firefox_config(),
+            Some(1),
+            None,
+            &mut window_script::WindowScript::Oneshot(missing.clone()),
+            false,
+            None,
+            None,
+            None,
+        )
+        .unwrap();
+        assert!(matches!(result, SwitchResult::Kept(Some(1))));
+
+        for script in [non_utf8, failing, missing] {
+            std::fs::remove_file(script).unwrap();
+        }
+    }
+
+    #[test]
+    fn switch_profile_returns_hid_errors() {
+        let device = FakeTransport::new().reply(Err(HidError::HidApiError {
+            message: "No such device".to_string(),
+        }));
+
+        assert!(switch(&device, &firefox_config(), Some(1), &mut window("firefox")).is_err());
+    }
+
+    #[test]
+    fn switch_profile_resolves_relative_targets_against_the_device() {
+        let device = FakeTransport::new().profile_reply(Ok(5));
+        let config = config(
+            r#"{"rules_list": [
+                {"enabled": true, "app_name": "firefox", "title": "", "switch_to": "next"}
+            ]}"#,
+        );
+
+        let result = switch(&device, &config, Some(1), &mut window("firefox")).unwrap();
+        assert_eq!(result.profile(), Some(6));
+        assert_eq!(device.sent(), [info_frame(), goto_profile_frame(6)]);
+    }
+
+    #[test]
+    fn goto_profile_retries_once_if_busy() {
+        let device = FakeTransport::new().reply(Ok(Status::Busy));
+
+        goto_profile(&device, 2).unwrap();
+        assert_eq!(
+            device.sent(),
+            [goto_profile_frame(2), goto_profile_frame(2)]
+        );
+        assert_eq!(device.profile.get(), 2);
+    }
+
+    #[test]
+    fn goto_profile_fails_if_still_busy() {
+        let device = FakeTransport::new()
+            .reply(Ok(Status::Busy))
+            .reply(Ok(Status::Busy))
+            .reply(Ok(Status::Ok));
+
+        let err = goto_profile(&device, 2).unwrap_err();
+        assert!(err.to_string().contains("still busy"));
+        assert_eq!(device.sent().len(), 2);
+    }
+
+    #[test]
+    fn goto_profile_fails_if_refused() {
+        let device = FakeTransport::new().reply(Ok(Status::Error));
+
+        let err = goto_profile(&device, 2).unwrap_err();
+        assert!(err.to_string().contains("refused"));
+        assert_eq!(device.sent().len(), 1);
+    }
+
+    #[test]
+    fn goto_profile_rejects_profiles_of_other_models() {
+        let device = FakeTransport::new();
+
+        assert!(goto_profile(&device, 32).is_err());
+        assert!(device.sent().is_empty());
+    }
+
+    #[test]
+    fn goto_profile_sleeps_if_supported() {
+        let device = FakeTransport::new();
+        goto_profile(&device, SLEEP).unwrap();
+        assert_eq!(device.sent(), [hid::frame(hid::SLEEP_COMMAND, &[])]);
+
+        let device = FakeTransport {
+            version: Some((0, 19, 0)),
+            ..FakeTransport::new()
+        };
+        goto_profile(&device, SLEEP).unwrap();
+        assert!(device.sent().is_empty());
+    }
+
+    #[test]
+    fn next_profile_falls_back_to_the_default_profile() {
+        let with_default = config(
+            r#"{"autoswitch_enabled": true, "default_profile": 1, "rules_list": [
+                {"enabled": true, "app_name": "firefox", "title": "", "switch_to": 3}
+            ]}"#,
+        );
+        let desktop = DesktopState::default();
+
+        let matched = next_match(&with_default, &app("firefox"), &desktop).unwrap();
+        assert_eq!((matched.target, matched.rule), (Profile(3), Some(0)));
+        let unmatched = next_match(&with_default, &app("thunderbird"), &desktop).unwrap();
+        assert_eq!((unmatched.target, unmatched.rule), (Profile(1), None));
+
+        assert_eq!(
+            next_profile(&firefox_config(), &app("thunderbird"), &desktop),
+            None
+        );
+        let empty = DesktopState {
+            empty: true,
+            ..DesktopState::default()
+        };
+        assert_eq!(
+            next_profile(&with_default, &WindowInfo::default(), &empty),
+            None
+        );
+    }
+
+    #[test]
+    fn default_profile_must_be_a_profile() {
+        for profile in [0, hid::MAX_PROFILES + 1] {
+            let mut invalid: Config = parse_json(&format!(
+                r#"{{"autoswitch_enabled": true, "default_profile": {profile}, "rules_list": []}}"#
+            ))
+            .unwrap();
+            assert!(matches!(
+                invalid.prepare(),
+                Err(ConfigError::Profile(location, p)) if location == "default_profile" && p == profile
+            ));
+        }
+    }
+
+    /// Returns the error of parsing the invalid JSON config `raw`.
+    fn parse_error(raw: &str) -> ConfigError {
+        let Err(err) = parse_json::<Config>(raw) else {
+            panic!("valid config: {raw}");
+        };
+        err
+    }
+
+    /// Returns the error of preparing the JSON config `raw`, which has to
+    /// parse.
+    fn prepare_error(raw: &str) -> ConfigError {
+        let mut config: Config = parse_json(raw).expect("invalid config");
+        let Err(err) = config.prepare() else {
+            panic!("valid config: {raw}");
+        };
+        err
+    }
+
+    #[test]
+    fn config_errors_mention_the_line() {
+        let err = parse_error("{\n  \"autoswitch_enabled\": true,\n  \"rules_list\": 3\n}");
+        assert!(matches!(err, ConfigError::Parse(_)));
+        assert!(err.to_string().contains("line 3"), "{err}");
+
+        let err = parse_error("{\n  \"autoswitch_enabled\": true,\n  \"rules_list\": [}\n}");
+        assert!(matches!(err, ConfigError::Json5(_)));
+        assert!(err.to_string().contains("line 3"), "{err}");
+    }
+
+    #[test]
+    fn read_config_returns_errors() {
+        let path = temp_path("invalid.json");
+        std::fs::write(&path, "{\"autoswitch_enabled\": yes}").unwrap();
+        let invalid = read_config(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(invalid.is_err());
+        assert!(matches!(read_config(&path), Err(ConfigError::Io(_))));
+    }
+
+    #[test]
+    fn rules_switching_to_missing_profiles_are_refused() {
+        let rule = |switch_to: &str| {
+            format!(
+                r#"{{"autoswitch_enabled": true, "rules_list": [
+                    {{"enabled": true, "app_name": "firefox", "switch_to": {switch_to}}}
+                ]}}"#
+            )
+        };
+
+        let mut zero: Config = parse_json(&rule("0")).unwrap();
+        assert!(matches!(zero.prepare(), Err(ConfigError::Profile(_, 0))));
+        assert_eq!(
+            config(&rule("\"sleep\"")).rules_list[0].target,
+            Target::Sleep
+        );
+
+        // 32 is a valid profile id, but not one of a duckyPad with 31 profiles
+        let config = config(&rule("32"));
+        assert!(matches!(
+            config.check_max_profiles(31),
+            Err(ConfigError::MissingProfiles(locations, 31)) if locations == ["rule 0 (profile 32)"]
+        ));
+        let device = FakeTransport::new();
+        assert!(switch(&device, &config, Some(1), &mut window("firefox")).is_err());
+        assert!(device.sent().iter().all(|frame| *frame == info_frame()));
+    }
+
+    #[test]
+    fn toml_configs_round_trip() {
+        let path = temp_path("config.toml");
+        std::fs::write(
+            &path,
+            r#"
+# rules are checked by priority
+autoswitch_enabled = true
+default_profile = 1
+
+[profile_names]
+browser = 3
+
+[[rules_list]]
+enabled = true
+app_name = "firefox"
+window_title = ["Meet", "Zoom"]
+switch_to = 2
+priority = 10
+
+[[rules_list]]
+enabled = true
+app_name = "firefox"
+switch_to = "browser"
+
+[[rules_list.exclude]]
+window_title = "Private"
+"#,
+        )
+        .unwrap();
+        let parsed = read_config(&path);
+        std::fs::remove_file(&path).unwrap();
+        let parsed = parsed.unwrap();
+
+        let mut round_trip: Config = toml::from_str(&toml::to_string(&parsed).unwrap()).unwrap();
+        round_trip.prepare().unwrap();
+        assert_eq!(
+            serde_json::to_value(&round_trip).unwrap(),
+            serde_json::to_value(&parsed).unwrap()
+        );
+
+        let desktop = DesktopState::default();
+        let titled = |title: &str| WindowInfo {
+            title: title.to_string(),
+            ..app("firefox")
+        };
+        for config in [&parsed, &round_trip] {
+            assert_eq!(
+                next_profile(config, &titled("Meet - Call"), &desktop),
+                Some(Profile(2))
+            );
+            assert_eq!(
+                next_profile(config, &titled("Mozilla Firefox"), &desktop),
+                Some(Profile(3))
+            );
+            assert_eq!(
+                next_profile(config, &titled("Private Browsing"), &desktop),
+                Some(Profile(1))
+            );
+        }
+    }
+
+    #[test]
+    fn higher_priority_rules_win_regardless_of_order() {
+        let config = config(
+            r#"{"autoswitch_enabled": true, "rules_list": [
+                {"enabled": true, "app_name": "firefox", "switch_to": 2},
+                {"enabled": true, "app_name": "", "window_title": "Meet", "switch_to": 4, "priority": 5},
+                {"enabled": true, "app_name": "firefox", "window_title": "Meet", "switch_to": 3}
+            ]}"#,
+        );
+        let desktop = DesktopState::default();
+        let meet = WindowInfo {
+            title: "Meet - Mozilla Firefox".to_string(),
+            ..app("firefox")
+        };
+
+        let matched = next_match(&config, &meet, &desktop).unwrap();
+        assert_eq!((matched.target, matched.rule), (Profile(4), Some(1)));
+        // equal priorities keep the order of the file
+        let matched = next_match(&config, &app("firefox"), &desktop).unwrap();
+        assert_eq!((matched.target, matched.rule), (Profile(2), Some(0)));
+    }
+
+    #[test]
+    fn exclusions_veto_matching_rules() {
+        let config = config(
+            r#"{"autoswitch_enabled": true, "rules_list": [
+                {"enabled": true, "app_name": "firefox", "switch_to": 2,
+                 "exclude": [{"window_title": "Meet"}, {"process_name": "firefox-esr"}]},
+                {"enabled": true, "app_name": "firefox", "switch_to": 3}
+            ]}"#,
+        );
+        let desktop = DesktopState::default();
+        let firefox = |title: &str, process_name: &str| WindowInfo {
+            title: title.to_string(),
+            process_name: process_name.to_string(),
+            ..app("firefox")
+        };
+
+        let passed = next_match(&config, &firefox("Mozilla Firefox", "firefox"), &desktop).unwrap();
+        assert_eq!((passed.target, passed.rule), (Profile(2), Some(0)));
+        // any exclusion vetoes the rule, so the next rule is checked
+        let vetoed = next_match(
+            &config,
+            &firefox("Meet - Mozilla Firefox", "firefox"),
+            &desktop,
+        )
+        .unwrap();
+        assert_eq!((vetoed.target, vetoed.rule), (Profile(3), Some(1)));
+        let vetoed = next_match(
+            &config,
+            &firefox("Mozilla Firefox", "firefox-esr"),
+            &desktop,
+        )
+        .unwrap();
+        assert_eq!((vetoed.target, vetoed.rule), (Profile(3), Some(1)));
+    }
+
+    #[test]
+    fn write_config_replaces_the_file_atomically() {
+        let path = temp_path("written.json");
+        std::fs::write(&path, "{\"autoswitch_enabled\": tr").unwrap();
+
+        write_config(&path, r#"{"autoswitch_enabled": false, "rules_list": []}"#).unwrap();
+        let written = read_config(&path);
+        let tmp_left = temp_path("written.json.tmp").exists();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(!written.unwrap().autoswitch_enabled());
+        assert!(!tmp_left);
+    }
+
+    #[test]
+    fn default_configs_are_valid_json_and_toml() {
+        let json = temp_path("created.json");
+        let toml = temp_path("created.toml");
+        create_default_config(&json);
+        create_default_config(&toml);
+        let raw = (
+            std::fs::read_to_string(&json),
+            std::fs::read_to_string(&toml),
+        );
+        std::fs::remove_file(&json).unwrap();
+        std::fs::remove_file(&toml).unwrap();
+
+        let (json, toml) = (raw.0.unwrap(), raw.1.unwrap());
+        assert!(serde_json::from_str::<Value>(&json).is_ok(), "{json}");
+        assert!(toml::from_str::<Config>(&toml).is_ok(), "{toml}");
+    }
+
+    #[test]
+    fn case_insensitive_rules_match_mixed_case_windows() {
+        let rules = |case_insensitive: &str, rule_case: &str| {
+            config(&format!(
+                r#"{{"autoswitch_enabled": true, {case_insensitive} "rules_list": [
+                    {{"enabled": true, "app_name": "firefox", "switch_to": 2 {rule_case}}},
+                    {{"enabled": true, "app_name": "", "window_title": "github", "switch_to": 3 {rule_case}}},
+                    {{"enabled": true, "app_name": "", "process_name": "thunderbird", "switch_to": 4 {rule_case}}}
+                ]}}"#
+            ))
+        };
+        let desktop = DesktopState::default();
+        let firefox = app("FireFox");
+        let github = WindowInfo {
+            title: "Pull requests - GitHub".to_string(),
+            ..app("chromium")
+        };
+        let thunderbird = WindowInfo {
+            process_name: "Thunderbird".to_string(),
+            ..app("mail")
+        };
+        let profiles = |config: &Config| {
+            [&firefox, &github, &thunderbird].map(|window| next_profile(config, window, &desktop))
+        };
+
+        assert_eq!(profiles(&rules("", "")), [None; 3]);
+        let insensitive = [Some(Profile(2)), Some(Profile(3)), Some(Profile(4))];
+        assert_eq!(
+            profiles(&rules(r#""case_insensitive": true,"#, "")),
+            insensitive
+        );
+        assert_eq!(
+            profiles(&rules("", r#", "case_insensitive": true"#)),
+            insensitive
+        );
+        // the rule overrides the config
+        assert_eq!(
+            profiles(&rules(
+                r#""case_insensitive": true,"#,
+                r#", "case_insensitive": false"#
+            )),
+            [None; 3]
+        );
+    }
+
+    #[test]
+    fn goto_profile_uses_the_profiles_of_the_model() {
+        for model in &hid::MODELS {
+            let device = FakeTransport {
+                max_profiles: model.max_profiles,
+                ..FakeTransport::new()
+            };
+
+            goto_profile(&device, model.max_profiles).unwrap();
+            assert!(goto_profile(&device, model.max_profiles + 1).is_err());
+            assert_eq!(
+                device.sent(),
+                [goto_profile_frame(
+                    u8::try_from(model.max_profiles).unwrap()
+                )]
+            );
+        }
+    }
+
+    #[test]
+    fn rules_match_any_of_their_patterns() {
+        let config = config(
+            r#"{"autoswitch_enabled": true, "rules_list": [
+                {"enabled": true, "app_name": ["firefox", "chromium"], "window_title": ["GitHub", "GitLab"], "switch_to": 2},
+                {"enabled": true, "app_name": "firefox", "window_title": "Codeberg", "switch_to": 3},
+                {"enabled": true, "app_name": "", "process_name": ["thunderbird", "evolution"], "switch_to": 4}
+            ]}"#,
+        );
+        let desktop = DesktopState::default();
+        let page = |app_name: &str, title: &str| WindowInfo {
+            title: title.to_string(),
+            ..app(app_name)
+        };
+
+        assert_eq!(
+            next_profile(&config, &page("firefox", "Issues - GitHub"), &desktop),
+            Some(Profile(2))
+        );
+        assert_eq!(
+            next_profile(
+                &config,
+                &page("chromium", "Merge requests - GitLab"),
+                &desktop
+            ),
+            Some(Profile(2))
+        );
+        assert_eq!(
+            next_profile(&config, &page("firefox", "Gitea"), &desktop),
+            None
+        );
+        assert_eq!(
+            next_profile(&config, &page("thunderbird", "GitHub"), &desktop),
+            Some(Profile(4))
+        );
+        assert_eq!(
+            next_profile(&config, &page("evolution", "Inbox"), &desktop),
+            Some(Profile(4))
+        );
+        // single strings still work
+        assert_eq!(
+            next_profile(&config, &page("firefox", "Explore - Codeberg"), &desktop),
+            Some(Profile(3))
+        );
+        assert_eq!(
+            next_profile(&config, &page("chromium", "Explore - Codeberg"), &desktop),
+            None
+        );
+    }
+
+    #[test]
+    fn ignored_windows_keep_the_profile() {
+        let config = config(
+            r#"{"autoswitch_enabled": true, "default_profile": 1,
+                "ignore_list": [{"app_name": "flameshot"}, {"app_name": "", "window_title": "KeePassXC"}],
+                "rules_list": [
+                    {"enabled": true, "app_name": "firefox", "switch_to": 3},
+                    {"enabled": true, "app_name": "thunderbird", "switch_to": 4}
+                ]}"#,
+        );
+        let device = FakeTransport::new();
+
+        let first = switch(&device, &config, None, &mut window("firefox")).unwrap();
+        assert_eq!(first.profile(), Some(3));
+        // without the ignore_list, both would switch to the default_profile
+        let ignored = switch(&device, &config, first.profile(), &mut window("flameshot")).unwrap();
+        assert!(matches!(ignored, SwitchResult::Kept(Some(3))));
+        let mut keepassxc = FakeWindow(WindowInfo {
+            title: "Passwords - KeePassXC".to_string(),
+            ..window("keepassxc").0
+        });
+        let ignored = switch(&device, &config, ignored.profile(), &mut keepassxc).unwrap();
+        assert!(matches!(ignored, SwitchResult::Kept(Some(3))));
+        let last = switch(
+            &device,
+            &config,
+            ignored.profile(),
+            &mut window("thunderbird"),
+        )
+        .unwrap();
+        assert_eq!(last.profile(), Some(4));
+
+        assert_eq!(
+            device.sent(),
+            [
+                info_frame(),
+                goto_profile_frame(3),
+                info_frame(),
+                goto_profile_frame(4)
+            ]
+        );
+    }
+
+    /// A window source of an X server that always reports the same window.
+    struct FakeX11Window(WindowInfo);
+
+    impl WindowProvider for FakeX11Window {
+        fn active(&mut self) -> Result<WindowInfo, WindowError> {
+            Ok(self.0.clone())
+        }
+
+        fn is_xorg(&self) -> bool {
+            true
+        }
+    }
+
+    #[test]
+    fn switch_profile_keeps_the_profile_without_a_focused_x11_window() {
+        let catch_all = config(
+            r#"{"autoswitch_enabled": true, "default_profile": 1, "rules_list": [
+                {"enabled": true, "app_name": "", "switch_to": 3}
+            ]}"#,
+        );
+        let empty_desktop = config(
+            r#"{"autoswitch_enabled": true, "rules_list": [
+                {"enabled": true, "app_name": "", "switch_to": 5, "empty_desktop": true}
+            ]}"#,
+        );
+        let device = FakeTransport::new();
+
+        // None and PointerRoot
+        for window_id in ["0", "1"] {
+            let mut focus = FakeX11Window(WindowInfo {
+                window_id: window_id.to_string(),
+                ..WindowInfo::default()
+            });
+            let mut next = |config: &Config| {
+                switch_profile(
+                    Some(&device),
+                    &mut Processes::new(),
+                    config,
+                    Some(2),
+                    None,
+                    &mut focus,
+                    false,
+                    None,
+                    None,
+                    None,
+                )
+                .unwrap()
+                .profile()
+            };
+
+            assert_eq!(next(&catch_all), Some(2));
+            assert_eq!(next(&empty_desktop), Some(5));
+        }
+    }
+
+    #[test]
+    fn json5_configs_can_be_commented() {
+        let config = config(
+            r"{
+                // toggled via the control socket
+                autoswitch_enabled: true,
+                rules_list: [
+                    /* browsers */
+                    {enabled: true, app_name: 'firefox', switch_to: 3,},
+                ],
+            }",
+        );
+
+        assert_eq!(
+            next_profile(&config, &app("firefox"), &DesktopState::default()),
+            Some(Profile(3))
+        );
+    }
+
+    #[test]
+    fn process_paths_expand_environment_variables() {
+        // cargo sets it while running the tests as well
+        let dir = env!("CARGO_MANIFEST_DIR");
+        let rule = |process_path: &str| {
+            format!(
+                r#"{{"autoswitch_enabled": true, "rules_list": [
+                    {{"enabled": true, "app_name": "app", "process_path": "{process_path}", "switch_to": 2}}
+                ]}}"#
+            )
+        };
+        let config = config(&rule("${CARGO_MANIFEST_DIR}/bin/app"));
+        let window = |process_path: PathBuf| WindowInfo {
+            process_path,
+            ..app("app")
+        };
+        let desktop = DesktopState::default();
+
+        assert_eq!(
+            next_profile(&config, &window(Path::new(dir).join("bin/app")), &desktop),
+            Some(Profile(2))
+        );
+        assert_eq!(
+            next_profile(&config, &window(PathBuf::from("/usr/bin/app")), &desktop),
+            None
+        );
+
+        let mut unset: Config = parse_json(&rule("${DUCKYPAD_DAEMON_UNSET}/app")).unwrap();
+        assert!(matches!(
+            unset.prepare(),
+            Err(ConfigError::EnvVar(location, name)) if location == "rule 0" && name == "DUCKYPAD_DAEMON_UNSET"
+        ));
+        // other dollar signs are kept, e.g. for regex anchors
+        assert_eq!(expand_env("^/opt/app$").unwrap(), "^/opt/app$");
+    }
+
+    #[test]
+    fn empty_desktop_rules_match_without_a_focused_window() {
+        let config = config(
+            r#"{"autoswitch_enabled": true, "default_profile": 1, "rules_list": [
+                {"enabled": true, "app_name": "", "switch_to": 6, "empty_desktop": true},
+                {"enabled": true, "app_name": "firefox", "switch_to": 3}
+            ]}"#,
+        );
+        let device = FakeTransport::new();
+        // what the bundled window scripts print if nothing is focused
+        let mut nothing = FakeWindow(WindowInfo::default());
+
+        let empty = switch(&device, &config, Some(3), &mut nothing).unwrap();
+        assert!(matches!(&empty, SwitchResult::Switched(event) if event.rule == Some(0)));
+        assert_eq!(empty.profile(), Some(6));
+        // windows never match empty_desktop rules, but fall back to the default_profile
+        let unmatched = switch(
+            &device,
+            &config,
+            empty.profile(),
+            &mut window("thunderbird"),
+        )
+        .unwrap();
+        assert_eq!(unmatched.profile(), Some(1));
+
+        // without empty_desktop rules the default_profile isn't used
+        let kept = switch(&device, &firefox_config(), Some(3), &mut nothing).unwrap();
+        assert!(matches!(kept, SwitchResult::Kept(Some(3))));
+    }
+
+    #[test]
+    fn match_types_compare_patterns_differently() {
+        let rule = |match_type: &str| {
+            let match_type = if match_type.is_empty() {
+                String::new()
+            } else {
+                format!(r#", "match_type": "{match_type}""#)
+            };
+            config(&format!(
+                r#"{{"autoswitch_enabled": true, "rules_list": [
+                    {{"enabled": true, "app_name": "", "process_name": ".exe", "switch_to": 2 {match_type}}},
+                    {{"enabled": true, "app_name": "", "window_title": "[duckypad]", "switch_to": 3 {match_type}}}
+                ]}}"#
+            ))
+        };
+        let desktop = DesktopState::default();
+        let windows = [
+            ("Code.exe", "main.rs - Visual Studio Code"),
+            ("exec.exe.bak", "notes"),
+            ("kitty", "[duckypad] cargo test"),
+            ("kitty", "~/src/[duckypad]"),
+        ];
+        let profiles = |config: &Config| {
+            windows.map(|(process_name, title)| {
+                let window = WindowInfo {
+                    process_name: process_name.to_string(),
+                    title: title.to_string(),
+                    ..app("app")
+                };
+                next_profile(config, &window, &desktop)
+            })
+        };
+
+        // contains is the default
+        assert_eq!(
+            profiles(&rule("")),
+            [
+                Some(Profile(2)),
+                Some(Profile(2)),
+                Some(Profile(3)),
+                Some(Profile(3))
+            ]
+        );
+        assert_eq!(profiles(&rule("contains")), profiles(&rule("")));
+        assert_eq!(
+            profiles(&rule("starts_with")),
+            [None, None, Some(Profile(3)), None]
+        );
+        assert_eq!(
+            profiles(&rule("ends_with")),
+            [Some(Profile(2)), None, None, Some(Profile(3))]
+        );
+    }
+
+    #[test]
+    fn included_rules_follow_the_rules_of_the_including_file() {
+        let dir = temp_path("include");
+        std::fs::create_dir_all(dir.join("rules")).unwrap();
+        std::fs::write(
+            dir.join("config.json"),
+            r#"{"autoswitch_enabled": true, "include": ["rules/work.json", "rules/personal.toml"], "rules_list": [
+                {"enabled": true, "app_name": "firefox", "window_title": "Jira", "switch_to": 2}
+            ]}"#,
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("rules/work.json"),
+            r#"{"rules_list": [{"enabled": true, "app_name": "firefox", "switch_to": 3}]}"#,
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("rules/personal.toml"),
+            "[[rules_list]]\nenabled = true\napp_name = \"firefox\"\nswitch_to = 4\n\n\
+             [[rules_list]]\nenabled = true\napp_name = \"steam\"\nswitch_to = 5\n",
+        )
+        .unwrap();
+        let config = read_config(&dir.join("config.json"));
+        std::fs::remove_dir_all(&dir).unwrap();
+        let config = config.unwrap();
+
+        let desktop = DesktopState::default();
+        let jira = WindowInfo {
+            title: "Board - Jira".to_string(),
+            ..app("firefox")
+        };
+        let rule = |window: &WindowInfo| next_match(&config, window, &desktop).and_then(|m| m.rule);
+        // the including file first, then the includes in order
+        assert_eq!(rule(&jira), Some(0));
+        assert_eq!(rule(&app("firefox")), Some(1));
+        assert_eq!(rule(&app("steam")), Some(3));
+        assert_eq!(
+            config.included_files(),
+            [dir.join("rules/work.json"), dir.join("rules/personal.toml")]
+        );
+    }
+
+    #[test]
+    fn include_cycles_are_refused() {
+        let dir = temp_path("include-cycle");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("a.json"),
+            r#"{"autoswitch_enabled": true, "include": ["b.json"], "rules_list": []}"#,
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("b.json"),
+            r#"{"include": ["a.json"], "rules_list": []}"#,
+        )
+        .unwrap();
+        let config = read_config(&dir.join("a.json"));
+        let canonical = dir.canonicalize();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let dir = canonical.unwrap();
+        assert!(matches!(
+            config,
+            Err(ConfigError::IncludeCycle(files))
+                if files == [dir.join("a.json"), dir.join("b.json"), dir.join("a.json")]
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn synchronous_callbacks_are_waited_for_or_killed() {
+        let done = temp_path("callback-done");
+        let started = temp_path("callback-started");
+        let fast = script(
+            "fast-callback.sh",
+            &format!(
+                "echo \"$DUCKYPAD_PROFILE $DUCKYPAD_RULE\" > {}",
+                done.display()
+            ),
+        );
+        let hanging = script(
+            "hanging-callback.sh",
+            &format!("touch {}\nexec sleep 30", started.display()),
+        );
+        let sync = |path: &PathBuf, timeout: u64| Callback {
+            path: path.clone(),
+            timeout: Some(Duration::from_millis(timeout)),
+        };
+        let firefox = window("firefox").0;
+
+        run_callback(&sync(&fast, 5000), 3, Some(1), Some(0), &firefox);
+        let written = std::fs::read_to_string(&done);
+
+        let start = Instant::now();
+        run_callback(&sync(&hanging, 200), 3, Some(1), Some(0), &firefox);
+        let elapsed = start.elapsed();
+        let hanging_started = started.exists();
+
+        for path in [&done, &started, &fast, &hanging] {
+            let _: Result<_, _> = std::fs::remove_file(path);
+        }
+
+        // the switch only completes once the callback did
+        assert_eq!(written.unwrap(), "3 0\n");
+        assert!(hanging_started);
+        assert!(elapsed >= Duration::from_millis(200));
+        assert!(elapsed < Duration::from_secs(5), "{elapsed:?}");
+    }
+
+    #[test]
+    fn rules_match_any_member_of_their_group() {
+        let config = config(
+            r#"{"autoswitch_enabled": true,
+                "groups": {"terminals": [{"app_name": "kitty"}, {"app_name": "alacritty"}, {"app_name": "", "process_name": "gnome-terminal-server"}]},
+                "rules_list": [
+                    {"enabled": true, "app_name": "", "app_group": "terminals", "window_title": "vim", "switch_to": 2},
+                    {"enabled": true, "app_name": "", "app_group": "terminals", "switch_to": 3}
+                ]}"#,
+        );
+        let desktop = DesktopState::default();
+        let titled = |app_name: &str, title: &str| WindowInfo {
+            title: title.to_string(),
+            ..app(app_name)
+        };
+
+        assert_eq!(
+            next_profile(&config, &app("kitty"), &desktop),
+            Some(Profile(3))
+        );
+        assert_eq!(
+            next_profile(&config, &app("alacritty"), &desktop),
+            Some(Profile(3))
+        );
+        assert_eq!(
+            next_profile(&config, &app("gnome-terminal-server"), &desktop),
+            Some(Profile(3))
+        );
+        assert_eq!(next_profile(&config, &app("firefox"), &desktop), None);
+        // the patterns of the rule have to match as well
+        assert_eq!(
+            next_profile(&config, &titled("alacritty", "vim main.rs"), &desktop),
+            Some(Profile(2))
+        );
+
+        let mut unknown: Config = parse_json(
+            r#"{"autoswitch_enabled": true, "rules_list": [
+                {"enabled": true, "app_name": "", "app_group": "browsers", "switch_to": 3}
+            ]}"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            unknown.prepare(),
+            Err(ConfigError::UnknownGroup(location, name)) if location == "rule 0" && name == "browsers"
+        ));
+    }
+
+    #[test]
+    fn relative_profile_wraps_around() {
+        assert_eq!(relative_profile(Target::Next, 3, None, 16), Some(4));
+        assert_eq!(relative_profile(Target::Next, 16, None, 16), Some(1));
+        assert_eq!(relative_profile(Target::Previous, 3, None, 16), Some(2));
+        assert_eq!(relative_profile(Target::Previous, 1, None, 16), Some(16));
+        // at the last profile of the model, not of `PROFILES`
+        assert_eq!(relative_profile(Target::Next, 31, None, 31), Some(1));
+    }
+
+    #[test]
+    fn relative_profile_toggles_back() {
+        assert_eq!(relative_profile(Target::Toggle, 3, Some(5), 16), Some(5));
+        assert_eq!(relative_profile(Target::Toggle, 3, None, 16), None);
+        assert_eq!(relative_profile(Target::Toggle, 3, Some(20), 16), None);
+    }
+
+    #[test]
+    fn relative_profile_refuses_unknown_profiles() {
+        assert_eq!(relative_profile(Profile(3), 1, None, 16), Some(3));
+        assert_eq!(relative_profile(Profile(20), 1, None, 16), None);
+        assert_eq!(relative_profile(Profile(0), 1, None, 16), None);
+        // a current profile beyond the model still moves back into it
+        assert_eq!(relative_profile(Target::Previous, 20, None, 16), Some(16));
+        assert_eq!(relative_profile(Target::Sleep, 1, None, 16), None);
+    }
+
+    #[test]
+    fn normalize_title_composes_and_truncates() {
+        assert_eq!(
+            normalize_title("Cafe\u{301} - Firefox", None),
+            "Caf\u{e9} - Firefox"
+        );
+        // the limit counts composed characters
+        assert_eq!(
+            normalize_title("Cafe\u{301} - Firefox", Some(4)),
+            "Caf\u{e9}"
+        );
+        assert_eq!(normalize_title("vim", Some(1000)), "vim");
+        assert_eq!(normalize_title("vim", Some(0)), "");
+    }
+
+    #[test]
+    fn titles_and_patterns_are_normalized_before_matching() {
+        let config = config(
+            r#"{"autoswitch_enabled": true, "max_title_length": 4, "rules_list": [
+                {"enabled": true, "app_name": "", "title": "Cafe\u0301", "match_type": "exact", "switch_to": 3}
+            ]}"#,
+        );
+        let window = WindowInfo {
+            title: config.normalized_title("Cafe\u{301} - Firefox"),
+            ..app("firefox")
+        };
+
+        let matched = next_match(&config, &window, &DesktopState::default());
+        assert_eq!(matched.map(|m| m.target), Some(Profile(3)));
+    }
+
+    #[test]
+    fn manual_overrides_last_for_the_grace_period() {
+        let grace = Duration::from_secs(30);
+        let mut manual = ManualOverride::new(grace);
+        let diverged = Instant::now();
+        assert!(!manual.active(diverged));
+        assert!(!manual.expire(diverged + grace));
+
+        manual.start(diverged);
+        assert!(manual.active(diverged + Duration::from_secs(29)));
+        assert!(!manual.expire(diverged + Duration::from_secs(29)));
+        assert!(manual.expire(diverged + grace));
+        assert!(!manual.active(diverged + grace));
+        // only once per grace period
+        assert!(!manual.expire(diverged + grace));
+    }
+
+    #[test]
+    fn manual_overrides_restart_on_further_changes() {
+        let grace = Duration::from_secs(30);
+        let mut manual = ManualOverride::new(grace);
+        let diverged = Instant::now();
+
+        manual.start(diverged);
+        manual.start(diverged + Duration::from_secs(20));
+        assert!(manual.active(diverged + grace));
+        assert!(!manual.expire(diverged + grace));
+        assert!(manual.expire(diverged + Duration::from_secs(50)));
+    }
+
+    #[test]
+    fn cmdline_rules_tell_scripts_of_an_interpreter_apart() {
+        let config = config(
+            r#"{"autoswitch_enabled": true, "rules_list": [
+                {"enabled": true, "app_name": "python", "cmdline": "server.py", "switch_to": 2},
+                {"enabled": true, "app_name": "python", "cmdline": "python -m http.server", "switch_to": 4},
+                {"enabled": true, "app_name": "python", "switch_to": 3}
+            ]}"#,
+        );
+        let profile = |cmdline: &[&str]| {
+            let window = WindowInfo {
+                cmdline: cmdline.iter().map(ToString::to_string).collect(),
+                ..app("python")
+            };
+            next_match(&config, &window, &DesktopState::default()).map(|m| m.target)
+        };
+
+        assert_eq!(profile(&["python", "server.py"]), Some(Profile(2)));
+        // the whole command line joined with spaces
+        assert_eq!(profile(&["python", "-m", "http.server"]), Some(Profile(4)));
+        assert_eq!(profile(&["python", "other.py"]), Some(Profile(3)));
+        // unknown processes only match rules without a cmdline
+        assert_eq!(profile(&[]), Some(Profile(3)));
+    }
+
+    #[test]
+    fn only_conditions_within_a_window_need_polling() {
+        let polls = |rule: &str| {
+            config(&format!(
+                r#"{{"autoswitch_enabled": true, "rules_list": [
+                    {{"enabled": true, "app_name": "firefox", "switch_to": 3}},
+                    {rule}
+                ]}}"#
+            ))
+            .needs_polling()
+        };
+
+        assert!(!polls(
+            r#"{"enabled": true, "app_name": "kate", "title": "", "switch_to": 4}"#
+        ));
+        assert!(!polls(
+            r#"{"enabled": false, "app_name": "", "title": "Meet", "switch_to": 4}"#
+        ));
+        assert!(polls(
+            r#"{"enabled": true, "app_name": "", "title": "Meet", "switch_to": 4}"#
+        ));
+        assert!(polls(
+            r#"{"enabled": true, "app_name": "kate", "idle_after": 60, "switch_to": 4}"#
+        ));
+        assert!(polls(
+            r#"{"enabled": true, "app_name": "kate", "active_time": "Mon-Fri", "switch_to": 4}"#
+        ));
+        assert!(polls(
+            r#"{"enabled": true, "app_name": "kate", "exclude": [{"title": "Private"}], "switch_to": 4}"#
+        ));
+        assert!(config(
+            r#"{"autoswitch_enabled": true, "ignore_list": [{"app_name": "", "title": "KeePassXC"}],
+                "rules_list": []}"#
+        )
+        .needs_polling());
+    }
+
+    #[test]
+    fn regex_rules_match_any_of_their_patterns() {
+        let config = config(
+            r#"{"autoswitch_enabled": true, "rules_list": [
+                {"enabled": true, "app_name": "", "window_title": ["^\\[duckypad\\]", "\\.rs - Visual Studio Code$"],
+                    "match_type": "regex", "switch_to": 3},
+                {"enabled": true, "app_name": "", "window_title": "^jira-\\d+", "match_type": "regex",
+                    "case_insensitive": true, "switch_to": 4}
+            ]}"#,
+        );
+        let profile = |title: &str| {
+            let window = WindowInfo {
+                title: title.to_string(),
+                ..app("app")
+            };
+            next_profile(&config, &window, &DesktopState::default())
+        };
+
+        assert_eq!(profile("[duckypad] cargo test"), Some(Profile(3)));
+        assert_eq!(profile("main.rs - Visual Studio Code"), Some(Profile(3)));
+        // regexes search the whole value unless anchored
+        assert_eq!(profile("~/src/[duckypad]"), None);
+        assert_eq!(profile("main.rs.bak - Visual Studio Code - Insiders"), None);
+        assert_eq!(profile("JIRA-42 Fix the firmware query"), Some(Profile(4)));
+        assert_eq!(profile("[DUCKYPAD] cargo test"), None);
+    }
+
+    #[test]
+    fn invalid_regexes_name_their_rule() {
+        let mut config: Config = parse_json(
+            r#"{"autoswitch_enabled": true, "rules_list": [
+                {"enabled": true, "app_name": "firefox", "match_type": "regex", "switch_to": 2},
+                {"enabled": true, "app_name": "", "window_title": ["Meet", "(Zoom"], "match_type": "regex", "switch_to": 3}
+            ]}"#,
+        )
+        .unwrap();
+
+        let Err(err) = config.prepare() else {
+            panic!("invalid regex was accepted");
+        };
+        let ConfigError::Regex {
+            location, pattern, ..
+        } = &err
+        else {
+            panic!("unexpected error: {err}");
+        };
+        assert_eq!(location, "rule 1");
+        assert_eq!(pattern, "(Zoom");
+        assert!(err
+            .to_string()
+            .starts_with("Invalid regex \"(Zoom\" in rule 1:"));
+    }
+
+    #[test]
+    fn only_keywords_switch_to_other_targets_than_profiles() {
+        // the former pseudo profile ids of "next", "previous" and "toggle"
+        for out_of_range in [u32::MAX, u32::MAX - 1, u32::MAX - 2] {
+            let err = prepare_error(&format!(
+                r#"{{"autoswitch_enabled": true, "rules_list": [
+                    {{"enabled": true, "app_name": "firefox", "switch_to": {out_of_range}}}
+                ]}}"#
+            ));
+            assert!(
+                matches!(err, ConfigError::Profile(ref location, profile) if location == "rule 0" && profile == out_of_range),
+                "{err}"
+            );
+        }
+
+        let config = config(
+            r#"{"autoswitch_enabled": true, "rules_list": [
+                {"enabled": true, "app_name": "firefox", "switch_to": "next"},
+                {"enabled": true, "app_name": "kate", "switch_to": 31}
+            ]}"#,
+        );
+        let desktop = DesktopState::default();
+        assert_eq!(
+            next_profile(&config, &app("firefox"), &desktop),
+            Some(Target::Next)
+        );
+        assert_eq!(
+            next_profile(&config, &app("kate"), &desktop),
+            Some(Profile(31))
+        );
+    }
+
+    #[test]
+    fn keywords_are_no_profile_names() {
+        for keyword in ["sleep", "next", "previous", "toggle"] {
+            let err = prepare_error(&format!(
+                r#"{{"autoswitch_enabled": true, "profile_names": {{"{keyword}": 3}}, "rules_list": [
+                    {{"enabled": true, "app_name": "firefox", "switch_to": "{keyword}"}}
+                ]}}"#
+            ));
+            assert!(
+                matches!(err, ConfigError::ReservedProfileName(ref name) if name == keyword),
+                "{err}"
+            );
+        }
+    }
+
+    #[test]
+    fn profile_names_of_the_duckypad_come_before_the_config() {
+        let path = temp_path("device_names.json");
+        std::fs::write(
+            &path,
+            r#"{"autoswitch_enabled": true, "profile_names": {"Firefox": 5, "Chat": 6}, "rules_list": [
+                {"enabled": true, "app_name": "firefox", "switch_to": "Firefox"},
+                {"enabled": true, "app_name": "discord", "switch_to": "Chat"},
+                {"enabled": true, "app_name": "code", "switch_to": "Coding"}
+            ]}"#,
+        )
+        .unwrap();
+        let device_names = HashMap::from([("Firefox".to_string(), 3), ("Coding".to_string(), 4)]);
+
+        let offline = read_config(&path);
+        let config = read_config_with(&path, &device_names).unwrap();
+        let renamed = read_config_with(&path, &HashMap::from([("Firefox".to_string(), 3)]));
+        std::fs::remove_file(&path).unwrap();
+
+        let targets: Vec<_> = config.rules_list.iter().map(|rule| rule.target).collect();
+        assert_eq!(targets, [Profile(3), Profile(6), Profile(4)]);
+        assert_eq!(config.device_names(), &device_names);
+        assert_eq!(config.profile_name(3), Some("Firefox"));
+        assert_eq!(config.profile_name(6), Some("Chat"));
+
+        // without a duckyPad that has it, a name has to be in profile_names
+        for err in [offline.err(), renamed.err()] {
+            assert!(
+                matches!(&err, Some(ConfigError::UnknownProfileName(location, name)) if location == "rule 2" && name == "Coding"),
+                "{err:?}"
+            );
+        }
+    }
 }
@@ -0,0 +1,161 @@
+use std::{
+    fs::{File, OpenOptions, TryLockError},
+    io::{Read, Seek, Write},
+    path::{Path, PathBuf},
+};
+
+/// Reasons why the lock file couldn't be locked.
+#[derive(Debug)]
+pub enum LockError {
+    /// Another process holds the lock (its pid from the lock file, if known)
+    Locked(PathBuf, Option<u32>),
+    /// The lock file couldn't be opened, locked or written
+    Io(PathBuf, std::io::Error),
+}
+
+impl std::fmt::Display for LockError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Locked(path, pid) => write!(
+                f,
+                "Another duckypad_daemon is already running ({}lock file {}). Stop it first, or pass a different --lock-file to run a second daemon for another duckyPad (see --serial)",
+                pid.map(|pid| format!("pid {pid}, ")).unwrap_or_default(),
+                path.display()
+            ),
+            Self::Io(path, err) => write!(f, "Failed to lock {}: {err}", path.display()),
+        }
+    }
+}
+
+impl std::error::Error for LockError {}
+
+/// An advisory lock on a file (`flock` on Unix, `LockFileEx` on Windows) that
+/// keeps a second daemon from fighting over the same duckypad. The lock is
+/// released by the OS when the process exits, even after a crash, so a stale
+/// lock file doesn't block the next start. The file contains the pid of the
+/// daemon holding the lock, which is cleared once the lock is dropped.
+pub struct InstanceLock {
+    path: PathBuf,
+    /// kept open, as closing the file releases the lock
+    file: File,
+}
+
+impl InstanceLock {
+    /// Locks the file at `path`, creating it if necessary, and writes the pid
+    /// of this process to it.
+    ///
+    /// # Errors
+    ///
+    /// Will return `LockError::Locked` if another process holds the lock and
+    /// `LockError::Io` if the file can't be opened, locked or written.
+    pub fn acquire(path: PathBuf) -> Result<Self, LockError> {
+        let io_error = |err| LockError::Io(path.clone(), err);
+
+        // not truncated before locking, so the pid of a running daemon is kept
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .map_err(io_error)?;
+
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                let mut pid = String::new();
+                let pid = file
+                    .read_to_string(&mut pid)
+                    .ok()
+                    .and_then(|_| pid.trim().parse().ok());
+
+                return Err(LockError::Locked(path, pid));
+            }
+            Err(TryLockError::Error(err)) => return Err(io_error(err)),
+        }
+
+        file.set_len(0)
+            .and_then(|()| file.rewind())
+            .and_then(|()| writeln!(file, "{}", std::process::id()))
+            .and_then(|()| file.flush())
+            .map_err(io_error)?;
+
+        Ok(Self { path, file })
+    }
+
+    /// Returns the path of the lock file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for InstanceLock {
+    fn drop(&mut self) {
+        // The file is kept: after removing it, a daemon that opened it just
+        // before could still lock the removed file while another one creates
+        // and locks a new one, so both would run.
+        if let Err(err) = self.file.set_len(0) {
+            tracing::debug!("Failed to clear lock file {}: {err}", self.path.display());
+        }
+    }
+}
+
+/// Returns the default path of the lock file: `duckypad_daemon.lock` in the
+/// runtime directory (`$XDG_RUNTIME_DIR` on Linux) or the temporary directory
+/// on other platforms. With the `serial` of the duckypad to use, the name
+/// contains it, so daemons for different duckypads don't block each other.
+pub fn default_path(serial: Option<&str>) -> PathBuf {
+    let name = serial.map_or("duckypad_daemon.lock".to_string(), |serial| {
+        let serial: String = serial
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        format!("duckypad_daemon-{serial}.lock")
+    });
+
+    dirs_next::runtime_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the path of a lock file that no other test uses.
+    fn lock_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "duckypad_daemon-test-{}-{name}.lock",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn second_lock_reports_the_pid_of_the_first() {
+        let path = lock_path("second");
+        let lock = InstanceLock::acquire(path.clone()).unwrap();
+
+        match InstanceLock::acquire(path.clone()) {
+            Err(LockError::Locked(locked, pid)) => {
+                assert_eq!(locked, path);
+                assert_eq!(pid, Some(std::process::id()));
+            }
+            _ => panic!("the file was locked twice"),
+        }
+
+        drop(lock);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn dropping_the_lock_keeps_the_file() {
+        let path = lock_path("drop");
+        drop(InstanceLock::acquire(path.clone()).unwrap());
+
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "");
+        assert!(InstanceLock::acquire(path.clone()).is_ok());
+        std::fs::remove_file(path).unwrap();
+    }
+}
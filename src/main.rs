@@ -1,107 +1,858 @@
 #![warn(clippy::pedantic)]
 
 use clap::Parser;
-use duckypad_daemon::{config_file, enums, hid, read_config, switch_profile};
-use std::{env, path::PathBuf, process::Command};
-use sysinfo::{ProcessRefreshKind, RefreshKind, System, SystemExt};
+#[cfg(all(unix, feature = "control-socket"))]
+use duckypad_daemon::control;
+#[cfg(all(unix, feature = "dbus"))]
+use duckypad_daemon::dbus;
+use duckypad_daemon::{
+    config_file,
+    daemon::{Daemon, DaemonError, WAIT_INTERVAL},
+    default_config_path, hid,
+    history::History,
+    lock::{self, InstanceLock},
+    metrics::Metrics,
+    named_config_file, next_match,
+    provider::{self, WindowProvider},
+    read_config,
+    status::StatusFile,
+    window_script::{WindowScript, WindowStream},
+    Callback, Config, DesktopState, Match, SwitchResult, WindowInfo, DEFAULT_CONFIG_NAME,
+};
+#[cfg(unix)]
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM, SIGUSR1};
+#[cfg(all(unix, any(feature = "control-socket", feature = "dbus")))]
+use std::sync::mpsc::Receiver;
+use std::{
+    env,
+    path::{Path, PathBuf},
+    sync::{atomic::AtomicBool, Arc},
+    time::Duration,
+};
+use tracing_subscriber::EnvFilter;
 
+#[cfg(not(unix))]
+const SIGHUP: i32 = 1;
+#[cfg(not(unix))]
+const SIGUSR1: i32 = 10;
+#[cfg(not(unix))]
+const SIGINT: i32 = 2;
+#[cfg(not(unix))]
+const SIGTERM: i32 = 15;
+
+#[allow(clippy::doc_markdown, clippy::struct_excessive_bools)]
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
     /// Path to a config file to use
-    #[arg(short, long, default_value = None)]
+    #[arg(short, long, default_value = None, global = true)]
     config: Option<PathBuf>,
 
+    /// Directory of named config files (<NAME>.json, .json5 or .toml) to
+    /// switch between at runtime with use-config <NAME>. Without --config the
+    /// one named "default" is used on startup
+    #[arg(long, default_value = None)]
+    config_dir: Option<PathBuf>,
+
+    /// Reload the config file automatically whenever it changes
+    #[arg(long)]
+    watch_config: bool,
+
     /// Wait for <WAIT> seconds and retry if device isn't connected on daemon startup
     #[arg(short, long, default_value = None)]
     wait: Option<u64>,
 
+    /// Maximum number of seconds between two connection attempts, as the
+    /// time waited doubles after every failed attempt
+    #[arg(long, default_value_t = 10)]
+    wait_max_interval: u64,
+
+    /// Number of failed connection attempts (on startup with --wait or after
+    /// losing the connection) after which the daemon exits
+    #[arg(long, default_value = None, value_parser = clap::value_parser!(u32).range(1..))]
+    wait_max_attempts: Option<u32>,
+
     /// Path to an executable to call when switching profile
     /// CALLBACK -p <PROFILE> [-a <APP_NAME>] [-t <TITLE>] [-n <PROCESS_NAME>]
     #[arg(short = 'b', long, default_value = None, verbatim_doc_comment)]
     callback: Option<PathBuf>,
 
+    /// Wait for the callback to exit before the switch is done, e.g. when it
+    /// sets up something the profile relies on, killing it after --callback-timeout
+    #[arg(long, requires = "callback")]
+    callback_sync: bool,
+
+    /// Seconds to wait for the callback with --callback-sync before killing it
+    #[arg(long, default_value_t = 10, requires = "callback_sync", value_parser = clap::value_parser!(u64).range(1..))]
+    callback_timeout: u64,
+
+    /// Path to an executable to call when the duckyPad gets connected
+    /// ON_CONNECT -s <SERIAL> -m <MODEL>
+    #[arg(long, default_value = None, verbatim_doc_comment)]
+    on_connect: Option<PathBuf>,
+
+    /// Path to an executable to call when the connection to the duckyPad is lost
+    /// ON_DISCONNECT -s <SERIAL> -m <MODEL>
+    #[arg(long, default_value = None, verbatim_doc_comment)]
+    on_disconnect: Option<PathBuf>,
+
     /// Path to an executable to call periodically about active window information on platforms without native APIs
     /// Output must be a JSON with keys: title & process_name
     #[arg(short = 's', long, default_value = None, verbatim_doc_comment)]
     window_script: Option<PathBuf>,
+
+    /// How the window script is run: once per poll (oneshot) or kept running,
+    /// printing a JSON object per line whenever the active window changes (stream)
+    #[arg(long, value_enum, default_value_t = WindowScriptMode::Oneshot)]
+    window_script_mode: WindowScriptMode,
+
+    /// Read the active window from stdin instead of a window script or the
+    /// native API, as a JSON object per line (e.g. from a window manager's
+    /// focus hook). The daemon exits after the last line once stdin is closed
+    #[arg(long, conflicts_with_all = ["window_script", "window_script_mode", "once"])]
+    stdin: bool,
+
+    /// Interval in milliseconds at which the active window is checked (50 - 10000)
+    #[arg(long, default_value_t = 250, value_parser = clap::value_parser!(u64).range(50..=10000))]
+    poll_interval: u64,
+
+    /// Serial number of the duckyPad to use if multiple are connected
+    #[arg(long, default_value = None)]
+    serial: Option<String>,
+
+    /// Path of the lock file that keeps a second daemon from using the same
+    /// duckyPad (default: duckypad_daemon.lock, or duckypad_daemon-<SERIAL>.lock
+    /// with --serial, in $XDG_RUNTIME_DIR or the temporary directory)
+    #[arg(long, default_value = None)]
+    lock_file: Option<PathBuf>,
+
+    /// Path of a unix socket to create for controlling the daemon at runtime
+    /// Commands: switch <PROFILE>, status, info, pause, resume, reload, use-config <NAME>
+    #[cfg(all(unix, feature = "control-socket"))]
+    #[arg(long, default_value = None, verbatim_doc_comment)]
+    control_socket: Option<PathBuf>,
+
+    /// Provide a D-Bus service on the session bus for controlling the daemon
+    #[cfg(all(unix, feature = "dbus"))]
+    #[arg(long)]
+    dbus: bool,
+
+    /// Show a desktop notification whenever the profile is switched
+    #[arg(long)]
+    notify: bool,
+
+    /// Pause autoswitching while the screen is locked (via logind's
+    /// LockedHint with the dbus feature, or the screensaver on X11)
+    #[arg(long)]
+    pause_on_lock: bool,
+
+    /// Log profile switches instead of sending them to the duckyPad
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Refuse configs with rules switching to profiles the connected duckyPad
+    /// doesn't have or rules that never match, instead of only warning about
+    /// them
+    #[arg(long)]
+    strict: bool,
+
+    /// Switch to the profile for the active window once and exit instead of
+    /// running as a daemon. Exits with 0 if the profile was switched, 2 if no
+    /// rule matched and 1 if the duckyPad couldn't be switched
+    #[arg(long, conflicts_with_all = ["watch_config", "window_script_mode"])]
+    once: bool,
+
+    /// Don't connect to a duckyPad at all (requires --dry-run)
+    #[arg(long, requires = "dry_run")]
+    no_device: bool,
+
+    /// Don't read the firmware version when connecting, which is reported as
+    /// unknown then, e.g. if that request hangs or fails on your setup
+    #[arg(long)]
+    no_firmware_query: bool,
+
+    /// Log level or filter directives like RUST_LOG (default: RUST_LOG or info)
+    #[arg(long, default_value = None)]
+    log_level: Option<String>,
+
+    /// Log for every rule why it did or didn't match the active window, e.g.
+    /// to find out why a switch doesn't happen (also shown with --log-level debug)
+    #[arg(long)]
+    explain: bool,
+
+    /// Interval in seconds at which the profile is read from the duckyPad to
+    /// detect manual profile changes on the device (0 disables it)
+    #[arg(long, default_value_t = 5)]
+    sync_interval: u64,
+
+    /// Interval in seconds at which the profile is read from the duckyPad and
+    /// switched back if it differs from the profile the daemon switched to,
+    /// e.g. after another tool changed it (replaces --sync-interval)
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    reassert_interval: Option<u64>,
+
+    /// Time in seconds to pause autoswitching for after the profile was
+    /// changed by hand on the duckyPad, instead of switching right back
+    /// (requires --sync-interval or --reassert-interval)
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    manual_override_grace: Option<u64>,
+
+    /// Time in milliseconds the matched profile needs to stay the same before
+    /// switching to it (0 disables it)
+    #[arg(long, default_value_t = 0)]
+    debounce: u64,
+
+    /// Minimum time in milliseconds between two profile switches. Switches
+    /// within it are skipped and the latest matched profile is applied once
+    /// it elapsed (0 disables it)
+    #[arg(long, default_value_t = 0)]
+    min_interval: u64,
+
+    /// Address to serve Prometheus metrics at, e.g. 127.0.0.1:9184
+    #[cfg(feature = "metrics")]
+    #[arg(long, default_value = None)]
+    metrics_addr: Option<String>,
+
+    /// Path of a file to append a JSON line to for every profile switch
+    #[arg(long, default_value = None)]
+    history_file: Option<PathBuf>,
+
+    /// Size in bytes after which the history file is rotated to <PATH>.1
+    #[arg(long, default_value = None, requires = "history_file")]
+    history_max_size: Option<u64>,
+
+    /// Path of a file that always contains the current profile and whether
+    /// autoswitching is paused as JSON, e.g. for status bars
+    #[arg(long, default_value = None)]
+    status_file: Option<PathBuf>,
+
+    #[command(subcommand)]
+    command: Option<Subcommand>,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum WindowScriptMode {
+    Oneshot,
+    Stream,
+}
+
+// parsed once, so the size of `Test` doesn't matter
+#[allow(clippy::doc_markdown, clippy::large_enum_variant)]
+#[derive(clap::Subcommand, Debug)]
+enum Subcommand {
+    /// Print which rule and profile the config selects for a window, without
+    /// connecting to the duckyPad
+    Test {
+        /// App name (command) of the window
+        #[arg(long, default_value = "")]
+        app: String,
+
+        /// Title of the window
+        #[arg(long, default_value = "")]
+        title: String,
+
+        /// Process name of the window (WM_CLASS on X11)
+        #[arg(long, default_value = "")]
+        process: String,
+
+        /// Full path of the executable of the window's process
+        #[arg(long, default_value = None)]
+        path: Option<PathBuf>,
+
+        /// Command line of the window's process, one value per argument (must
+        /// come last, e.g. --cmdline python server.py --port 80)
+        #[arg(long, num_args = 1.., allow_hyphen_values = true)]
+        cmdline: Vec<String>,
+
+        /// URL of the page shown in the window (as reported by window scripts)
+        #[arg(long, default_value = "")]
+        url: String,
+
+        /// Instance name from WM_CLASS of the window (X11)
+        #[arg(long, default_value = "")]
+        wm_instance: String,
+
+        /// WM_WINDOW_ROLE of the window (X11)
+        #[arg(long, default_value = "")]
+        wm_role: String,
+
+        /// Seconds since the last user input (rules with idle_after never match without it)
+        #[arg(long, default_value = None)]
+        idle: Option<u64>,
+
+        /// Monitor of the window (rules with monitor never match without it)
+        #[arg(long, default_value = None)]
+        monitor: Option<u32>,
+
+        /// Whether the window is fullscreen (rules with fullscreen always match without it)
+        #[arg(long, default_value = None)]
+        fullscreen: Option<bool>,
+
+        /// Test an empty desktop instead of a window (only empty_desktop rules match)
+        #[arg(long, conflicts_with_all = ["app", "title", "process", "path", "cmdline", "url", "wm_instance", "wm_role", "fullscreen"])]
+        empty_desktop: bool,
+    },
+    /// Check the config file for errors and rules that never match, without
+    /// connecting to the duckyPad. Exits with 1 if there are any
+    Validate,
 }
 
 fn main() {
     let args = Args::parse();
+    init_logging(args.log_level.as_deref(), args.explain);
 
-    // create Command without args or spawning to use in `run_callback` (lib.rs)
-    let mut callback = args.callback.map(Command::new);
+    if let Some(command) = args.command {
+        std::process::exit(run_command(command, args.config));
+    }
 
-    let config_path = config_file(args.config);
-    let config = read_config(&config_path);
+    let lock = lock_instance(&args);
+    let config_path = startup_config_path(&args);
+    let metrics = Arc::new(Metrics::default());
 
-    let api = hidapi::HidApi::new().expect("Failed to connect to HidApi.");
+    let retry = |interval| hid::Retry {
+        interval,
+        max_interval: Duration::from_secs(args.wait_max_interval),
+        max_attempts: args.wait_max_attempts,
+    };
+    let provider = window_provider(args.window_script, args.window_script_mode, args.stdin);
 
-    {
-        let duckypad = if let Some(wait) = args.wait {
-            loop {
-                if let Ok(dev) = hid::init(&api) {
-                    break dev;
-                }
+    let builder = Daemon::builder(config_path)
+        .config_dir(args.config_dir)
+        .strict(args.strict)
+        .poll_interval(Duration::from_millis(args.poll_interval))
+        .window_provider(provider)
+        .callback(callback(
+            args.callback,
+            args.callback_sync,
+            args.callback_timeout,
+        ))
+        .connect(!args.no_device)
+        .serial(args.serial)
+        .wait(args.wait.map(|wait| retry(Duration::from_secs(wait))))
+        .retry(retry(WAIT_INTERVAL))
+        .query_firmware(!args.no_firmware_query)
+        .dry_run(args.dry_run)
+        .on_connect(args.on_connect)
+        .on_disconnect(args.on_disconnect)
+        .pause_on_lock(args.pause_on_lock)
+        .notify(args.notify)
+        .history(open_history(args.history_file, args.history_max_size))
+        .status_file(args.status_file.map(StatusFile::new))
+        .metrics(Arc::clone(&metrics))
+        .sync_interval((args.sync_interval > 0).then(|| Duration::from_secs(args.sync_interval)))
+        .reassert_interval(args.reassert_interval.map(Duration::from_secs))
+        .debounce((args.debounce > 0).then(|| Duration::from_millis(args.debounce)))
+        .min_interval((args.min_interval > 0).then(|| Duration::from_millis(args.min_interval)))
+        .manual_override_grace(args.manual_override_grace.map(Duration::from_secs))
+        .watch_config(args.watch_config && !args.once)
+        .reload_flag(signal_flag(SIGHUP))
+        .toggle_pause_flag(signal_flag(SIGUSR1))
+        .shutdown_flag(shutdown_flag())
+        .lock(lock);
 
-                eprintln!("Failed to connect to duckyPad. Retrying in {wait} seconds!");
-                std::thread::sleep(std::time::Duration::from_secs(wait));
-            }
-        } else {
-            hid::init(&api).expect(
-                "Failed to connect to duckyPad. See --help if you want to enable auto-retrying.",
-            )
-        };
-
-        let info = hid::info(&duckypad);
-        println!(
-            "Model: {}\tSerial: {}\tFirmware: {}",
-            info.model, info.serial, info.firmware
-        );
+    #[cfg(all(unix, feature = "control-socket"))]
+    let builder = builder.control(
+        args.control_socket
+            .as_deref()
+            .filter(|_| !args.once)
+            .map(listen_control),
+    );
+    #[cfg(all(unix, feature = "dbus"))]
+    let builder = builder.dbus((args.dbus && !args.once).then(serve_dbus));
+
+    let daemon = builder
+        .build()
+        .unwrap_or_else(|err| exit_daemon(&err, args.wait.is_some()));
+
+    #[cfg(feature = "metrics")]
+    if let Some(addr) = &args.metrics_addr {
+        duckypad_daemon::metrics::serve(addr, Arc::clone(&metrics))
+            .unwrap_or_else(|err| panic!("Failed to serve metrics at {addr}: {err}"));
+    }
+
+    if args.once {
+        std::process::exit(once(daemon));
+    }
+
+    daemon.run().unwrap_or_else(|err| exit_daemon(&err, true));
+}
+
+/// Checks the active window a single time for `--once` and returns the exit
+/// code: `0` if the profile was switched or is kept on purpose (e.g. for an
+/// ignored window), `2` if no rule matched and `1` if switching the duckyPad
+/// failed.
+fn once(daemon: Daemon) -> i32 {
+    match daemon.once() {
+        Ok(SwitchResult::Switched(_)) => 0,
+        Ok(SwitchResult::Kept(_)) => {
+            tracing::info!("Keeping the profile of the duckyPad");
+            0
+        }
+        Ok(SwitchResult::Unmatched(_)) => {
+            tracing::info!("No rule matches the active window");
+            2
+        }
+        Err(err) => {
+            tracing::error!("Failed to switch the profile of the duckyPad: {err}");
+            1
+        }
+    }
+}
+
+/// Creates the control socket at `path` (see `control::listen`).
+///
+/// # Panics
+///
+/// The function will panic if the socket cannot be created.
+#[cfg(all(unix, feature = "control-socket"))]
+fn listen_control(path: &Path) -> Receiver<control::Message> {
+    control::listen(path)
+        .unwrap_or_else(|err| panic!("Failed to create control socket {}: {err}", path.display()))
+}
+
+/// Provides the D-Bus service (see `dbus::serve`).
+///
+/// # Panics
+///
+/// The function will panic if the service cannot be provided.
+#[cfg(all(unix, feature = "dbus"))]
+fn serve_dbus() -> (dbus::Service, Receiver<dbus::Message>) {
+    dbus::serve().unwrap_or_else(|err| panic!("Failed to provide D-Bus service: {err}"))
+}
+
+/// Locks the `--lock-file`, so a second daemon for the same duckyPad exits
+/// instead of fighting over it. The lock is held until the returned value is
+/// dropped. A single `--once` run or a daemon without a duckyPad doesn't get
+/// in the way of others and isn't locked. If another daemon holds the lock or
+/// the lock file can't be created, the error is logged and the process exits
+/// with status 1, as both are setup mistakes rather than bugs.
+fn lock_instance(args: &Args) -> Option<InstanceLock> {
+    if args.once || args.no_device {
+        return None;
+    }
+
+    let path = args
+        .lock_file
+        .clone()
+        .unwrap_or_else(|| lock::default_path(args.serial.as_deref()));
+    let lock = InstanceLock::acquire(path).unwrap_or_else(|err| {
+        tracing::error!("{err}");
+        std::process::exit(1);
+    });
+    tracing::debug!("Locked {}", lock.path().display());
+
+    Some(lock)
+}
+
+/// Returns the `--callback` at `path`, which is waited for up to `timeout`
+/// seconds with `sync` (see `--callback-sync`).
+fn callback(path: Option<PathBuf>, sync: bool, timeout: u64) -> Option<Callback> {
+    path.map(|path| Callback {
+        path,
+        timeout: sync.then(|| Duration::from_secs(timeout)),
+    })
+}
+
+/// Opens the `--history-file`, if any.
+///
+/// # Panics
+///
+/// Will panic if the history file can't be opened.
+fn open_history(path: Option<PathBuf>, max_size: Option<u64>) -> Option<History> {
+    path.map(|path| {
+        History::open(path.clone(), max_size)
+            .unwrap_or_else(|err| panic!("Failed to open history file {}: {err}", path.display()))
+    })
+}
+
+/// Returns the path of the config to use on startup: `--config`, the config
+/// named `default` in the `--config-dir` or the default config file.
+///
+/// # Panics
+///
+/// The function will panic if the `--config-dir` has no config named
+/// `default`.
+fn startup_config_path(args: &Args) -> PathBuf {
+    match (&args.config_dir, &args.config) {
+        (Some(dir), None) => {
+            named_config_file(dir, DEFAULT_CONFIG_NAME).unwrap_or_else(|err| panic!("{err}"))
+        }
+        (_, config) => config_file(config.clone()),
+    }
+}
+
+/// Exits because the daemon failed with `err`. Not finding the duckyPad
+/// (`waited` for it with `--wait` or not) exits like `exit_no_device`, other
+/// errors panic like other startup errors.
+fn exit_daemon(err: &DaemonError, waited: bool) -> ! {
+    match err {
+        DaemonError::Connect(err) if waited => exit_no_device(&format!(
+            "Failed to connect to duckyPad, giving up: {err}"
+        )),
+        DaemonError::Connect(_) => exit_no_device(&format!(
+            "{err}\nUse --wait <SECONDS> to wait for it instead, e.g. when the daemon starts before the duckyPad is ready."
+        )),
+        DaemonError::Reconnect(_) => exit_no_device(&err.to_string()),
+        DaemonError::Config(_) | DaemonError::HidApi(_) => panic!("{err}"),
+    }
+}
+
+/// Logs `message` and exits with status 1. A missing duckyPad is expected
+/// (e.g. on autostart), so unlike other startup errors it doesn't panic with a
+/// backtrace.
+fn exit_no_device(message: &str) -> ! {
+    tracing::error!("{message}");
+    std::process::exit(1);
+}
+
+/// Determines how to get information about the active window on the running
+/// operating system. With `stdin` the windows are read from stdin like from a
+/// streaming `window_script`, and both take precedence over the native API on
+/// every platform.
+///
+/// # Panics
+///
+/// The function will panic if the platform has no supported native API and
+/// neither `window_script` nor `stdin` is given.
+fn window_provider(
+    window_script: Option<PathBuf>,
+    mode: WindowScriptMode,
+    stdin: bool,
+) -> Box<dyn WindowProvider> {
+    let window_script = if stdin {
+        Some(WindowScript::Stream(WindowStream::stdin()))
+    } else {
+        window_script.map(|script| match mode {
+            WindowScriptMode::Oneshot => WindowScript::Oneshot(script),
+            WindowScriptMode::Stream => WindowScript::Stream(WindowStream::spawn(script)),
+        })
+    };
+
+    if let Some(script) = window_script {
+        return Box::new(script);
     }
 
-    let os = match env::consts::OS {
-        "macos" => enums::OSIdent::MACOS,
-        "windows" => enums::OSIdent::WINDOWS,
+    match env::consts::OS {
+        "macos" | "windows" => Box::new(provider::Native),
         "linux" => {
             let session_type = env::var("XDG_SESSION_TYPE");
 
-            if let Some(script) = args.window_script {
-                enums::OSIdent::LINUX(enums::LinuxServer::WAYLAND(script))
-            } else if session_type
+            if session_type
                 .as_ref()
                 .is_ok_and(|session| session == "wayland")
                 || env::var("WAYLAND_DISPLAY").is_ok_and(|display| !display.is_empty())
             {
-                panic!("Wayland has no proper API for active window information. See --window-script,-s as well as the readme!")
+                let script = bundled_window_script().unwrap_or_else(|| {
+                    panic!("Wayland has no proper API for active window information. See --window-script,-s as well as the readme!")
+                });
+                Box::new(WindowScript::Oneshot(script))
             } else if session_type.as_ref().is_ok_and(|session| session == "x11") {
-                enums::OSIdent::LINUX(enums::LinuxServer::XORG)
+                Box::new(provider::X11::new())
             } else {
                 panic!("Unrecognized display server. See --window-script,-s as well as the readme!")
             }
         }
-        _ => {
-            if let Some(script) = args.window_script {
-                enums::OSIdent::UNSUPPORTED(script)
+        _ => panic!("Unsupported platform: See --window-script,-s as well as the readme!"),
+    }
+}
+
+/// Window scripts for Wayland compositors that are bundled with the daemon:
+/// (name, environment variable only set by the compositor, script).
+const BUNDLED_WINDOW_SCRIPTS: [(&str, &str, &str); 4] = [
+    (
+        "sway",
+        "SWAYSOCK",
+        include_str!("../examples/window-scripts/sway.sh"),
+    ),
+    (
+        "hyprland",
+        "HYPRLAND_INSTANCE_SIGNATURE",
+        include_str!("../examples/window-scripts/hyprland.sh"),
+    ),
+    (
+        "gnome",
+        "GNOME_SHELL_SESSION_MODE",
+        include_str!("../examples/window-scripts/gnome.sh"),
+    ),
+    (
+        "kde",
+        "KDE_FULL_SESSION",
+        include_str!("../examples/window-scripts/kde.sh"),
+    ),
+];
+
+/// Runs a subcommand instead of the daemon and returns its exit code.
+/// `config_path` is the `--config` option.
+fn run_command(command: Subcommand, config_path: Option<PathBuf>) -> i32 {
+    match command {
+        Subcommand::Test {
+            app,
+            title,
+            process,
+            path,
+            cmdline,
+            url,
+            wm_instance,
+            wm_role,
+            idle,
+            monitor,
+            fullscreen,
+            empty_desktop,
+        } => {
+            // unlike the daemon, no default config is created
+            let config_path = config_path.unwrap_or_else(default_config_path);
+            if !config_path.exists() {
+                eprintln!("{}: no config file found", config_path.display());
+                return 1;
+            }
+            let config = match read_config(&config_path) {
+                Ok(config) => config,
+                Err(err) => {
+                    eprintln!("{}: {err}", config_path.display());
+                    return 1;
+                }
+            };
+            let window = WindowInfo {
+                app_name: app,
+                process_name: process,
+                title: config.normalized_title(&title),
+                process_path: path.unwrap_or_default(),
+                cmdline,
+                fullscreen,
+                url,
+                wm_instance,
+                wm_role,
+                ..WindowInfo::default()
+            };
+            let desktop = DesktopState {
+                idle: idle.map(Duration::from_secs),
+                empty: empty_desktop,
+                monitor,
+            };
+
+            test_rules(&config, &window, &desktop);
+            0
+        }
+        Subcommand::Validate => validate(&config_path.unwrap_or_else(default_config_path)),
+    }
+}
+
+/// Checks the config file at `path` with the same validations the daemon uses
+/// when loading it and prints a report. Returns `1` if the config is invalid
+/// or has warnings (see `Config::warnings`), `0` otherwise.
+fn validate(path: &Path) -> i32 {
+    match read_config(path) {
+        Ok(config) => {
+            let warnings = config.warnings();
+
+            for warning in &warnings {
+                println!("{}: {warning}", path.display());
+            }
+
+            if warnings.is_empty() {
+                println!("{}: ok", path.display());
+                0
             } else {
-                panic!("Unsupported platform: See --window-script,-s as well as the readme!")
+                1
             }
         }
-    };
+        Err(err) => {
+            println!("{}: {err}", path.display());
+            1
+        }
+    }
+}
 
-    let mut sys = if System::IS_SUPPORTED {
-        Some(System::new_with_specifics(
-            RefreshKind::new().with_processes(ProcessRefreshKind::new()),
-        ))
-    } else {
-        None
+/// Prints the rule and profile that `config` selects for `window`, like the
+/// daemon would on switching to it.
+fn test_rules(config: &Config, window: &WindowInfo, desktop: &DesktopState) {
+    if let Some(entry) = config.ignored(window).filter(|_| !desktop.empty) {
+        println!("The window is ignored by ignore_list entry {entry}, keeping the profile");
+        return;
+    }
+
+    match next_match(config, window, desktop) {
+        Some(Match {
+            target,
+            rule: Some(rule),
+        }) => println!("Rule {rule} matches, {target}"),
+        Some(Match { target, rule: None }) => {
+            println!("No rule matches, {target} (the default_profile)");
+        }
+        None if desktop.empty => {
+            println!("No empty_desktop rule matches, keeping the profile");
+        }
+        None => println!("No rule matches and there is no default_profile, keeping the profile"),
+    }
+
+    if !config.autoswitch_enabled() {
+        println!("Note: autoswitch_enabled is false, so the daemon wouldn't switch at all");
+    }
+}
+
+/// Detects the running Wayland compositor from `XDG_CURRENT_DESKTOP` (or the
+/// compositor's own environment variables) and writes the bundled window
+/// script for it to the cache directory. Returns `None` if the compositor is
+/// unknown.
+fn bundled_window_script() -> Option<PathBuf> {
+    let desktop = env::var("XDG_CURRENT_DESKTOP").unwrap_or_default();
+    let (name, script) = detect_compositor(&desktop, |var| env::var_os(var).is_some())?;
+
+    let mut path = dirs_next::cache_dir()?;
+    path.push("duckypad_daemon");
+    path.push(format!("{name}.sh"));
+
+    let written = path
+        .parent()
+        .map_or(Ok(()), std::fs::create_dir_all)
+        .and_then(|()| std::fs::write(&path, script));
+    #[cfg(unix)]
+    let written = written.and_then(|()| {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755))
+    });
+
+    match written {
+        Ok(()) => {
+            tracing::info!(
+                "Detected {name}, using bundled window script {}",
+                path.display()
+            );
+            Some(path)
+        }
+        Err(err) => {
+            tracing::error!(
+                "Failed to write bundled window script {}: {err}",
+                path.display()
+            );
+            None
+        }
+    }
+}
+
+/// Returns the name and bundled window script of the compositor listed in
+/// `desktop` (the value of `XDG_CURRENT_DESKTOP`) or whose environment
+/// variable is set according to `is_set`.
+fn detect_compositor(
+    desktop: &str,
+    is_set: impl Fn(&str) -> bool,
+) -> Option<(&'static str, &'static str)> {
+    BUNDLED_WINDOW_SCRIPTS
+        .iter()
+        .find(|(name, var, _)| {
+            desktop
+                .split(':')
+                .any(|desktop| desktop.eq_ignore_ascii_case(name))
+                || is_set(var)
+        })
+        .map(|(name, _, script)| (*name, *script))
+}
+
+/// Returns a flag that gets set whenever the daemon receives the signal
+/// `signal`. SIGHUP requests reloading the config file and SIGUSR1 toggles
+/// pausing autoswitching. On platforms without these signals the flag is
+/// never set.
+fn signal_flag(signal: i32) -> Arc<AtomicBool> {
+    let flag = Arc::new(AtomicBool::new(false));
+
+    #[cfg(unix)]
+    signal_hook::flag::register(signal, Arc::clone(&flag))
+        .unwrap_or_else(|err| panic!("Failed to register handler for signal {signal}: {err}"));
+    #[cfg(not(unix))]
+    let _ = signal;
+
+    flag
+}
+
+/// Returns a flag that gets set when the daemon receives SIGTERM or SIGINT,
+/// so the main loop can shut down cleanly. If the daemon gets one of them
+/// again before it exits, it exits immediately. On platforms without these
+/// signals the flag is never set.
+fn shutdown_flag() -> Arc<AtomicBool> {
+    let flag = Arc::new(AtomicBool::new(false));
+
+    for signal in [SIGTERM, SIGINT] {
+        // registered first, so it only exits if the flag was set already
+        #[cfg(unix)]
+        signal_hook::flag::register_conditional_shutdown(signal, 1, Arc::clone(&flag))
+            .and_then(|_| signal_hook::flag::register(signal, Arc::clone(&flag)))
+            .unwrap_or_else(|err| panic!("Failed to register handler for signal {signal}: {err}"));
+        #[cfg(not(unix))]
+        let _ = signal;
+    }
+
+    flag
+}
+
+/// Sets up logging to stderr with the filter `level` or, if it isn't given,
+/// the filter from the `RUST_LOG` environment variable and `info` as fallback.
+/// With `explain`, why rules did or didn't match is logged as well (see
+/// `next_match`).
+///
+/// # Panics
+///
+/// The function will panic if `level` isn't a valid filter.
+fn init_logging(level: Option<&str>, explain: bool) {
+    let mut filter = match level {
+        Some(level) => EnvFilter::try_new(level)
+            .unwrap_or_else(|err| panic!("Invalid log level {level:?}: {err}")),
+        None => EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
     };
 
-    let mut prev_profile: Option<u32> = None;
+    if explain {
+        filter = filter.add_directive(
+            format!("{}=debug", duckypad_daemon::EXPLAIN_TARGET)
+                .parse()
+                .expect("Invalid explain directive"),
+        );
+    }
+
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr)
+        .init();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Detects the compositor like `bundled_window_script` with the
+    /// environment variables `vars` set.
+    fn detect(desktop: &str, vars: &[&str]) -> Option<&'static str> {
+        detect_compositor(desktop, |var| vars.contains(&var)).map(|(name, _)| name)
+    }
+
+    #[test]
+    fn detects_compositors_from_the_desktop() {
+        assert_eq!(detect("sway", &[]), Some("sway"));
+        assert_eq!(detect("Hyprland", &[]), Some("hyprland"));
+        assert_eq!(detect("ubuntu:GNOME", &[]), Some("gnome"));
+        assert_eq!(detect("KDE", &[]), Some("kde"));
+    }
+
+    #[test]
+    fn detects_compositors_from_their_variables() {
+        assert_eq!(detect("", &["SWAYSOCK"]), Some("sway"));
+        assert_eq!(
+            detect("", &["HYPRLAND_INSTANCE_SIGNATURE"]),
+            Some("hyprland")
+        );
+        assert_eq!(
+            detect("", &["WAYLAND_DISPLAY", "KDE_FULL_SESSION"]),
+            Some("kde")
+        );
+    }
+
+    #[test]
+    fn unknown_compositors_are_not_detected() {
+        assert_eq!(detect("", &[]), None);
+        assert_eq!(detect("niri", &["WAYLAND_DISPLAY"]), None);
+    }
 
-    loop {
-        prev_profile = switch_profile(&api, &mut sys, &config, prev_profile, &mut callback, &os);
-        std::thread::sleep(std::time::Duration::from_millis(250));
+    #[test]
+    fn detected_scripts_are_the_bundled_ones() {
+        let (_, script) = detect_compositor("sway", |_| false).unwrap();
+        assert_eq!(script, include_str!("../examples/window-scripts/sway.sh"));
     }
 }
@@ -0,0 +1,121 @@
+#[cfg(feature = "metrics")]
+use std::sync::Arc;
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{Mutex, PoisonError},
+};
+
+/// Counters about the daemon that can be rendered in the Prometheus text
+/// format. With the `metrics` feature they can be served over HTTP (see
+/// `serve`).
+#[derive(Default)]
+pub struct Metrics {
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    switches: BTreeMap<u32, u64>,
+    hid_errors: u64,
+    reconnects: u64,
+    current_profile: Option<u32>,
+}
+
+impl Metrics {
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Counts a switch to `profile`.
+    pub fn switched(&self, profile: u32) {
+        *self.state().switches.entry(profile).or_default() += 1;
+    }
+
+    /// Counts a failed communication with the duckypad.
+    pub fn hid_error(&self) {
+        self.state().hid_errors += 1;
+    }
+
+    /// Counts a reconnection to the duckypad.
+    pub fn reconnected(&self) {
+        self.state().reconnects += 1;
+    }
+
+    /// Sets the profile that is currently active on the duckypad.
+    pub fn set_current_profile(&self, profile: Option<u32>) {
+        self.state().current_profile = profile;
+    }
+
+    /// Renders the metrics in the Prometheus text format.
+    pub fn render(&self) -> String {
+        let state = self.state();
+        let mut out = String::new();
+
+        // writing to a String can't fail
+        let _ = writeln!(
+            out,
+            "# HELP duckypad_switches_total Profile switches.\n\
+            # TYPE duckypad_switches_total counter\n\
+            duckypad_switches_total {}",
+            state.switches.values().sum::<u64>()
+        );
+        let _ = writeln!(
+            out,
+            "# HELP duckypad_profile_switches_total Profile switches by target profile.\n\
+            # TYPE duckypad_profile_switches_total counter"
+        );
+        for (profile, count) in &state.switches {
+            let _ = writeln!(
+                out,
+                "duckypad_profile_switches_total{{profile=\"{profile}\"}} {count}"
+            );
+        }
+        let _ = writeln!(
+            out,
+            "# HELP duckypad_hid_errors_total Failed communications with the duckyPad.\n\
+            # TYPE duckypad_hid_errors_total counter\n\
+            duckypad_hid_errors_total {}\n\
+            # HELP duckypad_reconnects_total Reconnections to the duckyPad.\n\
+            # TYPE duckypad_reconnects_total counter\n\
+            duckypad_reconnects_total {}\n\
+            # HELP duckypad_current_profile Profile active on the duckyPad (0 if unknown).\n\
+            # TYPE duckypad_current_profile gauge\n\
+            duckypad_current_profile {}",
+            state.hid_errors,
+            state.reconnects,
+            state.current_profile.unwrap_or_default()
+        );
+
+        out
+    }
+}
+
+/// Serves `metrics` over HTTP at `addr` (e.g. `127.0.0.1:9184`) on a
+/// background thread. Every path returns the metrics.
+///
+/// # Errors
+///
+/// Will return an error if `addr` cannot be listened on.
+#[cfg(feature = "metrics")]
+pub fn serve(
+    addr: &str,
+    metrics: Arc<Metrics>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let server = tiny_http::Server::http(addr)?;
+    let content_type = tiny_http::Header::from_bytes("Content-Type", "text/plain; version=0.0.4")
+        .map_err(|()| "invalid content type header")?;
+
+    std::thread::spawn(move || {
+        for request in server.incoming_requests() {
+            let response = tiny_http::Response::from_string(metrics.render())
+                .with_header(content_type.clone());
+
+            if let Err(err) = request.respond(response) {
+                tracing::debug!("Failed to answer metrics request: {err}");
+            }
+        }
+    });
+
+    Ok(())
+}
@@ -0,0 +1,56 @@
+use crate::SwitchEvent;
+use notify_rust::Notification;
+use std::time::{Duration, Instant};
+
+/// Sends desktop notifications about profile switches. Notifications are rate
+/// limited: if multiple switches happen within `min_interval`, only the latest
+/// one gets shown once the interval has passed.
+pub struct Notifier {
+    min_interval: Duration,
+    last: Option<Instant>,
+    pending: Option<(u32, String)>,
+}
+
+impl Notifier {
+    /// Creates a `Notifier` that shows at most one notification per
+    /// `min_interval`.
+    pub fn new(min_interval: Duration) -> Self {
+        Notifier {
+            min_interval,
+            last: None,
+            pending: None,
+        }
+    }
+
+    /// Queues a notification for `event` and shows it if the rate limit
+    /// allows it (see `flush`).
+    pub fn notify(&mut self, event: &SwitchEvent) {
+        self.pending = Some((event.profile, event.window.app_name.clone()));
+        self.flush();
+    }
+
+    /// Shows the pending notification, if there is one and the last
+    /// notification was shown at least `min_interval` ago. Failures (e.g. no
+    /// running notification daemon) are only logged.
+    pub fn flush(&mut self) {
+        if self
+            .last
+            .is_some_and(|last| last.elapsed() < self.min_interval)
+        {
+            return;
+        }
+
+        if let Some((profile, app_name)) = self.pending.take() {
+            self.last = Some(Instant::now());
+            let body = if profile == crate::SLEEP {
+                format!("Went to sleep ({app_name})")
+            } else {
+                format!("Switched to profile {profile} ({app_name})")
+            };
+
+            if let Err(err) = Notification::new().summary("duckyPad").body(&body).show() {
+                tracing::warn!("Failed to show notification: {err}");
+            }
+        }
+    }
+}
@@ -0,0 +1,176 @@
+use crate::{uwp, window_script::WindowScript, x11, WindowInfo, WindowScriptError};
+use active_win_pos_rs::get_active_window;
+use std::time::Duration;
+
+/// Reasons why a `WindowProvider` couldn't report the active window.
+#[derive(Debug)]
+pub enum WindowError {
+    /// The window script failed (see `WindowScriptError`)
+    Script(WindowScriptError),
+    /// The native API of the platform didn't report a window
+    Native,
+}
+
+impl std::fmt::Display for WindowError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Script(err) => write!(f, "{err}"),
+            Self::Native => write!(f, "The native API didn't report an active window"),
+        }
+    }
+}
+
+impl std::error::Error for WindowError {}
+
+/// A source of information about the active window, chosen once on startup.
+/// `switch_profile` only asks it for the active window and adds what every
+/// source has in common, e.g. the `app_name` from the process list.
+pub trait WindowProvider {
+    /// Returns the active window.
+    ///
+    /// # Errors
+    ///
+    /// Will return `WindowError` if the active window can't be determined.
+    fn active(&mut self) -> Result<WindowInfo, WindowError>;
+
+    /// Returns whether the windows come from an X server, which is required
+    /// for the rule conditions queried via X11 (idle time, monitor and
+    /// fullscreen state) and for noticing that no window is focused.
+    fn is_xorg(&self) -> bool {
+        false
+    }
+
+    /// Waits up to `timeout` for the active window to change and returns
+    /// whether it did. Sources that can't tell just sleep.
+    fn wait(&self, timeout: Duration) -> bool {
+        std::thread::sleep(timeout);
+        false
+    }
+
+    /// Returns whether `wait` reports every change of the active window, so
+    /// it only has to be checked again once `wait` returned `true`.
+    fn notifies(&self) -> bool {
+        false
+    }
+
+    /// Returns whether the source won't report any further windows, e.g.
+    /// because stdin was closed (see `--stdin`), so the daemon exits after
+    /// checking the last one.
+    fn closed(&self) -> bool {
+        false
+    }
+}
+
+/// The native API of Windows and macOS via `active-win-pos-rs`. The windows
+/// of UWP apps are resolved to the app inside `ApplicationFrameHost.exe` (see
+/// `uwp::foreground_app`).
+pub struct Native;
+
+impl WindowProvider for Native {
+    fn active(&mut self) -> Result<WindowInfo, WindowError> {
+        let mut window = get_active_window()
+            .map(WindowInfo::from)
+            .map_err(|()| WindowError::Native)?;
+
+        if uwp::is_frame_host(&window.process_path) {
+            if let Some(app) = uwp::foreground_app(window.process_id) {
+                window.process_id = app.process_id;
+                window.process_path = app.process_path;
+                if let Some(aumid) = app.aumid {
+                    window.process_name = aumid;
+                }
+            } else {
+                tracing::debug!(
+                    "Couldn't find the UWP app in ApplicationFrameHost.exe, using the frame host"
+                );
+            }
+        }
+
+        Ok(window)
+    }
+}
+
+/// An X server, queried via `active-win-pos-rs` and directly for what it
+/// doesn't report properly. Focus changes end the wait for the next poll
+/// early (see `x11::ActiveWindowEvents`), so they are handled right away, and
+/// without them the active window isn't checked at all (see `notifies`).
+pub struct X11 {
+    events: Option<x11::ActiveWindowEvents>,
+}
+
+impl X11 {
+    /// Returns the provider and starts listening for focus changes.
+    pub fn new() -> Self {
+        Self {
+            events: x11::ActiveWindowEvents::listen(),
+        }
+    }
+}
+
+impl Default for X11 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl WindowProvider for X11 {
+    fn active(&mut self) -> Result<WindowInfo, WindowError> {
+        let mut window = get_active_window()
+            .map(WindowInfo::from)
+            .map_err(|()| WindowError::Native)?;
+
+        // active-win-pos-rs drops titles that aren't valid UTF-8
+        if let Some(title) = x11::window_title(&window.window_id) {
+            window.title = title;
+        }
+        // and only reports the class of WM_CLASS
+        if let Some((instance, _)) = x11::wm_class(&window.window_id) {
+            window.wm_instance = instance;
+        }
+        if let Some(role) = x11::wm_role(&window.window_id) {
+            window.wm_role = role;
+        }
+
+        Ok(window)
+    }
+
+    fn is_xorg(&self) -> bool {
+        true
+    }
+
+    fn wait(&self, timeout: Duration) -> bool {
+        if let Some(events) = &self.events {
+            events.wait(timeout)
+        } else {
+            std::thread::sleep(timeout);
+            false
+        }
+    }
+
+    fn notifies(&self) -> bool {
+        self.events.is_some()
+    }
+}
+
+impl WindowProvider for WindowScript {
+    fn active(&mut self) -> Result<WindowInfo, WindowError> {
+        self.active_window().map_err(WindowError::Script)
+    }
+
+    fn wait(&self, timeout: Duration) -> bool {
+        match self {
+            Self::Stream(stream) => stream.wait(timeout),
+            Self::Oneshot(_) => {
+                std::thread::sleep(timeout);
+                false
+            }
+        }
+    }
+
+    fn closed(&self) -> bool {
+        match self {
+            Self::Stream(stream) => stream.closed(),
+            Self::Oneshot(_) => false,
+        }
+    }
+}
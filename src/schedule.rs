@@ -0,0 +1,89 @@
+use chrono::{Datelike, NaiveDateTime, NaiveTime, Weekday};
+use std::str::FromStr;
+
+/// Time window in which a rule is active, parsed from a rule's `active_time`.
+///
+/// The format is an optional comma-separated list of weekdays or weekday
+/// ranges followed by an optional time range, e.g. `"09:00-17:00"`,
+/// `"Mon-Fri 09:00-17:00"` or `"Sat,Sun"`. Time ranges include their start
+/// and exclude their end and may span midnight (`"22:00-02:00"`), in which
+/// case the weekdays refer to the day of the current time.
+pub struct ActiveTime {
+    /// Bitmask of the active weekdays (bit 0 is monday), all days if `None`
+    weekdays: Option<u8>,
+    /// Start and end of the active time of day, the whole day if `None`
+    hours: Option<(NaiveTime, NaiveTime)>,
+}
+
+impl ActiveTime {
+    /// Returns whether `now` lies within the time window.
+    pub fn contains(&self, now: NaiveDateTime) -> bool {
+        let weekday = self
+            .weekdays
+            .is_none_or(|days| days & 1 << now.weekday().num_days_from_monday() != 0);
+        let time = now.time();
+        let hours = self.hours.is_none_or(|(start, end)| {
+            if start <= end {
+                start <= time && time < end
+            } else {
+                start <= time || time < end
+            }
+        });
+
+        weekday && hours
+    }
+}
+
+impl FromStr for ActiveTime {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut active_time = Self {
+            weekdays: None,
+            hours: None,
+        };
+
+        for part in s.split_whitespace() {
+            if part.contains(':') && active_time.hours.is_none() {
+                let (start, end) = part.split_once('-').ok_or(())?;
+                active_time.hours = Some((parse_time(start)?, parse_time(end)?));
+            } else if !part.contains(':') && active_time.weekdays.is_none() {
+                active_time.weekdays = Some(parse_weekdays(part)?);
+            } else {
+                return Err(());
+            }
+        }
+
+        if active_time.weekdays.is_none() && active_time.hours.is_none() {
+            return Err(());
+        }
+
+        Ok(active_time)
+    }
+}
+
+fn parse_time(time: &str) -> Result<NaiveTime, ()> {
+    NaiveTime::parse_from_str(time, "%H:%M").map_err(|_| ())
+}
+
+/// Parses a list like `Mon-Wed,Fri` into a bitmask of weekdays. Ranges may
+/// wrap around the end of the week (`Fri-Mon`).
+fn parse_weekdays(days: &str) -> Result<u8, ()> {
+    let mut mask = 0;
+
+    for item in days.split(',') {
+        let (start, end) = item.split_once('-').unwrap_or((item, item));
+        let mut day = Weekday::from_str(start).map_err(|_| ())?;
+        let end = Weekday::from_str(end).map_err(|_| ())?;
+
+        loop {
+            mask |= 1 << day.num_days_from_monday();
+            if day == end {
+                break;
+            }
+            day = day.succ();
+        }
+    }
+
+    Ok(mask)
+}
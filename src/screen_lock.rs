@@ -0,0 +1,103 @@
+use crate::x11;
+
+/// Detects whether the screen is locked, so autoswitching can be paused while
+/// the lock screen has the focus (see `--pause-on-lock`). The session counts
+/// as locked if logind's `LockedHint` of the user's graphical session is set
+/// (requires the `dbus` feature) or, on X11, if the screensaver is active
+/// (see `x11::screensaver_active`).
+pub struct LockDetector {
+    #[cfg(all(unix, feature = "dbus"))]
+    session: Option<zbus::blocking::Proxy<'static>>,
+    xorg: bool,
+    /// whether the screen was locked on the last `update`
+    locked: bool,
+}
+
+impl LockDetector {
+    /// Connects to logind if possible. `xorg` is whether the daemon runs on
+    /// X11, where the screensaver is checked as well.
+    pub fn new(xorg: bool) -> Self {
+        #[cfg(all(unix, feature = "dbus"))]
+        let session = logind_session()
+            .inspect_err(|err| {
+                tracing::warn!("Couldn't get the lock state of the session from logind: {err}");
+            })
+            .ok();
+
+        #[cfg(not(all(unix, feature = "dbus")))]
+        if !xorg {
+            tracing::warn!(
+                "--pause-on-lock can only detect the lock screen via logind, which requires the dbus feature"
+            );
+        }
+
+        Self {
+            #[cfg(all(unix, feature = "dbus"))]
+            session,
+            xorg,
+            locked: false,
+        }
+    }
+
+    /// Returns whether the screen was locked on the last `update`.
+    pub fn locked(&self) -> bool {
+        self.locked
+    }
+
+    /// Checks whether the screen is locked and returns the new state if it
+    /// changed since the last call.
+    pub fn update(&mut self) -> Option<bool> {
+        let locked = self.is_locked();
+        (locked != self.locked).then(|| {
+            self.locked = locked;
+            locked
+        })
+    }
+
+    /// Returns whether the screen is currently locked. Errors count as
+    /// unlocked, so a broken detection never stops autoswitching for good.
+    fn is_locked(&self) -> bool {
+        #[cfg(all(unix, feature = "dbus"))]
+        if let Some(session) = &self.session {
+            // cached by zbus and updated on PropertiesChanged, so no round trip
+            match session.get_property::<bool>("LockedHint") {
+                Ok(true) => return true,
+                Ok(false) => {}
+                Err(err) => tracing::debug!("Couldn't query LockedHint from logind: {err}"),
+            }
+        }
+
+        self.xorg && x11::screensaver_active() == Some(true)
+    }
+}
+
+/// Returns a proxy for the graphical logind session of the user, which is
+/// found via the `Display` property of the user, so it also works if the
+/// daemon runs outside of the session (e.g. as a systemd user service).
+#[cfg(all(unix, feature = "dbus"))]
+fn logind_session() -> zbus::Result<zbus::blocking::Proxy<'static>> {
+    const LOGIND: &str = "org.freedesktop.login1";
+
+    let connection = zbus::blocking::Connection::system()?;
+    let user = zbus::blocking::Proxy::new(
+        &connection,
+        LOGIND,
+        "/org/freedesktop/login1/user/self",
+        "org.freedesktop.login1.User",
+    )?;
+    let (id, path): (String, zbus::zvariant::OwnedObjectPath) = user.get_property("Display")?;
+
+    if id.is_empty() {
+        return Err(zbus::Error::Failure(
+            "the user has no graphical session".to_string(),
+        ));
+    }
+
+    tracing::debug!("Watching the lock state of logind session {id}");
+    zbus::blocking::Proxy::new_owned(
+        connection,
+        LOGIND,
+        path.into_inner(),
+        "org.freedesktop.login1.Session",
+    )
+}
@@ -0,0 +1,81 @@
+use serde::Serialize;
+use std::path::PathBuf;
+
+/// What the status file tells status bars about the daemon.
+#[derive(Serialize, PartialEq, Eq)]
+pub struct Status {
+    /// id of the active profile, `None` if unknown
+    pub profile: Option<u32>,
+    /// name of the active profile on the duckypad, or else from the config's
+    /// `profile_names` (see `Config::profile_name`)
+    pub profile_name: Option<String>,
+    /// app name of the window that caused the last autoswitch to the active
+    /// profile, `None` if the profile was changed otherwise
+    pub app_name: Option<String>,
+    /// index of the rule that switched to the active profile, see
+    /// `SwitchEvent::rule`
+    pub rule: Option<usize>,
+    /// whether autoswitching is paused (including the lock screen with
+    /// `--pause-on-lock`)
+    pub paused: bool,
+}
+
+/// A file that always contains the current `Status` as a single-line JSON
+/// object, e.g. for a status bar:
+/// ```json
+/// {"profile":3,"profile_name":"browser","app_name":"firefox","rule":0,"paused":false}
+/// ```
+/// It is replaced atomically, so readers never see a half-written file, and
+/// removed once the daemon exits.
+pub struct StatusFile {
+    path: PathBuf,
+    /// the status written last, so unchanged ones aren't written again
+    written: Option<Status>,
+}
+
+impl StatusFile {
+    /// Creates a status file at `path`, which is written on the first call of
+    /// `write`.
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            written: None,
+        }
+    }
+
+    /// Writes `status` to the status file if it differs from the one written
+    /// last. It is written to `<path>.tmp` first and then renamed to `path`.
+    /// Errors are logged, as a broken status file shouldn't stop the daemon
+    /// from switching profiles.
+    pub fn write(&mut self, status: Status) {
+        if self.written.as_ref() == Some(&status) {
+            return;
+        }
+
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+
+        let result = serde_json::to_string(&status)
+            .map_err(std::io::Error::from)
+            .and_then(|json| std::fs::write(&tmp, json + "\n"))
+            .and_then(|()| std::fs::rename(&tmp, &self.path));
+
+        match result {
+            Ok(()) => self.written = Some(status),
+            Err(err) => {
+                tracing::warn!("Failed to write status file {}: {err}", self.path.display());
+            }
+        }
+    }
+}
+
+impl Drop for StatusFile {
+    fn drop(&mut self) {
+        if let Err(err) = std::fs::remove_file(&self.path) {
+            tracing::debug!(
+                "Failed to remove status file {}: {err}",
+                self.path.display()
+            );
+        }
+    }
+}
@@ -0,0 +1,67 @@
+use sd_notify::NotifyState;
+use std::time::{Duration, Instant};
+
+/// Reports the state of the daemon to systemd when it runs as a service with
+/// `Type=notify`: readiness, watchdog keep-alives (if `WatchdogSec=` is set)
+/// and a status line shown by `systemctl status`. Outside of systemd nothing is
+/// sent.
+pub struct Systemd {
+    watchdog: Option<Duration>,
+    last_watchdog: Instant,
+    status: String,
+}
+
+impl Systemd {
+    pub fn new() -> Self {
+        Self {
+            // keep-alives are sent twice per watchdog interval, as recommended
+            // by sd_watchdog_enabled(3)
+            watchdog: sd_notify::watchdog_enabled().map(|interval| interval / 2),
+            last_watchdog: Instant::now(),
+            status: String::new(),
+        }
+    }
+
+    /// Tells systemd that the daemon finished starting up.
+    pub fn ready(&self) {
+        notify(&[NotifyState::Ready]);
+    }
+
+    /// Tells systemd that the daemon is shutting down.
+    pub fn stopping(&self) {
+        notify(&[NotifyState::Stopping]);
+    }
+
+    /// Sends a watchdog keep-alive if the last one is older than half of the
+    /// watchdog interval. Needs to be called regularly while the daemon works.
+    pub fn watchdog(&mut self) {
+        if self
+            .watchdog
+            .is_some_and(|interval| self.last_watchdog.elapsed() >= interval)
+        {
+            self.last_watchdog = Instant::now();
+            notify(&[NotifyState::Watchdog]);
+        }
+    }
+
+    /// Updates the status line of the service if it differs from the current
+    /// one.
+    pub fn status(&mut self, status: String) {
+        if self.status != status {
+            notify(&[NotifyState::Status(&status)]);
+            self.status = status;
+        }
+    }
+}
+
+impl Default for Systemd {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn notify(state: &[NotifyState]) {
+    if let Err(err) = sd_notify::notify(state) {
+        tracing::debug!("Failed to notify systemd: {err}");
+    }
+}
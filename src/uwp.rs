@@ -0,0 +1,165 @@
+use std::path::{Path, PathBuf};
+
+#[cfg(windows)]
+use windows::{
+    core::PWSTR,
+    Win32::{
+        Foundation::{CloseHandle, BOOL, ERROR_SUCCESS, FALSE, HANDLE, HWND, LPARAM, TRUE},
+        Storage::Packaging::Appx::GetApplicationUserModelId,
+        System::Threading::{
+            OpenProcess, QueryFullProcessImageNameW, PROCESS_NAME_WIN32,
+            PROCESS_QUERY_LIMITED_INFORMATION,
+        },
+        UI::WindowsAndMessaging::{
+            EnumChildWindows, GetForegroundWindow, GetWindowThreadProcessId,
+        },
+    },
+};
+
+/// Maximum length of an Application User Model ID, including the terminating
+/// null (`APPLICATION_USER_MODEL_ID_MAX_LENGTH`).
+#[cfg(windows)]
+const AUMID_MAX_LENGTH: usize = 130;
+
+/// The UWP (Microsoft Store) app shown in a window of
+/// `ApplicationFrameHost.exe`.
+pub struct UwpApp {
+    /// id of the app's own process
+    pub process_id: u64,
+    /// path of the app's executable, e.g.
+    /// `C:\Program Files\WindowsApps\...\HxOutlook.exe`
+    pub process_path: PathBuf,
+    /// Application User Model ID of the app, e.g.
+    /// `microsoft.windowscommunicationsapps_8wekyb3d8bbwe!microsoft.windowslive.mail`,
+    /// if it can be queried
+    pub aumid: Option<String>,
+}
+
+/// Returns whether the executable at `process_path` is
+/// `ApplicationFrameHost.exe`, which draws the frames of all UWP apps, so
+/// their windows belong to it instead of the app.
+pub fn is_frame_host(process_path: &Path) -> bool {
+    process_path
+        .file_name()
+        .is_some_and(|name| name.eq_ignore_ascii_case("ApplicationFrameHost.exe"))
+}
+
+/// An open handle of a process, closed on drop.
+#[cfg(windows)]
+struct Process(HANDLE);
+
+#[cfg(windows)]
+impl Process {
+    fn open(process_id: u32) -> Option<Self> {
+        unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, process_id) }
+            .ok()
+            .map(Self)
+    }
+
+    /// Returns the full path of the process' executable.
+    fn path(&self) -> Option<PathBuf> {
+        let mut buf = vec![0u16; 1024];
+        let mut len = u32::try_from(buf.len()).ok()?;
+
+        unsafe {
+            QueryFullProcessImageNameW(
+                self.0,
+                PROCESS_NAME_WIN32,
+                PWSTR(buf.as_mut_ptr()),
+                &mut len,
+            )
+        }
+        .as_bool()
+        .then(|| PathBuf::from(String::from_utf16_lossy(&buf[..len as usize])))
+    }
+
+    /// Returns the Application User Model ID of the process, or `None` if it
+    /// isn't a packaged app.
+    fn aumid(&self) -> Option<String> {
+        let mut buf = [0u16; AUMID_MAX_LENGTH];
+        let mut len = u32::try_from(buf.len()).ok()?;
+
+        let result =
+            unsafe { GetApplicationUserModelId(self.0, &mut len, PWSTR(buf.as_mut_ptr())) };
+
+        // the length includes the terminating null
+        (result == ERROR_SUCCESS)
+            .then(|| String::from_utf16_lossy(&buf[..(len as usize).saturating_sub(1)]))
+    }
+}
+
+#[cfg(windows)]
+impl Drop for Process {
+    fn drop(&mut self) {
+        unsafe { CloseHandle(self.0) };
+    }
+}
+
+/// Returns the UWP app in the foreground window if it belongs to the
+/// `ApplicationFrameHost.exe` process with the id `host_id`. The app draws its
+/// content into a child window (`Windows.UI.Core.CoreWindow`) of the frame,
+/// which belongs to the app's own process, so the first child of another
+/// process is taken.
+///
+/// Returns `None` if the foreground window changed in the meantime or has no
+/// such child, e.g. while the app is still starting or minimized, as Windows
+/// suspends minimized UWP apps and detaches their content from the frame.
+#[cfg(windows)]
+pub fn foreground_app(host_id: u64) -> Option<UwpApp> {
+    /// State of the search for the app's child window.
+    struct Search {
+        host_id: u32,
+        app_id: Option<u32>,
+    }
+
+    unsafe extern "system" fn find_app(child: HWND, search: LPARAM) -> BOOL {
+        // SAFETY: `search` points to the `Search` of `foreground_app`, which
+        // outlives `EnumChildWindows`
+        let search = unsafe { &mut *(search.0 as *mut Search) };
+        let mut process_id = 0;
+        unsafe { GetWindowThreadProcessId(child, Some(std::ptr::addr_of_mut!(process_id))) };
+
+        if process_id != 0 && process_id != search.host_id {
+            search.app_id = Some(process_id);
+            return FALSE;
+        }
+
+        TRUE
+    }
+
+    let frame = unsafe { GetForegroundWindow() };
+    let mut frame_id = 0;
+    unsafe { GetWindowThreadProcessId(frame, Some(std::ptr::addr_of_mut!(frame_id))) };
+
+    if u64::from(frame_id) != host_id {
+        return None;
+    }
+
+    let mut search = Search {
+        host_id: frame_id,
+        app_id: None,
+    };
+    unsafe {
+        EnumChildWindows(
+            frame,
+            Some(find_app),
+            LPARAM(std::ptr::addr_of_mut!(search) as isize),
+        )
+    };
+
+    let app_id = search.app_id?;
+    let process = Process::open(app_id)?;
+
+    Some(UwpApp {
+        process_id: u64::from(app_id),
+        process_path: process.path()?,
+        aumid: process.aumid(),
+    })
+}
+
+/// Returns the UWP app in the foreground window, which only exists on
+/// Windows.
+#[cfg(not(windows))]
+pub fn foreground_app(_host_id: u64) -> Option<UwpApp> {
+    None
+}
@@ -0,0 +1,157 @@
+use std::{path::PathBuf, time::SystemTime};
+
+/// Identifies a version of the config file. The inode changes when an editor
+/// saves atomically by replacing the file, even if size and modification time
+/// stay the same.
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct Stamp {
+    modified: Option<SystemTime>,
+    len: u64,
+    #[cfg(unix)]
+    inode: u64,
+}
+
+impl Stamp {
+    fn of(path: &PathBuf) -> Option<Self> {
+        let metadata = std::fs::metadata(path).ok()?;
+
+        Some(Self {
+            modified: metadata.modified().ok(),
+            len: metadata.len(),
+            #[cfg(unix)]
+            inode: std::os::unix::fs::MetadataExt::ino(&metadata),
+        })
+    }
+
+    /// Returns the stamps of all `paths`, or `None` if any of them is missing.
+    fn all(paths: &[PathBuf]) -> Option<Vec<Self>> {
+        paths.iter().map(Self::of).collect()
+    }
+}
+
+/// Detects changes of the config file and the files it includes by checking
+/// their metadata whenever `changed` is called. As the paths are checked
+/// instead of open files, editors that replace a file on save (e.g. vim or VS
+/// Code) are noticed as well, and so are files that get deleted and written
+/// again later (e.g. by a dotfile manager).
+pub struct ConfigWatcher {
+    paths: Vec<PathBuf>,
+    loaded: Option<Vec<Stamp>>,
+    pending: Option<Vec<Stamp>>,
+    /// whether a file was missing on the last call, so it is only logged once
+    missing: bool,
+}
+
+impl ConfigWatcher {
+    /// Starts watching the config files at `paths`, whose current versions
+    /// count as loaded.
+    pub fn new(paths: Vec<PathBuf>) -> Self {
+        let loaded = Stamp::all(&paths);
+
+        Self {
+            paths,
+            loaded,
+            pending: None,
+            missing: false,
+        }
+    }
+
+    /// Returns the paths of the watched config files.
+    pub fn paths(&self) -> &[PathBuf] {
+        &self.paths
+    }
+
+    /// Returns whether any of the config files changed since they were last
+    /// loaded. A change is only reported once the files stayed the same
+    /// between two calls, so a file that is still being written isn't loaded
+    /// half-way. A missing file (e.g. while it is being replaced) is never
+    /// reported, but its recreation is.
+    pub fn changed(&mut self) -> bool {
+        let Some(current) = Stamp::all(&self.paths) else {
+            if !self.missing {
+                if let Some(path) = self.paths.iter().find(|path| Stamp::of(path).is_none()) {
+                    tracing::info!(
+                        "Config file {} is missing, waiting for it to be recreated",
+                        path.display()
+                    );
+                }
+                self.missing = true;
+            }
+            self.pending = None;
+            return false;
+        };
+        self.missing = false;
+
+        if self.loaded.as_ref() == Some(&current) {
+            self.pending = None;
+            return false;
+        }
+
+        if self.pending.as_ref() == Some(&current) {
+            self.loaded = Some(current);
+            self.pending = None;
+            return true;
+        }
+
+        self.pending = Some(current);
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns a path in the temporary directory that no other test uses.
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "duckypad_daemon-test-{}-{name}",
+            std::process::id()
+        ))
+    }
+
+    /// Replaces the file at `path` with `contents` like editors that save
+    /// atomically do.
+    fn replace(path: &PathBuf, contents: &str) {
+        let tmp = path.with_extension("swp");
+        std::fs::write(&tmp, contents).unwrap();
+        std::fs::rename(&tmp, path).unwrap();
+    }
+
+    #[test]
+    fn replaced_configs_are_picked_up() {
+        let path = temp_path("watched.json");
+        std::fs::write(&path, r#"{"autoswitch_enabled": true, "rules_list": []}"#).unwrap();
+        let mut watcher = ConfigWatcher::new(vec![path.clone()]);
+        assert!(!watcher.changed());
+
+        // same length, so only the inode (or modification time) differs
+        replace(&path, r#"{"autoswitch_enabled": fals, "rules_list": []}"#);
+        let changes = [watcher.changed(), watcher.changed(), watcher.changed()];
+        replace(&path, r#"{"autoswitch_enabled": false, "rules_list": []}"#);
+        let fixed = [watcher.changed(), watcher.changed()];
+        let config = crate::read_config(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        // reported once the file stayed the same between two calls
+        assert_eq!(changes, [false, true, false]);
+        assert_eq!(fixed, [false, true]);
+        assert!(!config.unwrap().autoswitch_enabled());
+    }
+
+    #[test]
+    fn deleted_configs_are_picked_up_once_recreated() {
+        let path = temp_path("recreated.json");
+        std::fs::write(&path, "{}").unwrap();
+        let mut watcher = ConfigWatcher::new(vec![path.clone()]);
+
+        std::fs::remove_file(&path).unwrap();
+        let missing = [watcher.changed(), watcher.changed()];
+        std::fs::write(&path, "{\"rules_list\": []}").unwrap();
+        let recreated = [watcher.changed(), watcher.changed()];
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(missing, [false, false]);
+        assert_eq!(recreated, [false, true]);
+    }
+}
@@ -0,0 +1,178 @@
+use crate::{custom_active_window, parse_window, WindowInfo, WindowScriptError};
+use std::{
+    io::{BufRead, BufReader},
+    path::PathBuf,
+    process::{Command, Stdio},
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    time::Duration,
+};
+
+/// Time to wait before restarting a streaming window script that exited.
+const RESTART_DELAY: Duration = Duration::from_secs(1);
+
+/// Script that provides active window information on platforms without
+/// native APIs (see `--window-script`).
+pub enum WindowScript {
+    /// The script gets run on every poll and prints a single JSON object
+    Oneshot(PathBuf),
+    /// The script keeps running and prints a JSON object per line whenever
+    /// the active window changes
+    Stream(WindowStream),
+}
+
+impl WindowScript {
+    /// Returns the active window reported by the script.
+    ///
+    /// # Errors
+    ///
+    /// Will return `WindowScriptError` if the script cannot be run, its output
+    /// isn't a valid JSON object or a streaming script hasn't reported a window
+    /// yet.
+    pub fn active_window(&self) -> Result<WindowInfo, WindowScriptError> {
+        match self {
+            Self::Oneshot(script) => custom_active_window(script),
+            Self::Stream(stream) => stream.active_window(),
+        }
+    }
+}
+
+/// The latest window of a `WindowStream` and whether it arrived since the
+/// last call of `WindowStream::active_window`.
+#[derive(Default)]
+struct Latest {
+    window: Option<WindowInfo>,
+    fresh: bool,
+    /// whether no more windows arrive, because stdin was closed
+    closed: bool,
+}
+
+/// A window script that is kept running in the background (or the windows
+/// read from stdin, see `--stdin`). The latest window it printed is stored
+/// until the next line arrives.
+pub struct WindowStream {
+    latest: Arc<(Mutex<Latest>, Condvar)>,
+}
+
+impl WindowStream {
+    /// Starts `script` on a background thread and restarts it whenever it
+    /// exits.
+    pub fn spawn(script: PathBuf) -> Self {
+        let latest = Arc::new((Mutex::default(), Condvar::new()));
+        let stream = Self {
+            latest: Arc::clone(&latest),
+        };
+
+        std::thread::spawn(move || loop {
+            if let Err(err) = read_stream(&script, &latest) {
+                tracing::error!("{err}");
+            }
+
+            tracing::warn!("Window script {} exited, restarting it", script.display());
+            std::thread::sleep(RESTART_DELAY);
+        });
+
+        stream
+    }
+
+    /// Reads windows from stdin on a background thread, e.g. when the daemon
+    /// is driven by a window manager's focus hook. Once stdin is closed, the
+    /// stream is `closed`, so the daemon exits like a filter would after
+    /// handling the last window.
+    pub fn stdin() -> Self {
+        let latest = Arc::new((Mutex::default(), Condvar::new()));
+        let stream = Self {
+            latest: Arc::clone(&latest),
+        };
+
+        std::thread::spawn(move || {
+            read_lines(std::io::stdin().lock(), &latest);
+            tracing::info!("stdin was closed, exiting after its last window");
+
+            let (state, arrived) = &*latest;
+            lock(state).closed = true;
+            arrived.notify_all();
+        });
+
+        stream
+    }
+
+    /// Blocks until a new window arrived since the last call of
+    /// `active_window`, the stream was closed or `timeout` passed, so a new
+    /// window can be handled right away instead of on the next poll. Returns
+    /// whether a new window arrived.
+    pub fn wait(&self, timeout: Duration) -> bool {
+        let (latest, arrived) = &*self.latest;
+        let (latest, _) = arrived
+            .wait_timeout_while(lock(latest), timeout, |latest| {
+                !latest.fresh && !latest.closed
+            })
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+
+        latest.fresh
+    }
+
+    /// Returns whether no more windows arrive, because stdin was closed (see
+    /// `stdin`). The last window is still returned by `active_window`.
+    pub fn closed(&self) -> bool {
+        lock(&self.latest.0).closed
+    }
+
+    fn active_window(&self) -> Result<WindowInfo, WindowScriptError> {
+        let mut latest = lock(&self.latest.0);
+        latest.fresh = false;
+        latest.window.clone().ok_or(WindowScriptError::NoOutput)
+    }
+}
+
+fn lock(latest: &Mutex<Latest>) -> MutexGuard<'_, Latest> {
+    latest
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+}
+
+/// Runs `script` and stores every window it prints in `latest` until its
+/// output ends.
+fn read_stream(
+    script: &PathBuf,
+    latest: &(Mutex<Latest>, Condvar),
+) -> Result<(), WindowScriptError> {
+    let mut child = Command::new(script)
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|err| WindowScriptError::Spawn(script.clone(), err))?;
+    let stdout = child
+        .stdout
+        .take()
+        .expect("stdout of the window script is piped");
+
+    read_lines(BufReader::new(stdout), latest);
+
+    // the output of the script is closed, so it has (most likely) exited
+    let _: Result<_, _> = child.wait();
+    Ok(())
+}
+
+/// Stores every window read from `reader` (a JSON object per line) in
+/// `latest` and wakes up `WindowStream::wait`, until the input ends.
+fn read_lines(reader: impl BufRead, (latest, arrived): &(Mutex<Latest>, Condvar)) {
+    for line in reader.lines() {
+        let Ok(line) = line else {
+            break;
+        };
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        match parse_window(&line) {
+            Ok(window) => {
+                let mut latest = lock(latest);
+                latest.window = Some(window);
+                latest.fresh = true;
+                drop(latest);
+                arrived.notify_all();
+            }
+            Err(err) => tracing::warn!("{err}"),
+        }
+    }
+}
@@ -0,0 +1,681 @@
+use std::time::Duration;
+
+#[cfg(target_os = "linux")]
+use std::{
+    collections::HashMap,
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    time::Instant,
+};
+#[cfg(target_os = "linux")]
+use x11rb::{connection::Connection, errors::ReplyError, rust_connection::RustConnection};
+
+/// Time to wait before the first attempt to reconnect to the X server, doubled
+/// after every failed attempt up to `MAX_RECONNECT_INTERVAL`.
+#[cfg(target_os = "linux")]
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+#[cfg(target_os = "linux")]
+const MAX_RECONNECT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// A connection to the X server with its root window and the atoms interned
+/// so far. Atoms are only valid for the server they were interned on, so they
+/// are dropped together with the connection.
+#[cfg(target_os = "linux")]
+struct Server {
+    conn: RustConnection,
+    root: u32,
+    atoms: Mutex<HashMap<&'static [u8], u32>>,
+}
+
+#[cfg(target_os = "linux")]
+impl Server {
+    /// Returns the id of the atom `name`, or `None` if the X server doesn't
+    /// reply.
+    fn atom(&self, name: &'static [u8]) -> Option<u32> {
+        use x11rb::protocol::xproto::ConnectionExt;
+
+        let mut atoms = self
+            .atoms
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+
+        if let Some(atom) = atoms.get(name) {
+            return Some(*atom);
+        }
+
+        let atom = self.query("atom", |conn| {
+            Ok(conn.intern_atom(false, name)?.reply()?.atom)
+        })?;
+        atoms.insert(name, atom);
+        Some(atom)
+    }
+
+    /// Returns the text property `property` of `window` decoded according to
+    /// its type (see `decode_text`), or `None` if the window doesn't have it.
+    /// `what` describes the property in errors.
+    fn text(&self, window: u32, property: u32, what: &str) -> Option<String> {
+        use x11rb::protocol::xproto::{AtomEnum, ConnectionExt};
+
+        let reply = self
+            .query(what, |conn| {
+                conn.get_property(false, window, property, AtomEnum::ANY, 0, u32::MAX / 4)?
+                    .reply()
+            })
+            .filter(|reply| reply.format == 8 && reply.type_ != u32::from(AtomEnum::NONE))?;
+
+        let encoding = text_encoding(reply.type_, self.atom(b"UTF8_STRING"));
+        Some(decode_text(&reply.value, encoding))
+    }
+
+    /// Runs `request` on the connection and returns its reply. Errors are
+    /// logged as failing to query `what`. If the connection broke, it is
+    /// dropped, so the next call to `server` reconnects.
+    fn query<T>(
+        &self,
+        what: &str,
+        request: impl FnOnce(&RustConnection) -> Result<T, ReplyError>,
+    ) -> Option<T> {
+        match request(&self.conn) {
+            Ok(reply) => Some(reply),
+            Err(ReplyError::ConnectionError(err)) => {
+                // other queries might have noticed the broken connection already
+                let mut state = state();
+                if state
+                    .server
+                    .as_ref()
+                    .is_some_and(|current| std::ptr::eq(Arc::as_ptr(current), self))
+                {
+                    tracing::warn!("Lost the connection to the X server, reconnecting: {err}");
+                    state.server = None;
+                    state.next_attempt = None;
+                }
+                None
+            }
+            Err(err) => {
+                tracing::debug!("Couldn't query {what}: {err}");
+                None
+            }
+        }
+    }
+}
+
+/// The current connection to the X server, if any, and when to try connecting
+/// again if there is none.
+#[cfg(target_os = "linux")]
+struct State {
+    server: Option<Arc<Server>>,
+    next_attempt: Option<Instant>,
+    interval: Duration,
+    /// whether the last attempt to connect failed, so repeated failures are
+    /// only logged once
+    failed: bool,
+}
+
+#[cfg(target_os = "linux")]
+fn state() -> MutexGuard<'static, State> {
+    static STATE: Mutex<State> = Mutex::new(State {
+        server: None,
+        next_attempt: None,
+        interval: RECONNECT_INTERVAL,
+        failed: false,
+    });
+
+    STATE
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+}
+
+/// Returns the connection to the X server. Connecting on every poll would be
+/// wasteful, so the connection is created on first use and kept until it
+/// breaks (e.g. because the X server was restarted). Then reconnecting is
+/// attempted on later calls, waiting longer after every failed attempt.
+#[cfg(target_os = "linux")]
+fn server() -> Option<Arc<Server>> {
+    let mut state = state();
+
+    if let Some(server) = &state.server {
+        return Some(Arc::clone(server));
+    }
+
+    if state
+        .next_attempt
+        .is_some_and(|next_attempt| Instant::now() < next_attempt)
+    {
+        return None;
+    }
+
+    match x11rb::connect(None) {
+        Ok((conn, screen)) => {
+            if state.failed {
+                tracing::info!("Connected to the X server");
+            }
+
+            let root = conn.setup().roots[screen].root;
+            let server = Arc::new(Server {
+                conn,
+                root,
+                atoms: Mutex::new(HashMap::new()),
+            });
+            state.server = Some(Arc::clone(&server));
+            state.next_attempt = None;
+            state.interval = RECONNECT_INTERVAL;
+            state.failed = false;
+            Some(server)
+        }
+        Err(err) => {
+            if state.failed {
+                tracing::debug!("Couldn't connect to the X server: {err}");
+            } else {
+                tracing::warn!("Couldn't connect to the X server: {err}");
+            }
+
+            state.next_attempt = Some(Instant::now() + state.interval);
+            state.interval = (state.interval * 2).min(MAX_RECONNECT_INTERVAL);
+            state.failed = true;
+            None
+        }
+    }
+}
+
+/// Returns the time since the last user input, queried via the `XScreenSaver`
+/// extension of the X server. Returns `None` if the X server can't be reached
+/// or doesn't support the extension.
+///
+/// Only X11 is supported for now, as Wayland has no common protocol for
+/// querying the idle time.
+#[cfg(target_os = "linux")]
+pub fn idle_time() -> Option<Duration> {
+    use x11rb::protocol::screensaver::ConnectionExt;
+
+    let server = server()?;
+    let info = server.query("idle time", |conn| {
+        conn.screensaver_query_info(server.root)?.reply()
+    })?;
+
+    Some(Duration::from_millis(u64::from(info.ms_since_user_input)))
+}
+
+/// Returns whether the screensaver of the X server is active, which lockers
+/// like `xss-lock` or `xscreensaver` use for the lock screen. Returns `None`
+/// if the X server can't be reached or doesn't support the `XScreenSaver`
+/// extension.
+#[cfg(target_os = "linux")]
+pub fn screensaver_active() -> Option<bool> {
+    use x11rb::protocol::screensaver::{ConnectionExt, State};
+
+    let server = server()?;
+    let info = server.query("screensaver state", |conn| {
+        conn.screensaver_query_info(server.root)?.reply()
+    })?;
+
+    Some(info.state == u8::from(State::ON) || info.state == u8::from(State::CYCLE))
+}
+
+/// Returns the index of the monitor that contains the point (`x`, `y`), in
+/// the order reported by `RandR` (the same as `xrandr --listmonitors`).
+/// Returns `None` if the X server can't be reached or no monitor contains the
+/// point.
+#[cfg(target_os = "linux")]
+pub fn monitor_at(x: f64, y: f64) -> Option<u32> {
+    use x11rb::protocol::randr::ConnectionExt;
+
+    let server = server()?;
+    let monitors = server
+        .query("monitors", |conn| {
+            conn.randr_get_monitors(server.root, true)?.reply()
+        })?
+        .monitors;
+
+    monitors
+        .iter()
+        .position(|monitor| {
+            let (left, top) = (f64::from(monitor.x), f64::from(monitor.y));
+            let (width, height) = (f64::from(monitor.width), f64::from(monitor.height));
+
+            (left..left + width).contains(&x) && (top..top + height).contains(&y)
+        })
+        .and_then(|index| u32::try_from(index).ok())
+}
+
+/// Returns whether the window with the id `window_id` (as reported by
+/// `active-win-pos-rs`) has `_NET_WM_STATE_FULLSCREEN` in its `_NET_WM_STATE`.
+/// Returns `None` if the X server can't be reached or the state can't be
+/// queried.
+#[cfg(target_os = "linux")]
+pub fn is_fullscreen(window_id: &str) -> Option<bool> {
+    use x11rb::protocol::xproto::{AtomEnum, ConnectionExt};
+
+    let window = window_id.parse().ok()?;
+    let server = server()?;
+    let state = server.atom(b"_NET_WM_STATE")?;
+    let fullscreen = server.atom(b"_NET_WM_STATE_FULLSCREEN")?;
+
+    let reply = server.query("fullscreen state", |conn| {
+        conn.get_property(false, window, state, AtomEnum::ATOM, 0, 32)?
+            .reply()
+    })?;
+
+    Some(
+        reply
+            .value32()
+            .is_some_and(|mut atoms| atoms.any(|atom| atom == fullscreen)),
+    )
+}
+
+/// Returns whether `window_id` isn't an actual window, but one of the values
+/// X11 reports when no window is focused: `None` (`0`), `PointerRoot` (`1`)
+/// or the root window (e.g. after clicking the wallpaper of a desktop without
+/// a desktop window).
+#[cfg(target_os = "linux")]
+pub fn is_no_window(window_id: &str) -> bool {
+    const NONE: u32 = 0;
+    const POINTER_ROOT: u32 = 1;
+
+    window_id.parse().is_ok_and(|window: u32| {
+        window == NONE
+            || window == POINTER_ROOT
+            || server().is_some_and(|server| server.root == window)
+    })
+}
+
+/// Encodings of text properties of X11 windows, given by the type of the
+/// property.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextEncoding {
+    /// `UTF8_STRING`, used by `_NET_WM_NAME` and some clients for `WM_NAME`
+    Utf8,
+    /// `STRING`, which is ISO 8859-1 (Latin-1)
+    Latin1,
+    /// `COMPOUND_TEXT` or any other type, only the ASCII subset is decoded
+    Other,
+}
+
+/// Returns the encoding of a text property of the type `type_`, where
+/// `utf8_string` is the atom `UTF8_STRING` of the X server, if known.
+#[cfg(target_os = "linux")]
+fn text_encoding(type_: u32, utf8_string: Option<u32>) -> TextEncoding {
+    use x11rb::protocol::xproto::AtomEnum;
+
+    if Some(type_) == utf8_string {
+        TextEncoding::Utf8
+    } else if type_ == u32::from(AtomEnum::STRING) {
+        TextEncoding::Latin1
+    } else {
+        TextEncoding::Other
+    }
+}
+
+/// Decodes the `value` of a text property with `encoding`. Invalid bytes are
+/// replaced instead of dropping the whole text, so the rest of a title can
+/// still match rules.
+pub fn decode_text(value: &[u8], encoding: TextEncoding) -> String {
+    match encoding {
+        TextEncoding::Utf8 => String::from_utf8_lossy(value).into_owned(),
+        TextEncoding::Latin1 => value.iter().copied().map(char::from).collect(),
+        TextEncoding::Other => value
+            .iter()
+            .map(|&byte| {
+                if byte.is_ascii() {
+                    char::from(byte)
+                } else {
+                    char::REPLACEMENT_CHARACTER
+                }
+            })
+            .collect(),
+    }
+}
+
+/// Returns the title of the window with the id `window_id`: its
+/// `_NET_WM_NAME` or, for older clients that don't set it, the legacy
+/// `WM_NAME` decoded according to its type (see `decode_text`). Returns
+/// `None` if the X server can't be reached or the window has neither.
+#[cfg(target_os = "linux")]
+pub fn window_title(window_id: &str) -> Option<String> {
+    use x11rb::protocol::xproto::AtomEnum;
+
+    let window: u32 = window_id.parse().ok()?;
+    let server = server()?;
+    let net_wm_name = server.atom(b"_NET_WM_NAME")?;
+
+    server
+        .text(window, net_wm_name, "window title")
+        .or_else(|| server.text(window, AtomEnum::WM_NAME.into(), "window title"))
+}
+
+/// Returns the instance and class name from the `WM_CLASS` property of the
+/// window with the id `window_id`, e.g. `("Navigator", "firefox")`. Returns
+/// `None` if the X server can't be reached or the window doesn't set it.
+#[cfg(target_os = "linux")]
+pub fn wm_class(window_id: &str) -> Option<(String, String)> {
+    use x11rb::protocol::xproto::AtomEnum;
+
+    let window: u32 = window_id.parse().ok()?;
+    let server = server()?;
+    let wm_class = server.text(window, AtomEnum::WM_CLASS.into(), "WM_CLASS")?;
+
+    // two null-terminated strings
+    let mut parts = wm_class.split('\0');
+    let instance = parts.next().unwrap_or_default().to_string();
+    let class = parts.next().unwrap_or_default().to_string();
+
+    Some((instance, class))
+}
+
+/// Returns the `WM_WINDOW_ROLE` property of the window with the id
+/// `window_id`, which some applications set to tell their windows apart (e.g.
+/// `browser` and `pop-up` in Firefox). Returns `None` if the X server can't be
+/// reached or the window doesn't set it.
+#[cfg(target_os = "linux")]
+pub fn wm_role(window_id: &str) -> Option<String> {
+    let window: u32 = window_id.parse().ok()?;
+    let server = server()?;
+    let wm_window_role = server.atom(b"WM_WINDOW_ROLE")?;
+
+    server.text(window, wm_window_role, "WM_WINDOW_ROLE")
+}
+
+/// Blocks until the active window on the X server of `conn` changes, i.e.
+/// until the window manager sets `_NET_ACTIVE_WINDOW` of the `root` window,
+/// which it does on every focus change. Changes of the title of the active
+/// window aren't reported.
+///
+/// # Errors
+///
+/// Will return `ReplyError` if the X server can't be reached anymore.
+#[cfg(target_os = "linux")]
+pub fn wait_for_active_window_change(conn: &RustConnection, root: u32) -> Result<(), ReplyError> {
+    use x11rb::protocol::{
+        xproto::{ChangeWindowAttributesAux, ConnectionExt, EventMask},
+        Event,
+    };
+
+    let active_window = conn
+        .intern_atom(false, b"_NET_ACTIVE_WINDOW")?
+        .reply()?
+        .atom;
+    conn.change_window_attributes(
+        root,
+        &ChangeWindowAttributesAux::new().event_mask(EventMask::PROPERTY_CHANGE),
+    )?
+    .check()?;
+
+    loop {
+        if let Event::PropertyNotify(event) = conn.wait_for_event()? {
+            if event.window == root && event.atom == active_window {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Changes of the active window, noticed by a thread that waits for them
+/// with its own connection to the X server (see
+/// `wait_for_active_window_change`), so the main loop doesn't have to poll
+/// for them. While the X server can't be reached, the thread tries to
+/// reconnect like `server` does.
+#[cfg(target_os = "linux")]
+pub struct ActiveWindowEvents {
+    /// whether the active window changed since the last `wait`
+    changed: Arc<(Mutex<bool>, Condvar)>,
+}
+
+#[cfg(target_os = "linux")]
+impl ActiveWindowEvents {
+    /// Starts the thread listening for changes of the active window. Returns
+    /// `None` if it couldn't be started.
+    pub fn listen() -> Option<Self> {
+        let changed = Arc::new((Mutex::new(false), Condvar::new()));
+        let shared = Arc::clone(&changed);
+
+        std::thread::Builder::new()
+            .name("x11-events".to_string())
+            .spawn(move || watch_active_window(&shared))
+            .inspect_err(|err| {
+                tracing::warn!("Failed to listen for changes of the active window: {err}");
+            })
+            .ok()?;
+
+        Some(Self { changed })
+    }
+
+    /// Blocks until the active window changed since the last call or
+    /// `timeout` passed. Returns whether it changed.
+    pub fn wait(&self, timeout: Duration) -> bool {
+        let (changed, arrived) = &*self.changed;
+        let lock = changed
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        let (mut changed, _) = arrived
+            .wait_timeout_while(lock, timeout, |changed| !*changed)
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+
+        std::mem::take(&mut *changed)
+    }
+}
+
+/// Connects to the X server and sets `changed` on every change of the active
+/// window, reconnecting whenever the connection breaks.
+#[cfg(target_os = "linux")]
+fn watch_active_window(changed: &(Mutex<bool>, Condvar)) {
+    let mut interval = RECONNECT_INTERVAL;
+
+    loop {
+        match x11rb::connect(None) {
+            Ok((conn, screen)) => {
+                let root = conn.setup().roots[screen].root;
+                interval = RECONNECT_INTERVAL;
+                // changes while there was no connection went unnoticed
+                notify(changed);
+
+                loop {
+                    if let Err(err) = wait_for_active_window_change(&conn, root) {
+                        tracing::debug!("Stopped listening for window changes: {err}");
+                        break;
+                    }
+
+                    notify(changed);
+                }
+            }
+            Err(err) => tracing::debug!("Couldn't listen for window changes: {err}"),
+        }
+
+        std::thread::sleep(interval);
+        interval = (interval * 2).min(MAX_RECONNECT_INTERVAL);
+    }
+}
+
+/// Sets `changed` and wakes up `ActiveWindowEvents::wait`.
+#[cfg(target_os = "linux")]
+fn notify((flag, arrived): &(Mutex<bool>, Condvar)) {
+    *flag
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner) = true;
+    arrived.notify_all();
+}
+
+/// Changes of the active window, which only X11 reports.
+#[cfg(not(target_os = "linux"))]
+pub struct ActiveWindowEvents;
+
+#[cfg(not(target_os = "linux"))]
+impl ActiveWindowEvents {
+    /// Returns `None`, as there is no X server to listen to.
+    pub fn listen() -> Option<Self> {
+        None
+    }
+
+    /// Waits `timeout`, as changes are never reported.
+    pub fn wait(&self, timeout: Duration) -> bool {
+        std::thread::sleep(timeout);
+        false
+    }
+}
+
+/// Returns the time since the last user input, which isn't supported on this
+/// platform.
+#[cfg(not(target_os = "linux"))]
+pub fn idle_time() -> Option<Duration> {
+    None
+}
+
+/// Returns whether the screensaver is active, which isn't supported on this
+/// platform.
+#[cfg(not(target_os = "linux"))]
+pub fn screensaver_active() -> Option<bool> {
+    None
+}
+
+/// Returns the index of the monitor that contains a point, which isn't
+/// supported on this platform.
+#[cfg(not(target_os = "linux"))]
+pub fn monitor_at(_x: f64, _y: f64) -> Option<u32> {
+    None
+}
+
+/// Returns the title of a window, which isn't supported on this platform.
+#[cfg(not(target_os = "linux"))]
+pub fn window_title(_window_id: &str) -> Option<String> {
+    None
+}
+
+/// Returns the `WM_CLASS` of a window, which only X11 has.
+#[cfg(not(target_os = "linux"))]
+pub fn wm_class(_window_id: &str) -> Option<(String, String)> {
+    None
+}
+
+/// Returns the `WM_WINDOW_ROLE` of a window, which only X11 has.
+#[cfg(not(target_os = "linux"))]
+pub fn wm_role(_window_id: &str) -> Option<String> {
+    None
+}
+
+/// Returns whether a window id isn't an actual window, which only X11 has.
+#[cfg(not(target_os = "linux"))]
+pub fn is_no_window(_window_id: &str) -> bool {
+    false
+}
+
+/// Returns whether a window is fullscreen, which isn't supported on this
+/// platform.
+#[cfg(not(target_os = "linux"))]
+pub fn is_fullscreen(_window_id: &str) -> Option<bool> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_utf8_titles() {
+        // _NET_WM_NAME of "Größe – Firefox"
+        let value = b"Gr\xc3\xb6\xc3\x9fe \xe2\x80\x93 Firefox";
+        assert_eq!(decode_text(value, TextEncoding::Utf8), "Größe – Firefox");
+
+        // invalid bytes are replaced, the rest of the title is kept
+        assert_eq!(
+            decode_text(b"Gr\xf6\xdfe", TextEncoding::Utf8),
+            "Gr\u{fffd}\u{fffd}e"
+        );
+    }
+
+    #[test]
+    fn decodes_legacy_latin1_titles() {
+        // WM_NAME of type STRING of an older client
+        assert_eq!(
+            decode_text(b"Gr\xf6\xdfe - xterm", TextEncoding::Latin1),
+            "Größe - xterm"
+        );
+    }
+
+    #[test]
+    fn decodes_the_ascii_of_other_titles() {
+        // COMPOUND_TEXT with an escape sequence switching to Latin-1
+        let value = b"\x1b-AGr\xf6\xdfe";
+        assert_eq!(
+            decode_text(value, TextEncoding::Other),
+            "\u{1b}-AGr\u{fffd}\u{fffd}e"
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn text_encoding_depends_on_the_type() {
+        use x11rb::protocol::xproto::AtomEnum;
+
+        const UTF8_STRING: u32 = 300;
+        let string = u32::from(AtomEnum::STRING);
+
+        assert_eq!(
+            text_encoding(UTF8_STRING, Some(UTF8_STRING)),
+            TextEncoding::Utf8
+        );
+        assert_eq!(
+            text_encoding(string, Some(UTF8_STRING)),
+            TextEncoding::Latin1
+        );
+        // COMPOUND_TEXT, or UTF8_STRING if it couldn't be interned
+        assert_eq!(text_encoding(301, Some(UTF8_STRING)), TextEncoding::Other);
+        assert_eq!(text_encoding(UTF8_STRING, None), TextEncoding::Other);
+    }
+
+    /// Serializes the tests that replace the connection to the X server.
+    #[cfg(target_os = "linux")]
+    static SERVER: Mutex<()> = Mutex::new(());
+
+    /// Returns a server connected to `peer` instead of an X server, so
+    /// closing `peer` drops the connection like restarting the X server does.
+    #[cfg(target_os = "linux")]
+    fn fake_server() -> (Arc<Server>, std::os::unix::net::UnixStream) {
+        use x11rb::{protocol::xproto::Setup, rust_connection::DefaultStream};
+
+        let (stream, peer) = std::os::unix::net::UnixStream::pair().unwrap();
+        let (stream, _) = DefaultStream::from_unix_stream(stream).unwrap();
+        let setup = Setup {
+            resource_id_mask: 0x001f_ffff,
+            ..Setup::default()
+        };
+        let server = Server {
+            conn: RustConnection::for_connected_stream(stream, setup).unwrap(),
+            root: 0x0523,
+            atoms: Mutex::new(HashMap::new()),
+        };
+
+        (Arc::new(server), peer)
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn dropped_connections_are_reconnected_later() {
+        use x11rb::protocol::xproto::AtomEnum;
+
+        let _lock = SERVER
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        let (server, peer) = fake_server();
+        state().server = Some(Arc::clone(&server));
+        drop(peer);
+
+        let title = server.text(0x0040_0001, AtomEnum::WM_NAME.into(), "window title");
+        assert!(title.is_none());
+        // the next call to `server` connects again
+        assert!(state().server.is_none());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn root_and_pointer_root_are_no_windows() {
+        let _lock = SERVER
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        let (server, _peer) = fake_server();
+        state().server = Some(server);
+
+        let no_windows = ["0", "1", "1315"].map(is_no_window);
+        let windows = ["4194305", "not a window", ""].map(is_no_window);
+        state().server = None;
+
+        assert_eq!(no_windows, [true; 3]);
+        assert_eq!(windows, [false; 3]);
+    }
+}
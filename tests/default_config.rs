@@ -0,0 +1,120 @@
+//! Runs the daemon with a fake `XDG_CONFIG_HOME` and `XDG_DATA_HOME` and
+//! checks where it creates or imports its default config.
+#![cfg(target_os = "linux")]
+
+use std::{
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// A temporary home directory that no other test uses.
+struct Home(PathBuf);
+
+impl Home {
+    fn new(name: &str) -> Self {
+        let home = std::env::temp_dir().join(format!(
+            "duckypad_daemon-test-{}-{name}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&home).unwrap();
+
+        let script = home.join("window.sh");
+        std::fs::write(
+            &script,
+            "#!/bin/sh\necho '{\"title\": \"Inbox\", \"process_name\": \"thunderbird\"}'\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        Self(home)
+    }
+
+    fn path(&self, path: &str) -> PathBuf {
+        self.0.join(path)
+    }
+
+    /// Checks the active window once without a duckyPad and returns the exit
+    /// status.
+    fn run_once(&self) -> Option<i32> {
+        Command::new(env!("CARGO_BIN_EXE_duckypad_daemon"))
+            .args(["--once", "--dry-run", "--no-device", "--window-script"])
+            .arg(self.path("window.sh"))
+            .env_clear()
+            .env("PATH", std::env::var_os("PATH").unwrap_or_default())
+            .env("HOME", &self.0)
+            .env("XDG_CONFIG_HOME", self.path("config"))
+            .env("XDG_DATA_HOME", self.path("data"))
+            .env("XDG_RUNTIME_DIR", &self.0)
+            .env("XDG_SESSION_TYPE", "wayland")
+            .output()
+            .unwrap()
+            .status
+            .code()
+    }
+}
+
+impl Drop for Home {
+    fn drop(&mut self) {
+        let _: Result<_, _> = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+fn read(path: &Path) -> serde_json::Value {
+    serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap()
+}
+
+#[test]
+fn creates_the_default_config_in_xdg_config_home() {
+    let home = Home::new("xdg-created");
+
+    // no rule matches
+    assert_eq!(home.run_once(), Some(2));
+    let config = read(&home.path("config/duckypad_daemon/config.json"));
+    assert_eq!(config["autoswitch_enabled"], true);
+    assert_eq!(config["rules_list"], serde_json::json!([]));
+}
+
+#[test]
+fn imports_the_config_of_the_autoswitcher() {
+    let home = Home::new("xdg-imported");
+    let autoswitcher = home.path("data/duckypad_autoswitcher/config.txt");
+    std::fs::create_dir_all(autoswitcher.parent().unwrap()).unwrap();
+    std::fs::write(
+        &autoswitcher,
+        r#"{"autoswitch_enabled": true, "rules_list": [
+            {"enabled": true, "app_name": "thunderbird", "title": "", "switch_to": 3}
+        ]}"#,
+    )
+    .unwrap();
+
+    // the imported rule matches
+    assert_eq!(home.run_once(), Some(0));
+    assert_eq!(
+        read(&home.path("config/duckypad_daemon/config.json")),
+        read(&autoswitcher)
+    );
+}
+
+#[test]
+fn keeps_an_existing_config() {
+    let home = Home::new("xdg-existing");
+    let config = home.path("config/duckypad_daemon/config.json");
+    std::fs::create_dir_all(config.parent().unwrap()).unwrap();
+    std::fs::write(
+        &config,
+        r#"{"autoswitch_enabled": false, "rules_list": []}"#,
+    )
+    .unwrap();
+    let autoswitcher = home.path("data/duckypad_autoswitcher/config.txt");
+    std::fs::create_dir_all(autoswitcher.parent().unwrap()).unwrap();
+    std::fs::write(
+        &autoswitcher,
+        r#"{"autoswitch_enabled": true, "rules_list": []}"#,
+    )
+    .unwrap();
+
+    // autoswitching is disabled on purpose
+    assert_eq!(home.run_once(), Some(0));
+    assert_eq!(read(&config)["autoswitch_enabled"], false);
+}
@@ -0,0 +1,61 @@
+//! Runs the daemon with `--stdin` and checks that it handles the windows
+//! piped into it and exits once stdin is closed.
+#![cfg(unix)]
+
+use std::{
+    io::Write,
+    process::{Command, Stdio},
+    time::{Duration, Instant},
+};
+
+/// How long the daemon may take to exit after stdin was closed.
+const EXIT_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[test]
+fn handles_the_last_line_before_exiting() {
+    let dir =
+        std::env::temp_dir().join(format!("duckypad_daemon-test-{}-stdin", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let config = dir.join("config.json");
+    std::fs::write(
+        &config,
+        r#"{"autoswitch_enabled": true, "rules_list": [
+            {"enabled": true, "app_name": "firefox", "switch_to": 3}
+        ]}"#,
+    )
+    .unwrap();
+
+    let mut daemon = Command::new(env!("CARGO_BIN_EXE_duckypad_daemon"))
+        .args(["--dry-run", "--no-device", "--stdin", "--config"])
+        .arg(&config)
+        .env("XDG_RUNTIME_DIR", &dir)
+        .env_remove("RUST_LOG")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    // dropping stdin closes it right after the only line
+    daemon
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"{\"title\": \"t\", \"process_name\": \"firefox\"}\n")
+        .unwrap();
+
+    let deadline = Instant::now() + EXIT_TIMEOUT;
+    while daemon.try_wait().unwrap().is_none() {
+        if Instant::now() > deadline {
+            daemon.kill().unwrap();
+            panic!("the daemon didn't exit after stdin was closed");
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    let output = daemon.wait_with_output().unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{stderr}");
+    assert!(stderr.contains("Would switch to profile 3"), "{stderr}");
+}
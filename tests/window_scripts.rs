@@ -0,0 +1,158 @@
+//! Runs the bundled window scripts with fake versions of the tools they
+//! call, which print recorded output (in `tests/data`), and checks that the
+//! daemon reads the active window from it.
+#![cfg(unix)]
+
+use duckypad_daemon::{parse_window, WindowInfo};
+use std::{os::unix::fs::PermissionsExt, path::Path, process::Command};
+
+/// Runs the bundled window script `script` with the fake `tools` (name and
+/// shell script body) first in the `PATH` and parses its output. The tools
+/// get the directory of the recordings as `DATA`, a directory to share state
+/// in as `STATE` and the environment variables `env`. `name` has to be unique
+/// among the tests. Returns `None` if `jq` isn't installed.
+fn run_script(
+    script: &str,
+    name: &str,
+    tools: &[(&str, &str)],
+    env: &[(&str, &str)],
+) -> Option<WindowInfo> {
+    if Command::new("jq").arg("--version").output().is_err() {
+        eprintln!("jq isn't installed, skipping");
+        return None;
+    }
+
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let bin = std::env::temp_dir().join(format!(
+        "duckypad_daemon-test-{}-{name}",
+        std::process::id()
+    ));
+    std::fs::create_dir_all(&bin).unwrap();
+    for (tool, body) in tools {
+        let path = bin.join(tool);
+        std::fs::write(&path, format!("#!/bin/sh\n{body}\n")).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    let path = std::env::join_paths(std::iter::once(bin.clone()).chain(std::env::split_paths(
+        &std::env::var_os("PATH").unwrap_or_default(),
+    )))
+    .unwrap();
+    let output = Command::new("sh")
+        .arg(root.join("examples/window-scripts").join(script))
+        .env("PATH", path)
+        .env("DATA", root.join("tests/data"))
+        .env("STATE", &bin)
+        .envs(env.iter().copied())
+        .output()
+        .unwrap();
+    std::fs::remove_dir_all(&bin).unwrap();
+
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    Some(parse_window(&String::from_utf8(output.stdout).unwrap()).unwrap())
+}
+
+/// Runs `sway.sh` with a `swaymsg` that prints the recorded tree `tree`.
+fn focused_window(tree: &str) -> Option<WindowInfo> {
+    run_script(
+        "sway.sh",
+        tree,
+        &[("swaymsg", "cat \"$DATA/$SWAY_TREE\"")],
+        &[("SWAY_TREE", tree)],
+    )
+}
+
+/// Runs `kde.sh` with a `busctl` that loads the KWin script and a
+/// `journalctl` that logs the recorded output `output` of the KWin script,
+/// behind the marker of the `print` call in the KWin script.
+fn active_kwin_window(output: &str) -> Option<WindowInfo> {
+    // busctl --user call org.kde.KWin /Scripting org.kde.kwin.Scripting loadScript ss <file> <name>
+    let busctl = r#"if [ "$6" = loadScript ]; then
+    sed -n 's/^print("\([^"]*\)".*/\1/p' "$8" > "$STATE/marker"
+    echo "i 3"
+fi"#;
+    let journalctl =
+        r#"printf 'js: %s%s\n' "$(cat "$STATE/marker")" "$(cat "$DATA/$KWIN_OUTPUT")""#;
+
+    run_script(
+        "kde.sh",
+        output,
+        &[("busctl", busctl), ("journalctl", journalctl)],
+        &[("KWIN_OUTPUT", output)],
+    )
+}
+
+#[test]
+fn reads_the_focused_wayland_window() {
+    let Some(window) = focused_window("sway-tree.json") else {
+        return;
+    };
+
+    assert_eq!(window.title, "duckyPad - Mozilla Firefox");
+    assert_eq!(window.process_name, "firefox");
+    assert_eq!(window.process_id, 1984);
+    assert_eq!(window.window_id, "8");
+    assert_eq!(window.fullscreen, Some(true));
+    assert!(window.wm_instance.is_empty());
+}
+
+#[test]
+fn reads_the_class_of_xwayland_windows() {
+    let Some(window) = focused_window("sway-tree-xwayland.json") else {
+        return;
+    };
+
+    assert_eq!(window.title, "Inbox - Thunderbird");
+    assert_eq!(window.process_name, "thunderbird");
+    assert_eq!(window.process_id, 2210);
+    assert_eq!(window.wm_instance, "Mail");
+    assert_eq!(window.wm_role, "3pane");
+    assert_eq!(window.fullscreen, Some(false));
+}
+
+#[test]
+fn reports_an_empty_window_without_focus() {
+    let Some(window) = focused_window("sway-tree-empty.json") else {
+        return;
+    };
+
+    assert!(window.title.is_empty());
+    assert!(window.process_name.is_empty());
+    assert_eq!(window.process_id, 0);
+}
+
+#[test]
+fn reads_the_active_kwin_window() {
+    let Some(window) = active_kwin_window("kwin-window.json") else {
+        return;
+    };
+
+    assert_eq!(window.title, "main.rs — duckypad_daemon — Kate");
+    assert_eq!(window.process_name, "org.kde.kate");
+    assert_eq!(window.process_id, 3141);
+    assert_eq!(window.window_id, "{5e0d4f2a-8c1b-4d7e-9a4f-2b6c7d8e9f01}");
+    assert_eq!(window.wm_instance, "kate");
+    assert_eq!(window.wm_role, "MainWindow#1");
+    assert_eq!(window.fullscreen, Some(false));
+    let position = window.position.unwrap();
+    assert_eq!(
+        (position.x, position.y, position.width, position.height),
+        (0.0, 28.0, 1920.0, 1052.0)
+    );
+}
+
+#[test]
+fn reports_an_empty_kwin_window_without_focus() {
+    let Some(window) = active_kwin_window("kwin-empty.json") else {
+        return;
+    };
+
+    assert!(window.title.is_empty());
+    assert!(window.process_name.is_empty());
+    assert!(window.window_id.is_empty());
+    assert_eq!(window.process_id, 0);
+}